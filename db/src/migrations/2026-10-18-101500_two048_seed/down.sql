@@ -0,0 +1,5 @@
+ALTER TABLE two048_move_events DROP COLUMN IF EXISTS spawn_value;
+ALTER TABLE two048_move_events DROP COLUMN IF EXISTS spawn_col;
+ALTER TABLE two048_move_events DROP COLUMN IF EXISTS spawn_row;
+
+ALTER TABLE game_sessions DROP COLUMN IF EXISTS seed;
@@ -0,0 +1,5 @@
+ALTER TABLE game_sessions ADD COLUMN seed BIGINT;
+
+ALTER TABLE two048_move_events ADD COLUMN spawn_row INTEGER;
+ALTER TABLE two048_move_events ADD COLUMN spawn_col INTEGER;
+ALTER TABLE two048_move_events ADD COLUMN spawn_value INTEGER;
@@ -0,0 +1,7 @@
+ALTER TABLE tetris_snapshots DROP COLUMN IF EXISTS drop_kind;
+ALTER TABLE tetris_snapshots DROP COLUMN IF EXISTS column_index;
+ALTER TABLE tetris_snapshots DROP COLUMN IF EXISTS rotation;
+ALTER TABLE tetris_snapshots DROP COLUMN IF EXISTS piece;
+
+DROP TYPE IF EXISTS drop_kind;
+DROP TYPE IF EXISTS tetris_piece;
@@ -0,0 +1,7 @@
+CREATE TYPE tetris_piece AS ENUM ('i', 'o', 't', 's', 'z', 'j', 'l');
+CREATE TYPE drop_kind AS ENUM ('soft', 'hard');
+
+ALTER TABLE tetris_snapshots ADD COLUMN piece tetris_piece;
+ALTER TABLE tetris_snapshots ADD COLUMN rotation INTEGER;
+ALTER TABLE tetris_snapshots ADD COLUMN column_index INTEGER;
+ALTER TABLE tetris_snapshots ADD COLUMN drop_kind drop_kind;
@@ -0,0 +1,7 @@
+DROP INDEX IF EXISTS idx_task_completions_completed_at;
+DROP INDEX IF EXISTS idx_game_sessions_end_time;
+
+DROP TABLE IF EXISTS leaderboard_archives;
+DROP TABLE IF EXISTS seasons;
+
+DROP TYPE IF EXISTS leaderboard_window;
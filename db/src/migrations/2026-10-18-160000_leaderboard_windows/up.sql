@@ -0,0 +1,26 @@
+CREATE TYPE leaderboard_window AS ENUM ('daily', 'weekly', 'season');
+
+CREATE TABLE seasons (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    starts_at TIMESTAMPTZ NOT NULL,
+    ends_at TIMESTAMPTZ NOT NULL,
+    archived BOOLEAN NOT NULL DEFAULT FALSE,
+    CHECK (ends_at > starts_at)
+);
+
+CREATE TABLE leaderboard_archives (
+    id SERIAL PRIMARY KEY,
+    window_type leaderboard_window NOT NULL,
+    period TEXT NOT NULL,
+    user_id TEXT NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    rank INTEGER NOT NULL,
+    score INTEGER NOT NULL,
+    archived_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (window_type, period, user_id)
+);
+
+CREATE INDEX idx_seasons_ends_at ON seasons (ends_at);
+CREATE INDEX idx_leaderboard_archives_period ON leaderboard_archives (window_type, period);
+CREATE INDEX idx_game_sessions_end_time ON game_sessions (end_time);
+CREATE INDEX idx_task_completions_completed_at ON task_completions (completed_at);
@@ -0,0 +1 @@
+CREATE INDEX idx_users_points_user_id ON users (points DESC, user_id);
@@ -0,0 +1,6 @@
+DROP TRIGGER IF EXISTS point_transactions_no_update ON point_transactions;
+DROP FUNCTION IF EXISTS point_transactions_append_only();
+
+DROP TABLE IF EXISTS point_transactions;
+
+DROP TYPE IF EXISTS point_source;
@@ -0,0 +1,30 @@
+CREATE TYPE point_source AS ENUM ('opening', 'game_session', 'task', 'referral', 'admin');
+
+CREATE TABLE point_transactions (
+    id BIGSERIAL PRIMARY KEY,
+    user_id TEXT NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    source point_source NOT NULL,
+    reference TEXT,
+    amount INTEGER NOT NULL,
+    balance INTEGER NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_point_transactions_user_id ON point_transactions (user_id, id);
+
+-- Rows are never changed once written
+CREATE FUNCTION point_transactions_append_only() RETURNS TRIGGER AS $$
+BEGIN
+    RAISE EXCEPTION 'point_transactions is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER point_transactions_no_update
+    BEFORE UPDATE ON point_transactions
+    FOR EACH ROW EXECUTE FUNCTION point_transactions_append_only();
+
+-- Points earned before the ledger existed
+INSERT INTO point_transactions (user_id, source, amount, balance)
+SELECT user_id, 'opening', points, points
+FROM users
+WHERE points <> 0;
@@ -0,0 +1,3 @@
+CREATE TYPE user_status AS ENUM ('active', 'banned', 'shadow_banned');
+
+ALTER TABLE users ADD COLUMN status user_status NOT NULL DEFAULT 'active';
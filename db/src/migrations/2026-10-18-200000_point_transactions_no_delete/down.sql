@@ -0,0 +1,3 @@
+DROP TRIGGER IF EXISTS point_transactions_no_truncate ON point_transactions;
+DROP TRIGGER IF EXISTS point_transactions_no_delete ON point_transactions;
+DROP FUNCTION IF EXISTS point_transactions_delete_with_user();
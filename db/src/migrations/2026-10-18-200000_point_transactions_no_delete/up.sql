@@ -0,0 +1,21 @@
+-- Ledger rows are kept for as long as their user exists. They can only be deleted by the
+-- cascade of deleting the user, e.g. to erase an account.
+
+-- The cascade runs after the user row is gone, a direct delete still finds it
+CREATE FUNCTION point_transactions_delete_with_user() RETURNS TRIGGER AS $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM users WHERE user_id = OLD.user_id) THEN
+        RAISE EXCEPTION 'point_transactions rows are only deleted with their user';
+    END IF;
+
+    RETURN OLD;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER point_transactions_no_delete
+    BEFORE DELETE ON point_transactions
+    FOR EACH ROW EXECUTE FUNCTION point_transactions_delete_with_user();
+
+CREATE TRIGGER point_transactions_no_truncate
+    BEFORE TRUNCATE ON point_transactions
+    FOR EACH STATEMENT EXECUTE FUNCTION point_transactions_append_only();
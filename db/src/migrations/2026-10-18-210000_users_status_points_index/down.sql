@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS idx_users_status_points_user_id;
+
+CREATE INDEX idx_users_points_user_id ON users (points DESC, user_id);
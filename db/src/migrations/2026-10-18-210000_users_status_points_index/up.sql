@@ -0,0 +1,5 @@
+-- Leaderboard queries only rank active users, diesel binds the status as a parameter so it
+-- leads the index instead of being a partial index predicate
+DROP INDEX IF EXISTS idx_users_points_user_id;
+
+CREATE INDEX idx_users_status_points_user_id ON users (status, points DESC, user_id);
@@ -2,10 +2,12 @@ use chrono::{DateTime, Utc};
 use diesel::prelude::*;
 use diesel::result::Error;
 use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use shared::protocol::FlappyData;
 
 use crate::schema::flappy_score_events;
 
-#[derive(Default, Debug, Clone, Insertable, Queryable, Selectable)]
+#[derive(Default, Debug, Clone, Insertable, Queryable, Selectable, Serialize, Deserialize)]
 pub struct FlappyScoreEvent {
     session_id: String,
     user_id: String,
@@ -42,6 +44,20 @@ impl FlappyScoreEvent {
         }
     }
 
+    #[must_use]
+    pub fn from_data(session_id: String, user_id: String, data: &FlappyData) -> Self {
+        Self::new(
+            session_id,
+            user_id,
+            data.timestamp,
+            data.prev_timestamp,
+            data.points,
+            data.prev_points,
+            data.pipes,
+            data.prev_pipes,
+        )
+    }
+
     pub async fn insert_batch(
         conn: &mut AsyncPgConnection,
         events: Vec<Self>,
@@ -53,4 +69,33 @@ impl FlappyScoreEvent {
             .execute(conn)
             .await
     }
+
+    pub async fn get_by_session_id(
+        s_id: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Vec<Self>, Error> {
+        use crate::schema::flappy_score_events::dsl::{
+            flappy_score_events, id, session_id, timestamp,
+        };
+
+        flappy_score_events
+            .filter(session_id.eq(s_id))
+            .order_by((timestamp.asc(), id.asc()))
+            .select(Self::as_select())
+            .get_results(conn)
+            .await
+    }
+}
+
+impl From<&FlappyScoreEvent> for FlappyData {
+    fn from(event: &FlappyScoreEvent) -> Self {
+        Self {
+            timestamp: event.timestamp,
+            prev_timestamp: event.prev_timestamp,
+            points: event.points,
+            prev_points: event.prev_points,
+            pipes: event.pipes,
+            prev_pipes: event.prev_pipes,
+        }
+    }
 }
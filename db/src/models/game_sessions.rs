@@ -2,37 +2,15 @@ use chrono::{DateTime, Utc};
 use diesel::prelude::*;
 use diesel::result::Error;
 use diesel_async::{AsyncPgConnection, RunQueryDsl};
-use diesel_derive_enum::DbEnum;
-use serde::Serialize;
-use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+pub use shared::protocol::GameType;
+use shared::protocol::PartialGameSession;
 use ulid::Ulid;
 
+use crate::models::UserStatus;
 use crate::schema::game_sessions;
 
-#[derive(DbEnum, Debug, Clone, Copy, Serialize)]
-#[db_enum(existing_type_path = "crate::schema::sql_types::GameType")]
-pub enum GameType {
-    Snake,
-    Tetris,
-    Flappy,
-    Two048,
-}
-
-impl FromStr for GameType {
-    type Err = ();
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.to_lowercase().as_str() {
-            "tetris" => Ok(GameType::Tetris),
-            "snake" => Ok(GameType::Snake),
-            "2048" | "two048" => Ok(GameType::Two048),
-            "flappy" => Ok(GameType::Flappy),
-            _ => Err(()),
-        }
-    }
-}
-
-#[derive(Debug, Clone, Insertable, Queryable, Selectable)]
+#[derive(Debug, Clone, Insertable, Queryable, Selectable, Serialize, Deserialize)]
 pub struct GameSession {
     pub id: String,
     pub user_id: String,
@@ -40,11 +18,17 @@ pub struct GameSession {
     pub start_time: DateTime<Utc>,
     pub end_time: DateTime<Utc>,
     pub final_score: i32,
+    pub seed: Option<i64>,
 }
 
 impl GameSession {
     #[must_use]
-    pub fn new(user_id: String, game: GameType, start_time: DateTime<Utc>) -> Self {
+    pub fn new(
+        user_id: String,
+        game: GameType,
+        start_time: DateTime<Utc>,
+        seed: Option<i64>,
+    ) -> Self {
         let id = Ulid::new().to_string();
         Self {
             id,
@@ -53,6 +37,7 @@ impl GameSession {
             start_time,
             end_time: start_time,
             final_score: 0,
+            seed,
         }
     }
 
@@ -66,6 +51,85 @@ impl GameSession {
             .await
     }
 
+    pub async fn get_by_id(
+        session_id: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Option<Self>, Error> {
+        use crate::schema::game_sessions::dsl::{game_sessions, id};
+
+        game_sessions
+            .filter(id.eq(session_id))
+            .select(Self::as_select())
+            .first(conn)
+            .await
+            .optional()
+    }
+
+    /// Pages through every stored session ordered by id, optionally for a single game
+    pub async fn get_batch(
+        after_id: Option<&str>,
+        game_type: Option<GameType>,
+        limit: i64,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Vec<Self>, Error> {
+        use crate::schema::game_sessions::dsl::{game, game_sessions, id};
+
+        let mut query = game_sessions.into_boxed();
+
+        if let Some(after_id) = after_id {
+            query = query.filter(id.gt(after_id.to_string()));
+        }
+
+        if let Some(game_type) = game_type {
+            query = query.filter(game.eq(game_type));
+        }
+
+        query
+            .order_by(id.asc())
+            .limit(limit)
+            .select(Self::as_select())
+            .get_results(conn)
+            .await
+    }
+
+    /// Best final score of every active user for a game, highest first
+    pub async fn get_best_scores(
+        game_type: GameType,
+        limit: i64,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Vec<(String, Option<i32>)>, Error> {
+        use crate::schema::game_sessions::dsl::{final_score, game, game_sessions, user_id};
+        use crate::schema::users::dsl::{status, users};
+        use diesel::dsl::max;
+
+        game_sessions
+            .inner_join(users)
+            .filter(game.eq(game_type))
+            .filter(status.eq(UserStatus::Active))
+            .group_by(user_id)
+            .select((user_id, max(final_score)))
+            .order_by(max(final_score).desc())
+            .limit(limit)
+            .load(conn)
+            .await
+    }
+
+    /// Best final score of a user in every game they played
+    pub async fn get_user_best_scores(
+        u_id: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Vec<(GameType, Option<i32>)>, Error> {
+        use crate::schema::game_sessions::dsl::{final_score, game, game_sessions, user_id};
+        use diesel::dsl::max;
+
+        game_sessions
+            .filter(user_id.eq(u_id))
+            .group_by(game)
+            .select((game, max(final_score)))
+            .load(conn)
+            .await
+    }
+
     pub async fn get_by_user_id(
         id: &str,
         conn: &mut AsyncPgConnection,
@@ -80,3 +144,14 @@ impl GameSession {
             .await
     }
 }
+
+impl From<GameSession> for PartialGameSession {
+    fn from(entry: GameSession) -> Self {
+        PartialGameSession {
+            game_type: entry.game,
+            start_time: entry.start_time,
+            end_time: entry.end_time,
+            final_score: entry.final_score,
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use diesel::result::Error;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+pub use shared::protocol::LeaderboardWindow;
+
+use crate::schema::leaderboard_archives;
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = leaderboard_archives)]
+pub struct NewLeaderboardArchive {
+    window_type: LeaderboardWindow,
+    period: String,
+    user_id: String,
+    rank: i32,
+    score: i32,
+}
+
+#[derive(Debug, Clone, Queryable, Selectable, Serialize)]
+pub struct LeaderboardArchive {
+    pub id: i32,
+    pub window_type: LeaderboardWindow,
+    pub period: String,
+    pub user_id: String,
+    pub rank: i32,
+    pub score: i32,
+    pub archived_at: DateTime<Utc>,
+}
+
+impl NewLeaderboardArchive {
+    #[must_use]
+    pub fn new(
+        window_type: LeaderboardWindow,
+        period: String,
+        user_id: String,
+        rank: i32,
+        score: i32,
+    ) -> Self {
+        Self {
+            window_type,
+            period,
+            user_id,
+            rank,
+            score,
+        }
+    }
+
+    /// Inserts the final standings of a window. Rows that were already archived are kept as is.
+    pub async fn insert_batch(rows: &[Self], conn: &mut AsyncPgConnection) -> Result<usize, Error> {
+        use crate::schema::leaderboard_archives::dsl::leaderboard_archives;
+
+        diesel::insert_into(leaderboard_archives)
+            .values(rows)
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .await
+    }
+}
+
+impl LeaderboardArchive {
+    /// Newest archived period of a window. Daily and weekly periods sort chronologically as
+    /// text.
+    pub async fn latest_period(
+        window: LeaderboardWindow,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Option<String>, Error> {
+        use crate::schema::leaderboard_archives::dsl::{leaderboard_archives, period, window_type};
+        use diesel::dsl::max;
+
+        leaderboard_archives
+            .filter(window_type.eq(window))
+            .select(max(period))
+            .get_result(conn)
+            .await
+    }
+
+    pub async fn get_by_period(
+        window: LeaderboardWindow,
+        window_period: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Vec<Self>, Error> {
+        use crate::schema::leaderboard_archives::dsl::{
+            leaderboard_archives, period, rank, window_type,
+        };
+
+        leaderboard_archives
+            .filter(window_type.eq(window))
+            .filter(period.eq(window_period))
+            .order_by(rank.asc())
+            .select(Self::as_select())
+            .get_results(conn)
+            .await
+    }
+}
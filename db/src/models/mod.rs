@@ -1,8 +1,11 @@
 mod flappy_score_events;
 mod game_sessions;
+mod leaderboard_archives;
+mod point_transactions;
 mod raw_sqls;
 mod referral_rewards;
 mod referrals;
+mod seasons;
 mod snake_food_events;
 mod task_completion;
 mod tasks;
@@ -13,9 +16,12 @@ mod users;
 
 pub use flappy_score_events::*;
 pub use game_sessions::*;
+pub use leaderboard_archives::*;
+pub use point_transactions::*;
 pub use raw_sqls::*;
 pub use referral_rewards::*;
 pub use referrals::*;
+pub use seasons::*;
 pub use snake_food_events::*;
 pub use task_completion::*;
 pub use tasks::*;
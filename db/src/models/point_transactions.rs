@@ -0,0 +1,78 @@
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use diesel::result::Error;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+pub use shared::protocol::PointSource;
+
+use crate::schema::point_transactions;
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = point_transactions)]
+pub struct NewPointTransaction {
+    user_id: String,
+    source: PointSource,
+    reference: Option<String>,
+    amount: i32,
+    balance: i32,
+}
+
+/// One change of a user's points. `reference` is the id of the session, task or user that
+/// caused it and `balance` the points right after it.
+#[derive(Debug, Clone, Queryable, Selectable, Serialize)]
+pub struct PointTransaction {
+    pub id: i64,
+    pub user_id: String,
+    pub source: PointSource,
+    pub reference: Option<String>,
+    pub amount: i32,
+    pub balance: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+impl NewPointTransaction {
+    #[must_use]
+    pub fn new(
+        user_id: String,
+        source: PointSource,
+        reference: Option<String>,
+        amount: i32,
+        balance: i32,
+    ) -> Self {
+        Self {
+            user_id,
+            source,
+            reference,
+            amount,
+            balance,
+        }
+    }
+
+    pub async fn insert(&self, conn: &mut AsyncPgConnection) -> Result<usize, Error> {
+        use crate::schema::point_transactions::dsl::point_transactions;
+
+        diesel::insert_into(point_transactions)
+            .values(self)
+            .execute(conn)
+            .await
+    }
+}
+
+impl PointTransaction {
+    /// The latest point changes of a user, newest first
+    pub async fn get_by_user(
+        conn: &mut AsyncPgConnection,
+        u_id: &str,
+        limit: i64,
+    ) -> Result<Vec<Self>, Error> {
+        use crate::schema::point_transactions::dsl::{id, point_transactions, user_id};
+
+        point_transactions
+            .filter(user_id.eq(u_id))
+            .order(id.desc())
+            .limit(limit)
+            .select(Self::as_select())
+            .load(conn)
+            .await
+    }
+}
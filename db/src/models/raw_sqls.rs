@@ -1,7 +1,7 @@
+use chrono::{DateTime, Utc};
 use diesel::deserialize::QueryableByName;
-use diesel::prelude::*;
 use diesel::result::Error;
-use diesel::sql_types::{BigInt, Text};
+use diesel::sql_types::{BigInt, Integer, Text, Timestamptz};
 use diesel_async::{AsyncPgConnection, RunQueryDsl};
 
 #[derive(QueryableByName)]
@@ -10,23 +10,137 @@ struct UserRank {
     rank: i64,
 }
 
+#[derive(QueryableByName)]
+struct LedgerMismatch {
+    #[diesel(sql_type = Text)]
+    user_id: String,
+    #[diesel(sql_type = Integer)]
+    points: i32,
+    #[diesel(sql_type = Integer)]
+    ledger_points: i32,
+}
+
+#[derive(QueryableByName)]
+struct WindowScore {
+    #[diesel(sql_type = Text)]
+    user_id: String,
+    #[diesel(sql_type = Integer)]
+    score: i32,
+}
+
+/// Position of a user on the global leaderboard for the given points. Ties are ordered by
+/// `user_id`, same as the leaderboard pages. Served from
+/// `idx_users_status_points_user_id`, which leads with the status so only active users are read.
 pub async fn get_user_rank(
     conn: &mut AsyncPgConnection,
     target_user_id: &str,
-) -> Result<Option<i64>, Error> {
+    target_points: i32,
+) -> Result<i64, Error> {
     let sql = r"
-        WITH ranked_users AS (
-            SELECT user_id, ROW_NUMBER() OVER (ORDER BY points DESC) AS rank
-            FROM users
-        )
-        SELECT rank FROM ranked_users WHERE user_id = $1
+        SELECT COUNT(*) + 1 AS rank
+        FROM users
+        WHERE (points > $2 OR (points = $2 AND user_id < $1))
+            AND status = 'active'
     ";
 
-    let result: Option<UserRank> = diesel::sql_query(sql)
+    let result: UserRank = diesel::sql_query(sql)
         .bind::<Text, _>(target_user_id)
+        .bind::<Integer, _>(target_points)
         .get_result(conn)
-        .await
-        .optional()?;
+        .await?;
+
+    Ok(result.rank)
+}
+
+/// Points active users earned between `start` and `end` from finished game sessions and
+/// rewarded tasks, highest first
+pub async fn get_window_standings(
+    conn: &mut AsyncPgConnection,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    limit: i64,
+) -> Result<Vec<(String, i32)>, Error> {
+    let sql = r"
+        WITH earned AS (
+            SELECT user_id, final_score AS points
+            FROM game_sessions
+            WHERE end_time >= $1 AND end_time < $2
+            UNION ALL
+            SELECT tc.user_id, t.reward_point AS points
+            FROM task_completions tc
+            JOIN tasks t ON t.id = tc.task_id
+            WHERE tc.points_assigned AND tc.completed_at >= $1 AND tc.completed_at < $2
+        )
+        SELECT e.user_id, SUM(e.points)::INTEGER AS score
+        FROM earned e
+        JOIN users u ON u.user_id = e.user_id
+        WHERE u.status = 'active'
+        GROUP BY e.user_id
+        HAVING SUM(e.points) > 0
+        ORDER BY score DESC, e.user_id
+        LIMIT $3
+    ";
+
+    let result: Vec<WindowScore> = diesel::sql_query(sql)
+        .bind::<Timestamptz, _>(start)
+        .bind::<Timestamptz, _>(end)
+        .bind::<BigInt, _>(limit)
+        .load(conn)
+        .await?;
+
+    Ok(result.into_iter().map(|r| (r.user_id, r.score)).collect())
+}
+
+/// Users whose `points` differ from the sum of their ledger, as `(user_id, points,
+/// ledger_points)`. Points that are still dirty in redis show up here until they are flushed,
+/// points of running game sessions until the session is committed.
+pub async fn get_ledger_mismatches(
+    conn: &mut AsyncPgConnection,
+) -> Result<Vec<(String, i32, i32)>, Error> {
+    let sql = r"
+        SELECT u.user_id, u.points, COALESCE(l.total, 0)::INTEGER AS ledger_points
+        FROM users u
+        LEFT JOIN (
+            SELECT user_id, SUM(amount) AS total
+            FROM point_transactions
+            GROUP BY user_id
+        ) l ON l.user_id = u.user_id
+        WHERE u.points <> COALESCE(l.total, 0)
+        ORDER BY u.user_id
+    ";
+
+    let result: Vec<LedgerMismatch> = diesel::sql_query(sql).load(conn).await?;
+
+    Ok(result
+        .into_iter()
+        .map(|r| (r.user_id, r.points, r.ledger_points))
+        .collect())
+}
+
+/// Sets `points` of every user to the sum of their ledger and returns the users that changed
+/// with their old points and ledger points. Dirty points must be flushed and running game
+/// sessions committed before running this.
+pub async fn rebuild_points_from_ledger(
+    conn: &mut AsyncPgConnection,
+) -> Result<Vec<(String, i32, i32)>, Error> {
+    let sql = r"
+        WITH ledger AS (
+            SELECT u.user_id, u.points, COALESCE(SUM(pt.amount), 0)::INTEGER AS ledger_points
+            FROM users u
+            LEFT JOIN point_transactions pt ON pt.user_id = u.user_id
+            GROUP BY u.user_id, u.points
+        )
+        UPDATE users
+        SET points = ledger.ledger_points
+        FROM ledger
+        WHERE users.user_id = ledger.user_id AND ledger.points <> ledger.ledger_points
+        RETURNING users.user_id, ledger.points, ledger.ledger_points
+    ";
+
+    let result: Vec<LedgerMismatch> = diesel::sql_query(sql).load(conn).await?;
 
-    Ok(result.map(|r| r.rank))
+    Ok(result
+        .into_iter()
+        .map(|r| (r.user_id, r.points, r.ledger_points))
+        .collect())
 }
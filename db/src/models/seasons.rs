@@ -0,0 +1,91 @@
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use diesel::result::Error;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+
+use crate::schema::seasons;
+
+#[derive(Debug, Clone, Insertable, Queryable, Selectable, Serialize, Deserialize)]
+pub struct Season {
+    pub id: String,
+    pub name: String,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    pub archived: bool,
+}
+
+impl Season {
+    #[must_use]
+    pub fn new(name: String, starts_at: DateTime<Utc>, ends_at: DateTime<Utc>) -> Self {
+        Self {
+            id: Ulid::new().to_string(),
+            name,
+            starts_at,
+            ends_at,
+            archived: false,
+        }
+    }
+
+    pub async fn insert(&self, conn: &mut AsyncPgConnection) -> Result<usize, Error> {
+        use crate::schema::seasons::dsl::seasons;
+
+        diesel::insert_into(seasons)
+            .values(self)
+            .execute(conn)
+            .await
+    }
+
+    pub async fn get_all(conn: &mut AsyncPgConnection) -> Result<Vec<Self>, Error> {
+        use crate::schema::seasons::dsl::{seasons, starts_at};
+
+        seasons
+            .order_by(starts_at.desc())
+            .select(Self::as_select())
+            .get_results(conn)
+            .await
+    }
+
+    /// The season running at `now`. If seasons overlap, the one that ends first wins
+    pub async fn get_active(
+        now: DateTime<Utc>,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Option<Self>, Error> {
+        use crate::schema::seasons::dsl::{ends_at, seasons, starts_at};
+
+        seasons
+            .filter(starts_at.le(now))
+            .filter(ends_at.gt(now))
+            .order_by(ends_at.asc())
+            .select(Self::as_select())
+            .first(conn)
+            .await
+            .optional()
+    }
+
+    /// Seasons that have ended but whose standings were not archived yet
+    pub async fn get_ended_unarchived(
+        now: DateTime<Utc>,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Vec<Self>, Error> {
+        use crate::schema::seasons::dsl::{archived, ends_at, seasons};
+
+        seasons
+            .filter(ends_at.le(now))
+            .filter(archived.eq(false))
+            .order_by(ends_at.asc())
+            .select(Self::as_select())
+            .get_results(conn)
+            .await
+    }
+
+    pub async fn mark_archived(&self, conn: &mut AsyncPgConnection) -> Result<usize, Error> {
+        use crate::schema::seasons::dsl::{archived, id, seasons};
+
+        diesel::update(seasons.filter(id.eq(&self.id)))
+            .set(archived.eq(true))
+            .execute(conn)
+            .await
+    }
+}
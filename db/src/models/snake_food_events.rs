@@ -2,10 +2,12 @@ use chrono::{DateTime, Utc};
 use diesel::prelude::*;
 use diesel::result::Error;
 use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use shared::protocol::SnakeData;
 
 use crate::schema::snake_food_events;
 
-#[derive(Default, Debug, Clone, Insertable, Queryable, Selectable)]
+#[derive(Default, Debug, Clone, Insertable, Queryable, Selectable, Serialize, Deserialize)]
 pub struct SnakeFoodEvent {
     session_id: String,
     user_id: String,
@@ -48,6 +50,22 @@ impl SnakeFoodEvent {
         }
     }
 
+    #[must_use]
+    pub fn from_data(session_id: String, user_id: String, data: &SnakeData) -> Self {
+        Self::new(
+            session_id,
+            user_id,
+            data.timestamp,
+            data.prev_timestamp,
+            data.points,
+            data.prev_points,
+            data.length,
+            data.prev_length,
+            data.level,
+            data.prev_level,
+        )
+    }
+
     pub async fn insert_batch(
         conn: &mut AsyncPgConnection,
         events: Vec<Self>,
@@ -59,4 +77,33 @@ impl SnakeFoodEvent {
             .execute(conn)
             .await
     }
+
+    pub async fn get_by_session_id(
+        s_id: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Vec<Self>, Error> {
+        use crate::schema::snake_food_events::dsl::{id, session_id, snake_food_events, timestamp};
+
+        snake_food_events
+            .filter(session_id.eq(s_id))
+            .order_by((timestamp.asc(), id.asc()))
+            .select(Self::as_select())
+            .get_results(conn)
+            .await
+    }
+}
+
+impl From<&SnakeFoodEvent> for SnakeData {
+    fn from(event: &SnakeFoodEvent) -> Self {
+        Self {
+            timestamp: event.timestamp,
+            prev_timestamp: event.prev_timestamp,
+            points: event.points,
+            prev_points: event.prev_points,
+            length: event.length,
+            prev_length: event.prev_length,
+            level: event.level,
+            prev_level: event.prev_level,
+        }
+    }
 }
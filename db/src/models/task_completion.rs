@@ -3,6 +3,7 @@ use diesel::prelude::*;
 use diesel::result::Error;
 use diesel_async::{AsyncPgConnection, RunQueryDsl};
 use serde::Serialize;
+use std::collections::HashMap;
 
 use crate::schema::task_completions;
 
@@ -36,6 +37,18 @@ impl TaskCompletion {
             .await
     }
 
+    /// Every task completion of a user, newest first
+    pub async fn get_by_user(conn: &mut AsyncPgConnection, u_id: &str) -> Result<Vec<Self>, Error> {
+        use crate::schema::task_completions::dsl::{completed_at, task_completions, user_id};
+
+        task_completions
+            .filter(user_id.eq(u_id))
+            .order(completed_at.desc())
+            .select(Self::as_select())
+            .load(conn)
+            .await
+    }
+
     pub async fn get_user_completed_tasks(
         conn: &mut AsyncPgConnection,
         u_id: &str,
@@ -53,6 +66,29 @@ impl TaskCompletion {
         Ok(results)
     }
 
+    /// Completed task ids of each of the given users. Users without any are left out.
+    pub async fn get_users_completed_tasks(
+        conn: &mut AsyncPgConnection,
+        u_ids: &[String],
+    ) -> Result<HashMap<String, Vec<String>>, Error> {
+        use crate::schema::task_completions::dsl::{
+            points_assigned, task_completions, task_id, user_id,
+        };
+        let rows = task_completions
+            .filter(user_id.eq_any(u_ids))
+            .filter(points_assigned.eq(true))
+            .select((user_id, task_id))
+            .load::<(String, String)>(conn)
+            .await?;
+
+        let mut completed: HashMap<String, Vec<String>> = HashMap::new();
+        for (u_id, t_id) in rows {
+            completed.entry(u_id).or_default().push(t_id);
+        }
+
+        Ok(completed)
+    }
+
     pub async fn task_already_complete(
         conn: &mut AsyncPgConnection,
         u_id: &str,
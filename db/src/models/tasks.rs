@@ -3,27 +3,17 @@ use chrono::{DateTime, Utc};
 use diesel::prelude::*;
 use diesel::result::Error;
 use diesel_async::{AsyncPgConnection, RunQueryDsl};
-use diesel_derive_enum::DbEnum;
 use serde::{Deserialize, Serialize};
+pub use shared::protocol::TaskType;
 use ulid::Ulid;
 
 use crate::models::Platform;
 use crate::schema::tasks;
 
-#[derive(DbEnum, Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq, Hash)]
-#[db_enum(existing_type_path = "crate::schema::sql_types::TaskType")]
-pub enum TaskType {
-    JoinDiscord,
-    FollowTwitter,
-    JoinTelegram,
-    CreateTweet,
-    CheckTelegramPost,
-    CheckDiscordPost,
-    RetweetPost,
-    LikeTweet,
-}
-
-#[derive(Clone, Insertable, Queryable, Selectable, Identifiable, Serialize, Deserialize)]
+#[derive(
+    Clone, Insertable, Queryable, Selectable, Identifiable, AsChangeset, Serialize, Deserialize,
+)]
+#[diesel(treat_none_as_null = true)]
 pub struct Task {
     pub id: String,
     pub task_type: TaskType,
@@ -81,6 +71,28 @@ impl Task {
         })
     }
 
+    /// Moves a validated task onto the id of the task it replaces when it is edited
+    pub fn replacing(
+        mut self,
+        id: String,
+        created_at: DateTime<Utc>,
+        backend_url: &str,
+    ) -> anyhow::Result<Self> {
+        self.redirect_url = Task::enforce_rules(
+            &id,
+            &self.task_type,
+            &self.completion_url,
+            &self.platform,
+            &self.platform_id,
+            &self.platform_username,
+            backend_url,
+        )?;
+        self.id = id;
+        self.created_at = created_at;
+
+        Ok(self)
+    }
+
     fn enforce_rules(
         id: &str,
         task_type: &TaskType,
@@ -204,6 +216,44 @@ impl Task {
             .await
     }
 
+    /// Every task with whether it is active, newest first
+    pub async fn get_all(conn: &mut AsyncPgConnection) -> Result<Vec<(Self, bool)>, Error> {
+        use crate::schema::tasks::dsl::{created_at, is_active, tasks};
+
+        tasks
+            .order(created_at.desc())
+            .select((Self::as_select(), is_active))
+            .load(conn)
+            .await
+    }
+
+    pub async fn get_by_id(
+        t_id: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Option<Self>, Error> {
+        use crate::schema::tasks::dsl::{id, tasks};
+
+        tasks
+            .filter(id.eq(t_id))
+            .select(Self::as_select())
+            .first(conn)
+            .await
+            .optional()
+    }
+
+    /// Saves every field of an edited task. Returns whether it is active, `None` if it does not
+    /// exist.
+    pub async fn update(&self, conn: &mut AsyncPgConnection) -> Result<Option<bool>, Error> {
+        use crate::schema::tasks::dsl::{id, is_active, tasks};
+
+        diesel::update(tasks.filter(id.eq(&self.id)))
+            .set(self)
+            .returning(is_active)
+            .get_result(conn)
+            .await
+            .optional()
+    }
+
     pub async fn set_inactive(t_id: &str, conn: &mut AsyncPgConnection) -> Result<usize, Error> {
         use crate::schema::tasks::dsl::{id, is_active, tasks};
 
@@ -2,10 +2,13 @@ use chrono::{DateTime, Utc};
 use diesel::prelude::*;
 use diesel::result::Error;
 use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+pub use shared::protocol::{DropKind, TetrisPiece};
+use shared::protocol::{TetrisData, TetrisPlacement};
 
 use crate::schema::tetris_snapshots;
 
-#[derive(Default, Debug, Clone, Insertable, Queryable, Selectable)]
+#[derive(Default, Debug, Clone, Insertable, Queryable, Selectable, Serialize, Deserialize)]
 pub struct TetrisSnapshot {
     session_id: String,
     user_id: String,
@@ -19,6 +22,10 @@ pub struct TetrisSnapshot {
     pub prev_level: i32,
     line_points: i32,
     drop_points: i32,
+    pub piece: Option<TetrisPiece>,
+    pub rotation: Option<i32>,
+    pub column_index: Option<i32>,
+    pub drop_kind: Option<DropKind>,
 }
 
 impl TetrisSnapshot {
@@ -37,6 +44,10 @@ impl TetrisSnapshot {
         prev_level: i32,
         line_points: i32,
         drop_points: i32,
+        piece: TetrisPiece,
+        rotation: i32,
+        column_index: i32,
+        drop_kind: DropKind,
     ) -> Self {
         Self {
             session_id,
@@ -51,9 +62,43 @@ impl TetrisSnapshot {
             prev_level,
             line_points,
             drop_points,
+            piece: Some(piece),
+            rotation: Some(rotation),
+            column_index: Some(column_index),
+            drop_kind: Some(drop_kind),
         }
     }
 
+    /// The snapshot of a placement that resulted in `data`
+    #[must_use]
+    pub fn from_data(
+        session_id: String,
+        user_id: String,
+        data: &TetrisData,
+        placement: &TetrisPlacement,
+        line_points: i32,
+        drop_points: i32,
+    ) -> Self {
+        Self::new(
+            session_id,
+            user_id,
+            data.timestamp,
+            data.prev_timestamp,
+            data.points,
+            data.prev_points,
+            data.lines,
+            data.prev_lines,
+            data.level,
+            data.prev_level,
+            line_points,
+            drop_points,
+            placement.piece,
+            placement.rotation,
+            placement.column,
+            placement.drop_kind,
+        )
+    }
+
     pub async fn insert_batch(
         conn: &mut AsyncPgConnection,
         snapshots: Vec<Self>,
@@ -65,4 +110,18 @@ impl TetrisSnapshot {
             .execute(conn)
             .await
     }
+
+    pub async fn get_by_session_id(
+        s_id: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Vec<Self>, Error> {
+        use crate::schema::tetris_snapshots::dsl::{id, session_id, tetris_snapshots, timestamp};
+
+        tetris_snapshots
+            .filter(session_id.eq(s_id))
+            .order_by((timestamp.asc(), id.asc()))
+            .select(Self::as_select())
+            .get_results(conn)
+            .await
+    }
 }
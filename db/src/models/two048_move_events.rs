@@ -2,20 +2,11 @@ use chrono::{DateTime, Utc};
 use diesel::prelude::*;
 use diesel::result::Error;
 use diesel_async::{AsyncPgConnection, RunQueryDsl};
-use diesel_derive_enum::DbEnum;
-use serde::{Deserialize, Serialize};
+pub use shared::protocol::Direction;
+use shared::protocol::Two048Data;
 
 use crate::schema::two048_move_events;
 
-#[derive(DbEnum, Debug, Clone, Copy, Serialize, Deserialize)]
-#[db_enum(existing_type_path = "crate::schema::sql_types::Direction")]
-pub enum Direction {
-    Left,
-    Right,
-    Up,
-    Down,
-}
-
 #[derive(Clone, Insertable, Queryable, Selectable)]
 pub struct Two048MoveEvent {
     pub session_id: String,
@@ -27,6 +18,9 @@ pub struct Two048MoveEvent {
     pub prev_points: i32,
     pub highest_number: i32,
     pub prev_highest_number: i32,
+    pub spawn_row: Option<i32>,
+    pub spawn_col: Option<i32>,
+    pub spawn_value: Option<i32>,
 }
 
 impl Two048MoveEvent {
@@ -42,6 +36,9 @@ impl Two048MoveEvent {
         prev_points: i32,
         highest_number: i32,
         prev_highest_number: i32,
+        spawn_row: Option<i32>,
+        spawn_col: Option<i32>,
+        spawn_value: Option<i32>,
     ) -> Self {
         Self {
             session_id,
@@ -53,9 +50,30 @@ impl Two048MoveEvent {
             prev_points,
             highest_number,
             prev_highest_number,
+            spawn_row,
+            spawn_col,
+            spawn_value,
         }
     }
 
+    #[must_use]
+    pub fn from_data(session_id: String, user_id: String, data: &Two048Data) -> Self {
+        Self::new(
+            session_id,
+            user_id,
+            data.timestamp,
+            data.prev_timestamp,
+            data.direction,
+            data.points,
+            data.prev_points,
+            data.highest_number,
+            data.prev_highest_number,
+            data.new_tile.map(|tile| tile.row as i32),
+            data.new_tile.map(|tile| tile.col as i32),
+            data.new_tile.map(|tile| tile.value),
+        )
+    }
+
     pub async fn insert_batch(
         conn: &mut AsyncPgConnection,
         events: Vec<Self>,
@@ -67,4 +85,20 @@ impl Two048MoveEvent {
             .execute(conn)
             .await
     }
+
+    pub async fn get_by_session_id(
+        s_id: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Vec<Self>, Error> {
+        use crate::schema::two048_move_events::dsl::{
+            id, session_id, timestamp, two048_move_events,
+        };
+
+        two048_move_events
+            .filter(session_id.eq(s_id))
+            .order_by((timestamp.asc(), id.asc()))
+            .select(Self::as_select())
+            .get_results(conn)
+            .await
+    }
 }
@@ -2,21 +2,13 @@ use diesel::dsl::exists;
 use diesel::prelude::*;
 use diesel::result::Error;
 use diesel_async::{AsyncPgConnection, RunQueryDsl};
-use diesel_derive_enum::DbEnum;
 use serde::{Deserialize, Serialize};
+pub use shared::protocol::Platform;
 
 use crate::schema::user_socials;
 
 pub const MAX_SOCIALS: i64 = 2;
 
-#[derive(DbEnum, Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq, Hash)]
-#[db_enum(existing_type_path = "crate::schema::sql_types::Platform")]
-pub enum Platform {
-    Discord,
-    Twitter,
-    Telegram,
-}
-
 #[derive(Clone, Insertable, Queryable, Selectable, Identifiable, Serialize, Deserialize)]
 #[diesel(primary_key(user_id, platform))]
 pub struct UserSocial {
@@ -4,8 +4,11 @@ use diesel::result::Error;
 use diesel::{prelude::*, select};
 use diesel_async::{AsyncPgConnection, RunQueryDsl};
 use serde::Serialize;
+use shared::protocol::UserProfile;
+pub use shared::protocol::UserStatus;
 use ulid::Ulid;
 
+use crate::models::{NewPointTransaction, PointSource};
 use crate::schema::users;
 
 #[derive(Default, Clone, Insertable, Queryable, Selectable, Identifiable, Serialize)]
@@ -20,6 +23,9 @@ pub struct User {
     pub photo_id: Option<String>,
     pub points: i32,
     pub referral_code: Option<String>,
+    /// Never sent to clients so a shadow-banned user can't tell
+    #[serde(skip)]
+    pub status: UserStatus,
 }
 
 impl User {
@@ -43,6 +49,7 @@ impl User {
             photo_id: None,
             points: 0,
             referral_code: None,
+            status: UserStatus::Active,
         }
     }
 
@@ -68,6 +75,7 @@ impl User {
             photo_id: None,
             points,
             referral_code,
+            status: UserStatus::Active,
         }
     }
 
@@ -186,28 +194,55 @@ impl User {
             .await
     }
 
-    pub async fn set_points(
+    /// Adds `to_add` points and writes the matching ledger row. Meant to run inside the
+    /// transaction making the change so the balance and the ledger never disagree.
+    pub async fn increase_points_with_ledger(
+        conn: &mut AsyncPgConnection,
+        u_id: &str,
+        to_add: i32,
+        source: PointSource,
+        reference: Option<String>,
+    ) -> Result<Self, Error> {
+        let user = Self::increase_points(conn, u_id, to_add).await?;
+
+        NewPointTransaction::new(user.user_id.clone(), source, reference, to_add, user.points)
+            .insert(conn)
+            .await?;
+
+        Ok(user)
+    }
+
+    /// Raises the stored balance to `new_points`, never lowering it. When the row already holds
+    /// more points it is left untouched, so the returned user's points differ from `new_points`.
+    pub async fn raise_points(
         conn: &mut AsyncPgConnection,
         u_id: &str,
         new_points: i32,
     ) -> Result<Self, Error> {
         use crate::schema::users::dsl::{points, user_id, users};
 
-        diesel::update(users.filter(user_id.eq(u_id)))
+        let raised = diesel::update(users.filter(user_id.eq(u_id)).filter(points.le(new_points)))
             .set(points.eq(new_points))
             .returning(Self::as_returning())
             .get_result(conn)
             .await
+            .optional()?;
+
+        match raised {
+            Some(user) => Ok(user),
+            None => Self::get_user(conn, u_id.to_string()).await,
+        }
     }
 
     pub async fn get_leaderboard(
         conn: &mut AsyncPgConnection,
         limit: i64,
     ) -> Result<Vec<Self>, Error> {
-        use crate::schema::users::dsl::{points, users};
+        use crate::schema::users::dsl::{points, status, users};
 
         users
             .filter(points.gt(0))
+            .filter(status.eq(UserStatus::Active))
             .order(points.desc())
             .limit(limit)
             .select(Self::as_select())
@@ -215,6 +250,100 @@ impl User {
             .await
     }
 
+    /// Leaderboard page ordered by points then user id, starting after the `(points, user_id)`
+    /// cursor if given
+    pub async fn get_leaderboard_page(
+        conn: &mut AsyncPgConnection,
+        after: Option<(i32, &str)>,
+        limit: i64,
+    ) -> Result<Vec<Self>, Error> {
+        use crate::schema::users::dsl::{points, status, user_id, users};
+
+        let mut query = users
+            .filter(points.gt(0))
+            .filter(status.eq(UserStatus::Active))
+            .into_boxed();
+
+        if let Some((after_points, after_id)) = after {
+            query = query.filter(
+                points.lt(after_points).or(points
+                    .eq(after_points)
+                    .and(user_id.gt(after_id.to_string()))),
+            );
+        }
+
+        query
+            .order((points.desc(), user_id.asc()))
+            .limit(limit)
+            .select(Self::as_select())
+            .load(conn)
+            .await
+    }
+
+    /// Users ranked right above the given position, closest first. The user itself is skipped
+    /// as its stored points may be behind.
+    pub async fn get_ranked_above(
+        conn: &mut AsyncPgConnection,
+        u_id: &str,
+        u_points: i32,
+        limit: i64,
+    ) -> Result<Vec<Self>, Error> {
+        use crate::schema::users::dsl::{points, status, user_id, users};
+
+        users
+            .filter(user_id.ne(u_id))
+            .filter(status.eq(UserStatus::Active))
+            .filter(
+                points
+                    .gt(u_points)
+                    .or(points.eq(u_points).and(user_id.lt(u_id))),
+            )
+            .order((points.asc(), user_id.desc()))
+            .limit(limit)
+            .select(Self::as_select())
+            .load(conn)
+            .await
+    }
+
+    /// Users ranked right below the given position, closest first. The user itself is skipped
+    /// as its stored points may be behind.
+    pub async fn get_ranked_below(
+        conn: &mut AsyncPgConnection,
+        u_id: &str,
+        u_points: i32,
+        limit: i64,
+    ) -> Result<Vec<Self>, Error> {
+        use crate::schema::users::dsl::{points, status, user_id, users};
+
+        users
+            .filter(user_id.ne(u_id))
+            .filter(points.gt(0))
+            .filter(status.eq(UserStatus::Active))
+            .filter(
+                points
+                    .lt(u_points)
+                    .or(points.eq(u_points).and(user_id.gt(u_id))),
+            )
+            .order((points.desc(), user_id.asc()))
+            .limit(limit)
+            .select(Self::as_select())
+            .load(conn)
+            .await
+    }
+
+    pub async fn get_by_ids(
+        conn: &mut AsyncPgConnection,
+        u_ids: &[String],
+    ) -> Result<Vec<Self>, Error> {
+        use crate::schema::users::dsl::{user_id, users};
+
+        users
+            .filter(user_id.eq_any(u_ids))
+            .select(Self::as_select())
+            .load(conn)
+            .await
+    }
+
     pub async fn set_referral_code(
         conn: &mut AsyncPgConnection,
         u_id: &str,
@@ -228,6 +357,53 @@ impl User {
             .await
     }
 
+    /// The user that bound `wallet` as either their Solana or EVM wallet
+    pub async fn get_by_wallet(
+        conn: &mut AsyncPgConnection,
+        wallet: &str,
+    ) -> Result<Option<Self>, Error> {
+        use crate::schema::users::dsl::{evm_wallet, sol_wallet, users};
+
+        users
+            .filter(sol_wallet.eq(wallet).or(evm_wallet.eq(wallet)))
+            .select(Self::as_select())
+            .first(conn)
+            .await
+            .optional()
+    }
+
+    /// The user that linked a social account with `p_id` on any platform
+    pub async fn get_by_social_id(
+        conn: &mut AsyncPgConnection,
+        p_id: &str,
+    ) -> Result<Option<Self>, Error> {
+        use crate::schema::user_socials::dsl::{platform_user_id, user_socials};
+        use crate::schema::users::dsl::users;
+
+        users
+            .inner_join(user_socials)
+            .filter(platform_user_id.eq(p_id))
+            .select(Self::as_select())
+            .first(conn)
+            .await
+            .optional()
+    }
+
+    pub async fn set_status(
+        conn: &mut AsyncPgConnection,
+        u_id: &str,
+        new_status: UserStatus,
+    ) -> Result<Option<Self>, Error> {
+        use crate::schema::users::dsl::{status, user_id, users};
+
+        diesel::update(users.filter(user_id.eq(u_id)))
+            .set(status.eq(new_status))
+            .returning(Self::as_returning())
+            .get_result(conn)
+            .await
+            .optional()
+    }
+
     pub async fn get_by_referral_code(
         conn: &mut AsyncPgConnection,
         code: &str,
@@ -268,3 +444,19 @@ impl User {
             .await
     }
 }
+
+impl From<User> for UserProfile {
+    fn from(user: User) -> Self {
+        Self {
+            joined_at: user.joined_at,
+            user_id: user.user_id,
+            username: user.username,
+            sol_wallet: user.sol_wallet,
+            evm_wallet: user.evm_wallet,
+            photo_url: user.photo_url,
+            photo_id: user.photo_id,
+            points: user.points,
+            referral_code: user.referral_code,
+        }
+    }
+}
@@ -1,21 +1,7 @@
 // @generated automatically by Diesel CLI.
 
 pub mod sql_types {
-    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
-    #[diesel(postgres_type(name = "direction"))]
-    pub struct Direction;
-
-    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
-    #[diesel(postgres_type(name = "game_type"))]
-    pub struct GameType;
-
-    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
-    #[diesel(postgres_type(name = "platform"))]
-    pub struct Platform;
-
-    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
-    #[diesel(postgres_type(name = "task_type"))]
-    pub struct TaskType;
+    pub use shared::protocol::sql_types::*;
 }
 
 diesel::table! {
@@ -43,6 +29,37 @@ diesel::table! {
         start_time -> Timestamptz,
         end_time -> Timestamptz,
         final_score -> Int4,
+        seed -> Nullable<Int8>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::LeaderboardWindow;
+
+    leaderboard_archives (id) {
+        id -> Int4,
+        window_type -> LeaderboardWindow,
+        period -> Text,
+        user_id -> Text,
+        rank -> Int4,
+        score -> Int4,
+        archived_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::PointSource;
+
+    point_transactions (id) {
+        id -> Int8,
+        user_id -> Text,
+        source -> PointSource,
+        reference -> Nullable<Text>,
+        amount -> Int4,
+        balance -> Int4,
+        created_at -> Timestamptz,
     }
 }
 
@@ -65,6 +82,16 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    seasons (id) {
+        id -> Text,
+        name -> Text,
+        starts_at -> Timestamptz,
+        ends_at -> Timestamptz,
+        archived -> Bool,
+    }
+}
+
 diesel::table! {
     snake_food_events (id) {
         id -> Int4,
@@ -115,6 +142,10 @@ diesel::table! {
 }
 
 diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::TetrisPiece;
+    use super::sql_types::DropKind;
+
     tetris_snapshots (id) {
         id -> Int4,
         session_id -> Text,
@@ -129,6 +160,10 @@ diesel::table! {
         prev_level -> Int4,
         line_points -> Int4,
         drop_points -> Int4,
+        piece -> Nullable<TetrisPiece>,
+        rotation -> Nullable<Int4>,
+        column_index -> Nullable<Int4>,
+        drop_kind -> Nullable<DropKind>,
     }
 }
 
@@ -147,6 +182,9 @@ diesel::table! {
         prev_points -> Int4,
         highest_number -> Int4,
         prev_highest_number -> Int4,
+        spawn_row -> Nullable<Int4>,
+        spawn_col -> Nullable<Int4>,
+        spawn_value -> Nullable<Int4>,
     }
 }
 
@@ -163,6 +201,9 @@ diesel::table! {
 }
 
 diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::UserStatus;
+
     users (user_id) {
         joined_at -> Timestamptz,
         user_id -> Text,
@@ -173,12 +214,15 @@ diesel::table! {
         photo_url -> Text,
         photo_id -> Nullable<Text>,
         referral_code -> Nullable<Text>,
+        status -> UserStatus,
     }
 }
 
 diesel::joinable!(flappy_score_events -> game_sessions (session_id));
 diesel::joinable!(flappy_score_events -> users (user_id));
 diesel::joinable!(game_sessions -> users (user_id));
+diesel::joinable!(leaderboard_archives -> users (user_id));
+diesel::joinable!(point_transactions -> users (user_id));
 diesel::joinable!(referral_rewards -> game_sessions (session_id));
 diesel::joinable!(snake_food_events -> game_sessions (session_id));
 diesel::joinable!(snake_food_events -> users (user_id));
@@ -193,8 +237,11 @@ diesel::joinable!(user_socials -> users (user_id));
 diesel::allow_tables_to_appear_in_same_query!(
     flappy_score_events,
     game_sessions,
+    leaderboard_archives,
+    point_transactions,
     referral_rewards,
     referrals,
+    seasons,
     snake_food_events,
     task_completions,
     tasks,
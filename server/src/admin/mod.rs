@@ -0,0 +1,37 @@
+mod seasons;
+mod tasks;
+mod users;
+
+pub use seasons::*;
+pub use tasks::*;
+pub use users::*;
+
+use actix_web::{Error, HttpRequest, error};
+use log::error;
+use sha2::{Digest, Sha256};
+
+use crate::ADMIN_TOKEN;
+use crate::endpoints::extract_token;
+
+/// Rejects requests that don't carry the admin token. Admin routes are closed when no token is
+/// configured.
+pub fn require_admin(req: &HttpRequest) -> Result<(), Error> {
+    let Some(admin_token) = ADMIN_TOKEN.get() else {
+        return Err(error::ErrorUnauthorized("Admin API is disabled"));
+    };
+
+    let token = extract_token(req.headers())
+        .ok_or_else(|| error::ErrorUnauthorized("Missing or invalid Authorization header"))?;
+
+    // Comparing digests keeps the time taken independent of how much of the token matched
+    if Sha256::digest(token.as_bytes()) != Sha256::digest(admin_token.as_bytes()) {
+        return Err(error::ErrorUnauthorized("Invalid admin token"));
+    }
+
+    Ok(())
+}
+
+fn internal_error(e: impl std::fmt::Debug) -> Error {
+    error!("Admin request failed. Reason: {e:?}");
+    error::ErrorInternalServerError("Internal server error")
+}
@@ -0,0 +1,92 @@
+use actix_web::web::{Data, Json, Path};
+use actix_web::{Error, HttpRequest, HttpResponse, error};
+use chrono::{DateTime, Utc};
+use db::models::{LeaderboardArchive, LeaderboardWindow, Season};
+use diesel_async::AsyncPgConnection;
+use diesel_async::pooled_connection::bb8::Pool;
+use log::info;
+use serde::Deserialize;
+
+use crate::admin::{internal_error, require_admin};
+
+#[derive(Deserialize)]
+pub struct SeasonInput {
+    name: String,
+    starts_at: DateTime<Utc>,
+    ends_at: DateTime<Utc>,
+}
+
+pub async fn list_seasons(
+    req: HttpRequest,
+    pool: Data<Pool<AsyncPgConnection>>,
+) -> Result<HttpResponse, Error> {
+    require_admin(&req)?;
+
+    let mut conn = pool.get().await.map_err(internal_error)?;
+    let seasons = Season::get_all(&mut conn).await.map_err(internal_error)?;
+
+    Ok(HttpResponse::Ok().json(seasons))
+}
+
+/// Schedules a season. The window rollover picks it up once it starts and archives it after it
+/// ends.
+pub async fn create_season(
+    req: HttpRequest,
+    input: Json<SeasonInput>,
+    pool: Data<Pool<AsyncPgConnection>>,
+) -> Result<HttpResponse, Error> {
+    require_admin(&req)?;
+
+    let SeasonInput {
+        name,
+        starts_at,
+        ends_at,
+    } = input.into_inner();
+
+    if name.trim().is_empty() {
+        return Err(error::ErrorBadRequest("A name is required"));
+    }
+
+    if ends_at <= starts_at {
+        return Err(error::ErrorBadRequest("ends_at must be after starts_at"));
+    }
+
+    if ends_at <= Utc::now() {
+        return Err(error::ErrorBadRequest("ends_at must be in the future"));
+    }
+
+    let season = Season::new(name, starts_at, ends_at);
+
+    let mut conn = pool.get().await.map_err(internal_error)?;
+    season.insert(&mut conn).await.map_err(internal_error)?;
+
+    info!(
+        "Admin created season {} ({}) from {starts_at} to {ends_at}",
+        season.name, season.id
+    );
+
+    Ok(HttpResponse::Created().json(season))
+}
+
+/// Final standings of a closed window period. Daily periods look like `2026-10-18`, weekly ones
+/// like `2026-W42` and seasons use the season id.
+pub async fn leaderboard_archive(
+    req: HttpRequest,
+    path: Path<(LeaderboardWindow, String)>,
+    pool: Data<Pool<AsyncPgConnection>>,
+) -> Result<HttpResponse, Error> {
+    require_admin(&req)?;
+
+    let (window, period) = path.into_inner();
+
+    let mut conn = pool.get().await.map_err(internal_error)?;
+    let standings = LeaderboardArchive::get_by_period(window, &period, &mut conn)
+        .await
+        .map_err(internal_error)?;
+
+    if standings.is_empty() {
+        return Err(error::ErrorNotFound("No archive for this period"));
+    }
+
+    Ok(HttpResponse::Ok().json(standings))
+}
@@ -0,0 +1,184 @@
+use actix_web::web::{Data, Json, Path};
+use actix_web::{Error, HttpRequest, HttpResponse, error};
+use chrono::{DateTime, Utc};
+use db::models::{Platform, Task, TaskType};
+use diesel_async::AsyncPgConnection;
+use diesel_async::pooled_connection::bb8::Pool;
+use log::{error, info};
+use redis::AsyncCommands;
+use redis::aio::ConnectionManager;
+use serde::{Deserialize, Serialize};
+
+use crate::BACKEND_URL;
+use crate::admin::{internal_error, require_admin};
+use crate::ws::redis_ops::{TASKS_SUB, remove_tasks, update_task_details};
+
+/// A task as sent by the admin. The id and redirect url are filled in by the server.
+#[derive(Deserialize)]
+pub struct TaskInput {
+    task_type: TaskType,
+    ends_at: Option<DateTime<Utc>>,
+    title: String,
+    description: String,
+    completion_url: Option<String>,
+    platform: Option<Platform>,
+    platform_id: Option<String>,
+    platform_username: Option<String>,
+    reward_point: i32,
+}
+
+impl TaskInput {
+    fn into_task(self) -> anyhow::Result<Task> {
+        Task::new(
+            self.task_type,
+            self.ends_at,
+            self.title,
+            self.description,
+            self.completion_url,
+            self.platform,
+            self.platform_id,
+            self.platform_username,
+            self.reward_point,
+            BACKEND_URL.get().unwrap(),
+        )
+    }
+}
+
+#[derive(Serialize)]
+struct AdminTask {
+    #[serde(flatten)]
+    task: Task,
+    is_active: bool,
+}
+
+pub async fn list_tasks(
+    req: HttpRequest,
+    pool: Data<Pool<AsyncPgConnection>>,
+) -> Result<HttpResponse, Error> {
+    require_admin(&req)?;
+
+    let mut conn = pool.get().await.map_err(internal_error)?;
+    let tasks: Vec<AdminTask> = Task::get_all(&mut conn)
+        .await
+        .map_err(internal_error)?
+        .into_iter()
+        .map(|(task, is_active)| AdminTask { task, is_active })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(tasks))
+}
+
+pub async fn create_task(
+    req: HttpRequest,
+    input: Json<TaskInput>,
+    pool: Data<Pool<AsyncPgConnection>>,
+    redis_conn: Data<ConnectionManager>,
+) -> Result<HttpResponse, Error> {
+    require_admin(&req)?;
+
+    let task = input
+        .into_inner()
+        .into_task()
+        .map_err(|e| error::ErrorBadRequest(e.to_string()))?;
+
+    let mut conn = pool.get().await.map_err(internal_error)?;
+    task.clone()
+        .insert(&mut conn)
+        .await
+        .map_err(internal_error)?;
+
+    let mut redis_conn = redis_conn.as_ref().clone();
+    update_task_details(&mut redis_conn, &task.id, task.json_string())
+        .await
+        .map_err(internal_error)?;
+    publish_tasks_update(&mut redis_conn).await;
+
+    info!("Admin created task {} {}", task.id, task.title);
+
+    Ok(HttpResponse::Created().json(AdminTask {
+        task,
+        is_active: true,
+    }))
+}
+
+pub async fn edit_task(
+    req: HttpRequest,
+    task_id: Path<String>,
+    input: Json<TaskInput>,
+    pool: Data<Pool<AsyncPgConnection>>,
+    redis_conn: Data<ConnectionManager>,
+) -> Result<HttpResponse, Error> {
+    require_admin(&req)?;
+
+    let mut conn = pool.get().await.map_err(internal_error)?;
+
+    let Some(existing) = Task::get_by_id(&task_id, &mut conn)
+        .await
+        .map_err(internal_error)?
+    else {
+        return Err(error::ErrorNotFound("Task not found"));
+    };
+
+    let task = input
+        .into_inner()
+        .into_task()
+        .and_then(|task| {
+            task.replacing(existing.id, existing.created_at, BACKEND_URL.get().unwrap())
+        })
+        .map_err(|e| error::ErrorBadRequest(e.to_string()))?;
+
+    let Some(is_active) = task.update(&mut conn).await.map_err(internal_error)? else {
+        return Err(error::ErrorNotFound("Task not found"));
+    };
+
+    if is_active {
+        let mut redis_conn = redis_conn.as_ref().clone();
+        update_task_details(&mut redis_conn, &task.id, task.json_string())
+            .await
+            .map_err(internal_error)?;
+        publish_tasks_update(&mut redis_conn).await;
+    }
+
+    info!("Admin edited task {}", task.id);
+
+    Ok(HttpResponse::Ok().json(AdminTask { task, is_active }))
+}
+
+pub async fn deactivate_task(
+    req: HttpRequest,
+    task_id: Path<String>,
+    pool: Data<Pool<AsyncPgConnection>>,
+    redis_conn: Data<ConnectionManager>,
+) -> Result<HttpResponse, Error> {
+    require_admin(&req)?;
+
+    let task_id = task_id.into_inner();
+    let mut conn = pool.get().await.map_err(internal_error)?;
+
+    let updated = Task::set_inactive(&task_id, &mut conn)
+        .await
+        .map_err(internal_error)?;
+
+    if updated == 0 {
+        return Err(error::ErrorNotFound("Task not found"));
+    }
+
+    let mut redis_conn = redis_conn.as_ref().clone();
+    remove_tasks(&mut redis_conn, std::slice::from_ref(&task_id))
+        .await
+        .map_err(internal_error)?;
+    publish_tasks_update(&mut redis_conn).await;
+
+    info!("Admin deactivated task {task_id}");
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Makes every instance push the new task list to its logged in users
+async fn publish_tasks_update(redis_conn: &mut ConnectionManager) {
+    let result: redis::RedisResult<()> = redis_conn.publish(TASKS_SUB, "Tasks updated").await;
+
+    if let Err(e) = result {
+        error!("Failed to publish tasks update. Reason: {e:?}");
+    }
+}
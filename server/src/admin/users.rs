@@ -0,0 +1,325 @@
+use actix_web::web::{Data, Json, Path, Query};
+use actix_web::{Error, HttpRequest, HttpResponse, error};
+use db::models::{
+    GameSession, PointSource, PointTransaction, TaskCompletion, User, UserSocial, UserStatus,
+};
+use diesel_async::pooled_connection::bb8::Pool;
+use diesel_async::{AsyncConnection, AsyncPgConnection};
+use log::info;
+use redis::aio::ConnectionManager;
+use serde::{Deserialize, Serialize};
+
+use crate::admin::{internal_error, require_admin};
+use crate::ws::redis_ops::{revoke_user_tokens, user_connections};
+use crate::ws::server::{Server, ServerInterface};
+
+/// Point changes shown per user
+const POINT_HISTORY_LIMIT: i64 = 100;
+
+/// Exactly one of the fields is expected
+#[derive(Deserialize)]
+pub struct UserLookup {
+    wallet: Option<String>,
+    referral_code: Option<String>,
+    social_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PointChange {
+    Adjust(i32),
+    Zero,
+}
+
+#[derive(Deserialize)]
+pub struct PointAdjustment {
+    change: PointChange,
+    reason: String,
+}
+
+#[derive(Deserialize)]
+pub struct StatusChange {
+    status: UserStatus,
+}
+
+#[derive(Serialize)]
+struct AdminUser {
+    #[serde(flatten)]
+    user: User,
+    status: UserStatus,
+    socials: Vec<UserSocial>,
+    live_connections: usize,
+}
+
+pub async fn lookup_user(
+    req: HttpRequest,
+    query: Query<UserLookup>,
+    pool: Data<Pool<AsyncPgConnection>>,
+    redis_conn: Data<ConnectionManager>,
+) -> Result<HttpResponse, Error> {
+    require_admin(&req)?;
+
+    let mut conn = pool.get().await.map_err(internal_error)?;
+
+    let user = match query.into_inner() {
+        UserLookup {
+            wallet: Some(wallet),
+            referral_code: None,
+            social_id: None,
+        } => User::get_by_wallet(&mut conn, &wallet).await,
+        UserLookup {
+            wallet: None,
+            referral_code: Some(code),
+            social_id: None,
+        } => User::get_by_referral_code(&mut conn, &code).await,
+        UserLookup {
+            wallet: None,
+            referral_code: None,
+            social_id: Some(social_id),
+        } => User::get_by_social_id(&mut conn, &social_id).await,
+        _ => {
+            return Err(error::ErrorBadRequest(
+                "Expected one of wallet, referral_code or social_id",
+            ));
+        }
+    }
+    .map_err(internal_error)?;
+
+    let Some(user) = user else {
+        return Err(error::ErrorNotFound("User not found"));
+    };
+
+    let admin_user = admin_user(&mut conn, redis_conn.as_ref(), user).await?;
+
+    Ok(HttpResponse::Ok().json(admin_user))
+}
+
+pub async fn user_sessions(
+    req: HttpRequest,
+    user_id: Path<String>,
+    pool: Data<Pool<AsyncPgConnection>>,
+) -> Result<HttpResponse, Error> {
+    require_admin(&req)?;
+
+    let mut conn = pool.get().await.map_err(internal_error)?;
+    let sessions = GameSession::get_by_user_id(&user_id, &mut conn)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(HttpResponse::Ok().json(sessions))
+}
+
+pub async fn user_task_completions(
+    req: HttpRequest,
+    user_id: Path<String>,
+    pool: Data<Pool<AsyncPgConnection>>,
+) -> Result<HttpResponse, Error> {
+    require_admin(&req)?;
+
+    let mut conn = pool.get().await.map_err(internal_error)?;
+    let completions = TaskCompletion::get_by_user(&mut conn, &user_id)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(HttpResponse::Ok().json(completions))
+}
+
+pub async fn user_point_history(
+    req: HttpRequest,
+    user_id: Path<String>,
+    pool: Data<Pool<AsyncPgConnection>>,
+) -> Result<HttpResponse, Error> {
+    require_admin(&req)?;
+
+    let mut conn = pool.get().await.map_err(internal_error)?;
+    let transactions = PointTransaction::get_by_user(&mut conn, &user_id, POINT_HISTORY_LIMIT)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(HttpResponse::Ok().json(transactions))
+}
+
+/// Changes the points of a user the same way games and tasks do, so redis, the leaderboard and
+/// the ledger stay in step.
+pub async fn adjust_points(
+    req: HttpRequest,
+    user_id: Path<String>,
+    input: Json<PointAdjustment>,
+    pool: Data<Pool<AsyncPgConnection>>,
+    server: Data<Server>,
+) -> Result<HttpResponse, Error> {
+    require_admin(&req)?;
+
+    let PointAdjustment { change, reason } = input.into_inner();
+
+    if reason.trim().is_empty() {
+        return Err(error::ErrorBadRequest("A reason is required"));
+    }
+
+    let mut conn = pool.get().await.map_err(internal_error)?;
+    let user = find_user(&mut conn, &user_id).await?;
+
+    // Points still only in redis are written first so the change applies to the latest balance
+    let mut server = server.as_ref().clone();
+    server
+        .ensure_user_cached(&user)
+        .await
+        .map_err(internal_error)?;
+    server
+        .flush_user_points(&mut conn, &user.user_id)
+        .await
+        .map_err(internal_error)?;
+
+    let user = find_user(&mut conn, &user_id).await?;
+
+    let amount = match change {
+        PointChange::Adjust(amount) => amount,
+        PointChange::Zero => -user.points,
+    };
+
+    if amount == 0 {
+        return Ok(HttpResponse::Ok().json(user.points));
+    }
+
+    // Applied to Postgres first as flushes never lower the stored balance
+    conn.transaction::<_, diesel::result::Error, _>(async |conn| {
+        User::increase_points_with_ledger(
+            conn,
+            &user.user_id,
+            amount,
+            PointSource::Admin,
+            Some(reason.clone()),
+        )
+        .await
+    })
+    .await
+    .map_err(internal_error)?;
+    drop(conn);
+
+    let total_points = server
+        .increase_point(amount, &user, PointSource::Admin, None)
+        .await
+        .map_err(internal_error)?;
+
+    info!(
+        "Admin changed points of {} by {amount} to {total_points}. Reason: {reason}",
+        user.user_id
+    );
+
+    Ok(HttpResponse::Ok().json(total_points))
+}
+
+/// Sets the moderation status. Banned and shadow banned users are taken off the leaderboards and
+/// disconnected so their next login picks up the new status. Users made active again are put
+/// back on the leaderboards they qualify for.
+pub async fn set_user_status(
+    req: HttpRequest,
+    user_id: Path<String>,
+    input: Json<StatusChange>,
+    pool: Data<Pool<AsyncPgConnection>>,
+    redis_conn: Data<ConnectionManager>,
+    handler: Data<ServerInterface>,
+    server: Data<Server>,
+) -> Result<HttpResponse, Error> {
+    require_admin(&req)?;
+
+    let new_status = input.into_inner().status;
+    let mut conn = pool.get().await.map_err(internal_error)?;
+
+    let Some(user) = User::set_status(&mut conn, &user_id, new_status)
+        .await
+        .map_err(internal_error)?
+    else {
+        return Err(error::ErrorNotFound("User not found"));
+    };
+
+    info!("Admin set status of {} to {new_status:?}", user.user_id);
+
+    let mut server = server.as_ref().clone();
+
+    if new_status == UserStatus::Active {
+        server
+            .restore_to_leaderboards(&user)
+            .await
+            .map_err(internal_error)?;
+    } else {
+        server
+            .remove_from_leaderboards(&user.user_id)
+            .await
+            .map_err(internal_error)?;
+
+        if new_status == UserStatus::Banned {
+            revoke_user_tokens(&mut redis_conn.as_ref().clone(), &user.user_id)
+                .await
+                .map_err(internal_error)?;
+        }
+
+        disconnect_all(redis_conn.as_ref(), &handler, &user.user_id).await?;
+    }
+
+    let admin_user = admin_user(&mut conn, redis_conn.as_ref(), user).await?;
+
+    Ok(HttpResponse::Ok().json(admin_user))
+}
+
+pub async fn disconnect_user(
+    req: HttpRequest,
+    user_id: Path<String>,
+    redis_conn: Data<ConnectionManager>,
+    handler: Data<ServerInterface>,
+) -> Result<HttpResponse, Error> {
+    require_admin(&req)?;
+
+    let disconnected = disconnect_all(redis_conn.as_ref(), &handler, &user_id).await?;
+
+    info!("Admin disconnected {disconnected} connections of {user_id}");
+
+    Ok(HttpResponse::Ok().json(disconnected))
+}
+
+async fn find_user(conn: &mut AsyncPgConnection, user_id: &str) -> Result<User, Error> {
+    match User::get_user(conn, user_id.to_string()).await {
+        Ok(user) => Ok(user),
+        Err(diesel::result::Error::NotFound) => Err(error::ErrorNotFound("User not found")),
+        Err(e) => Err(internal_error(e)),
+    }
+}
+
+async fn admin_user(
+    conn: &mut AsyncPgConnection,
+    redis_conn: &ConnectionManager,
+    user: User,
+) -> Result<AdminUser, Error> {
+    let socials = UserSocial::get_user_socials(conn, &user.user_id)
+        .await
+        .map_err(internal_error)?;
+
+    let live_connections = user_connections(&mut redis_conn.clone(), &user.user_id)
+        .await
+        .map_err(internal_error)?
+        .len();
+
+    Ok(AdminUser {
+        status: user.status,
+        user,
+        socials,
+        live_connections,
+    })
+}
+
+/// Closes every live connection of the user, whichever instance holds it
+async fn disconnect_all(
+    redis_conn: &ConnectionManager,
+    handler: &ServerInterface,
+    user_id: &str,
+) -> Result<usize, Error> {
+    let conn_ids = user_connections(&mut redis_conn.clone(), user_id)
+        .await
+        .map_err(internal_error)?;
+
+    for conn_id in &conn_ids {
+        handler.close(*conn_id);
+    }
+
+    Ok(conn_ids.len())
+}
@@ -2,7 +2,6 @@ use actix_web::web::{self, Data};
 use actix_web::{HttpResponse, Responder};
 use anyhow::{Context as _, Result};
 use chrono::Utc;
-use dashmap::DashMap;
 use db::models::{Platform, UserSocial};
 use diesel_async::AsyncPgConnection;
 use diesel_async::pooled_connection::bb8::Pool;
@@ -10,11 +9,12 @@ use log::{error, info};
 use redis::aio::ConnectionManager;
 use reqwest::Client;
 use std::collections::HashMap;
-use std::sync::Arc;
 
 use crate::auth::{CodeVerifier, DiscordUser, OAuthUrl, TokenResponse};
 use crate::ws::get_body_text;
-use crate::ws::redis_ops::{USER_KEY, update_user_discord};
+use crate::ws::redis_ops::{
+    USER_KEY, delete_code_verifier, get_code_verifier, update_user_discord,
+};
 use crate::ws::server::{ConnId, Server, ServerInterface};
 use crate::{
     DISCORD_CLIENT_ID, DISCORD_CLIENT_SECRET, DISCORD_REDIRECT_FULL, DISCORD_REDIRECT_URI,
@@ -22,7 +22,6 @@ use crate::{
 
 pub async fn discord_callback(
     query: web::Query<serde_json::Value>,
-    verifier_list: Data<Arc<DashMap<String, CodeVerifier>>>,
     conn: Data<Pool<AsyncPgConnection>>,
     redis_conn: Data<ConnectionManager>,
     server: web::Data<Server>,
@@ -36,20 +35,29 @@ pub async fn discord_callback(
         return HttpResponse::BadRequest().body(get_body_text("Missing 'state' parameter"));
     };
 
+    let mut redis = redis_conn.as_ref().clone();
+
     let conn_id;
 
     {
-        let Some(code_verifier) = verifier_list.get(state) else {
+        let Ok(code_verifier) = get_code_verifier(&mut redis, state).await else {
+            return HttpResponse::InternalServerError()
+                .body(get_body_text("Internal server error"));
+        };
+
+        let Some(code_verifier) = code_verifier else {
             return HttpResponse::BadRequest()
                 .body(get_body_text("The link used to log in has expired"));
         };
 
         conn_id = code_verifier.conn_id;
 
-        let Some(target_user) = server
-            .logged_in
-            .get(&conn_id)
-            .map(|entry| entry.value().user_id.clone())
+        // The socket may be held by another instance
+        let mut server = server.as_ref().clone();
+        let Ok(Some(target_user)) = server
+            .conn_user(conn_id)
+            .await
+            .map(|user| user.map(|user| user.user_id))
         else {
             return HttpResponse::BadRequest().body(get_body_text("Connection not found"));
         };
@@ -98,12 +106,10 @@ pub async fn discord_callback(
             return HttpResponse::BadRequest().body(get_body_text("Failed to login with discord"));
         }
 
-        let mut conn = redis_conn.as_ref().clone();
-
         let user_key = format!("{USER_KEY}:{target_user}");
 
         if let Err(e) =
-            update_user_discord(&mut conn, &user_key, profile.username, profile.id).await
+            update_user_discord(&mut redis, &user_key, profile.username, profile.id).await
         {
             error!("Failed to update user discord. Reason: {e}");
             return HttpResponse::InternalServerError()
@@ -111,8 +117,10 @@ pub async fn discord_callback(
         }
     }
 
-    verifier_list.remove(state);
-    handler.me_with_rank_socials(conn_id);
+    if let Err(e) = delete_code_verifier(&mut redis, state).await {
+        error!("Failed to delete code verifier {state}. Reason: {e}");
+    }
+    handler.push_me_with_rank_socials(conn_id);
 
     HttpResponse::Ok()
         .content_type("text/html; charset=utf-8")
@@ -1,9 +1,7 @@
 mod discord;
-mod invalidater;
 mod state;
 mod twitter;
 
 pub use discord::*;
-pub use invalidater::*;
 pub use state::*;
 pub use twitter::*;
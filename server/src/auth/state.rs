@@ -1,5 +1,5 @@
 use chrono::{DateTime, Utc};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use crate::ws::server::ConnId;
 
@@ -8,6 +8,7 @@ pub struct OAuthUrl {
     pub code_verifier: CodeVerifier,
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct CodeVerifier {
     pub created_on: DateTime<Utc>,
     pub lifetime: u8,
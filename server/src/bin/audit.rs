@@ -0,0 +1,389 @@
+//! Re-validates stored game sessions with the current validator rules.
+//!
+//! Usage: `audit [game]` where `game` is one of `tetris`, `snake`, `2048` or `flappy`.
+//! Only reads from Postgres, live state is never touched. Tetris and 2048 sessions stored before
+//! seeds and placements were recorded can't be re-simulated and get the arithmetic checks the
+//! validators used back then instead.
+
+use anyhow::{Result, anyhow};
+use db::get_connection;
+use db::models::{
+    FlappyScoreEvent, GameSession, GameType, SnakeFoodEvent, TetrisSnapshot, Two048MoveEvent,
+};
+use diesel_async::AsyncPgConnection;
+use log::{LevelFilter, error, info};
+use server::ws::models::{FlappyData, SnakeData, TetrisPlacement, Two048Move};
+use server::ws::validator::consts::{
+    BOARD_HEIGHT, LEVEL_UP, MIN_TIME, POINTS_PER_LINE, VALID_TILES,
+};
+use server::ws::validator::flappy::flappy_move_valid;
+use server::ws::validator::rng::SeededRng;
+use server::ws::validator::snake::snake_move_valid;
+use server::ws::validator::tetris::tetris_move_valid;
+use server::ws::validator::tetris_engine::TetrisEngine;
+use server::ws::validator::two048::{
+    expected_score, two048_initial_state, two048_move_valid, two048_next_state,
+};
+use std::env::{args, var};
+use std::str::FromStr;
+
+const BATCH_SIZE: i64 = 500;
+
+enum Verdict {
+    Clean,
+    Flagged(anyhow::Error),
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenvy::dotenv().ok();
+
+    pretty_env_logger::formatted_timed_builder()
+        .format_timestamp_millis()
+        .filter_level(LevelFilter::Info)
+        .init();
+
+    let game_type = match args().nth(1) {
+        Some(game) => {
+            Some(GameType::from_str(&game).map_err(|()| anyhow!("Unknown game type {game}"))?)
+        }
+        None => None,
+    };
+
+    let database_url_tbd = var("DATABASE_URL_TBD").expect("DATABASE_URL_TBD must be set");
+    let pool = get_connection(&database_url_tbd).await;
+    let mut conn = pool.get().await?;
+
+    let mut last_id: Option<String> = None;
+    let mut audited = 0;
+    let mut flagged = 0;
+
+    loop {
+        let sessions =
+            GameSession::get_batch(last_id.as_deref(), game_type, BATCH_SIZE, &mut conn).await?;
+
+        let Some(last) = sessions.last() else {
+            break;
+        };
+        last_id = Some(last.id.clone());
+
+        for session in sessions {
+            audited += 1;
+
+            match audit_session(&session, &mut conn).await {
+                Ok(Verdict::Clean) => {}
+                Ok(Verdict::Flagged(reason)) => {
+                    flagged += 1;
+                    println!(
+                        "FLAGGED {} user {} {:?} score {}: {reason:#}",
+                        session.id, session.user_id, session.game, session.final_score
+                    );
+                }
+                Err(e) => error!("Failed to audit session {}. Reason: {:?}", session.id, e),
+            }
+        }
+
+        info!("Audited {audited} sessions so far");
+    }
+
+    info!("Audit finished. {audited} sessions, {flagged} flagged");
+
+    Ok(())
+}
+
+async fn audit_session(session: &GameSession, conn: &mut AsyncPgConnection) -> Result<Verdict> {
+    let result = match session.game {
+        GameType::Tetris => {
+            let snapshots = TetrisSnapshot::get_by_session_id(&session.id, conn).await?;
+            let placements_recorded = snapshots.iter().all(|s| s.piece.is_some());
+
+            match session.seed {
+                Some(seed) if placements_recorded => audit_tetris(session, seed, &snapshots),
+                _ => audit_tetris_legacy(session, &snapshots)
+                    .map_err(|e| e.context("Legacy tetris checks failed")),
+            }
+        }
+        GameType::Two048 => {
+            let events = Two048MoveEvent::get_by_session_id(&session.id, conn).await?;
+
+            match session.seed {
+                Some(seed) => audit_two048(session, seed, &events),
+                None => audit_two048_legacy(session, &events)
+                    .map_err(|e| e.context("Legacy 2048 checks failed")),
+            }
+        }
+        GameType::Snake => {
+            let events = SnakeFoodEvent::get_by_session_id(&session.id, conn).await?;
+            audit_snake(session, &events)
+        }
+        GameType::Flappy => {
+            let events = FlappyScoreEvent::get_by_session_id(&session.id, conn).await?;
+            audit_flappy(session, &events)
+        }
+    };
+
+    Ok(result.map_or_else(Verdict::Flagged, |()| Verdict::Clean))
+}
+
+fn final_score_matches(session: &GameSession, last_points: i32) -> Result<()> {
+    if session.final_score != last_points {
+        return Err(anyhow!(
+            "Final score {} does not match the last event points {last_points}",
+            session.final_score
+        ));
+    }
+
+    Ok(())
+}
+
+fn audit_tetris(session: &GameSession, seed: i64, snapshots: &[TetrisSnapshot]) -> Result<()> {
+    let mut rng = SeededRng::new(seed as u64);
+    let mut engine = TetrisEngine::new(&mut rng);
+    let mut last_move = engine.initial_state(session.start_time);
+
+    for (index, snapshot) in snapshots.iter().enumerate() {
+        let (Some(piece), Some(rotation), Some(column), Some(drop_kind)) = (
+            snapshot.piece,
+            snapshot.rotation,
+            snapshot.column_index,
+            snapshot.drop_kind,
+        ) else {
+            return Err(anyhow!("Placement {index} is incomplete"));
+        };
+
+        let placement = TetrisPlacement {
+            timestamp: snapshot.timestamp,
+            prev_timestamp: snapshot.prev_timestamp,
+            piece,
+            rotation,
+            column,
+            drop_kind,
+        };
+
+        tetris_move_valid(&placement, &last_move, &engine)
+            .map_err(|e| anyhow!("Placement {index} invalid: {e}"))?;
+
+        let result = engine.place(
+            piece,
+            rotation,
+            column,
+            drop_kind,
+            last_move.level,
+            &mut rng,
+        )?;
+        let new_state = engine.state_after(&placement, &last_move, &result);
+
+        if new_state.points != snapshot.points || new_state.lines != snapshot.lines {
+            return Err(anyhow!(
+                "Placement {index} stored {} points {} lines, simulation gives {} points {} lines",
+                snapshot.points,
+                snapshot.lines,
+                new_state.points,
+                new_state.lines
+            ));
+        }
+
+        last_move = new_state;
+    }
+
+    final_score_matches(session, last_move.points)
+}
+
+/// Checks the score and line arithmetic of a tetris session stored without placements: every
+/// step follows the previous one, clears at most 4 lines and scores the line points of the level
+/// plus at most a board height of drop points.
+fn audit_tetris_legacy(session: &GameSession, snapshots: &[TetrisSnapshot]) -> Result<()> {
+    let max_drop_points = BOARD_HEIGHT as i32;
+    let mut last: Option<&TetrisSnapshot> = None;
+
+    for (index, snapshot) in snapshots.iter().enumerate() {
+        let (prev_points, prev_lines, prev_level) =
+            last.map_or((0, 0, 1), |last| (last.points, last.lines, last.level));
+
+        if snapshot.prev_points != prev_points
+            || snapshot.prev_lines != prev_lines
+            || snapshot.prev_level != prev_level
+        {
+            return Err(anyhow!(
+                "Snapshot {index} does not follow the previous state"
+            ));
+        }
+
+        if let Some(last) = last
+            && snapshot.prev_timestamp != last.timestamp
+        {
+            return Err(anyhow!(
+                "Snapshot {index} does not follow the previous timestamp"
+            ));
+        }
+
+        let time_difference = (snapshot.timestamp - snapshot.prev_timestamp).num_milliseconds();
+        if time_difference < 0 || (time_difference != 0 && time_difference < MIN_TIME) {
+            return Err(anyhow!(
+                "Snapshot {index} time difference {time_difference}ms is not valid"
+            ));
+        }
+
+        if snapshot.level != snapshot.lines / LEVEL_UP + 1 {
+            return Err(anyhow!(
+                "Snapshot {index} level {} is not valid for lines {}",
+                snapshot.level,
+                snapshot.lines
+            ));
+        }
+
+        let lines_cleared = snapshot.lines - snapshot.prev_lines;
+        if !(0..=4).contains(&lines_cleared) {
+            return Err(anyhow!("Snapshot {index} cleared {lines_cleared} lines"));
+        }
+
+        let points = snapshot.points - snapshot.prev_points;
+        let line_points = POINTS_PER_LINE[lines_cleared as usize] * snapshot.prev_level;
+
+        if points < line_points || points > line_points + max_drop_points {
+            return Err(anyhow!(
+                "Snapshot {index} scored {points} points for {lines_cleared} lines, expected {line_points} to {}",
+                line_points + max_drop_points
+            ));
+        }
+
+        last = Some(snapshot);
+    }
+
+    final_score_matches(session, last.map_or(0, |last| last.points))
+}
+
+fn audit_two048(session: &GameSession, seed: i64, events: &[Two048MoveEvent]) -> Result<()> {
+    let mut rng = SeededRng::new(seed as u64);
+    let mut last_move = two048_initial_state(session.start_time, &mut rng);
+
+    for (index, event) in events.iter().enumerate() {
+        let data = Two048Move {
+            timestamp: event.timestamp,
+            prev_timestamp: event.prev_timestamp,
+            direction: event.direction,
+        };
+
+        two048_move_valid(&data, &last_move).map_err(|e| anyhow!("Move {index} invalid: {e}"))?;
+
+        let new_state = two048_next_state(&data, &last_move, &mut rng);
+
+        if new_state.points != event.points {
+            return Err(anyhow!(
+                "Move {index} stored {} points, simulation gives {}",
+                event.points,
+                new_state.points
+            ));
+        }
+
+        let spawn = new_state
+            .new_tile
+            .map(|tile| (tile.row as i32, tile.col as i32, tile.value));
+        let stored_spawn = match (event.spawn_row, event.spawn_col, event.spawn_value) {
+            (Some(row), Some(col), Some(value)) => Some((row, col, value)),
+            _ => None,
+        };
+
+        if spawn != stored_spawn {
+            return Err(anyhow!(
+                "Move {index} stored spawn {stored_spawn:?}, simulation gives {spawn:?}"
+            ));
+        }
+
+        last_move = new_state;
+    }
+
+    final_score_matches(session, last_move.points)
+}
+
+/// Checks the score and tile arithmetic of a 2048 session stored without a seed: every move
+/// follows the previous one, the highest tile grows at most one step and the points never
+/// exceed what the highest tile is worth.
+fn audit_two048_legacy(session: &GameSession, events: &[Two048MoveEvent]) -> Result<()> {
+    let mut last: Option<&Two048MoveEvent> = None;
+
+    for (index, event) in events.iter().enumerate() {
+        let (prev_points, prev_highest) =
+            last.map_or((0, 0), |last| (last.points, last.highest_number));
+
+        if event.prev_points != prev_points || event.prev_highest_number != prev_highest {
+            return Err(anyhow!("Move {index} does not follow the previous state"));
+        }
+
+        if let Some(last) = last
+            && event.prev_timestamp != last.timestamp
+        {
+            return Err(anyhow!(
+                "Move {index} does not follow the previous timestamp"
+            ));
+        }
+
+        if event.timestamp < event.prev_timestamp {
+            return Err(anyhow!("Move {index} timestamp moved backward"));
+        }
+
+        let tile_index = |tile: i32| VALID_TILES.iter().position(|valid| *valid == tile);
+
+        let Some(highest) = tile_index(event.highest_number) else {
+            return Err(anyhow!(
+                "Move {index} highest tile {} is not valid",
+                event.highest_number
+            ));
+        };
+
+        // The first move starts from 2s and 4s so it can make an 8 at most
+        let allowed = tile_index(event.prev_highest_number)
+            .map_or(0..=2, |prev_highest| prev_highest..=prev_highest + 1);
+
+        if !allowed.contains(&highest) {
+            return Err(anyhow!(
+                "Move {index} highest tile went from {} to {}",
+                event.prev_highest_number,
+                event.highest_number
+            ));
+        }
+
+        if event.points < event.prev_points {
+            return Err(anyhow!("Move {index} points went down"));
+        }
+
+        let max_points = expected_score(event.highest_number);
+        if event.points > max_points {
+            return Err(anyhow!(
+                "Move {index} has {} points, highest tile {} is worth {max_points}",
+                event.points,
+                event.highest_number
+            ));
+        }
+
+        last = Some(event);
+    }
+
+    final_score_matches(session, last.map_or(0, |last| last.points))
+}
+
+fn audit_snake(session: &GameSession, events: &[SnakeFoodEvent]) -> Result<()> {
+    let mut last_event: Option<SnakeFoodEvent> = None;
+
+    for (index, event) in events.iter().enumerate() {
+        snake_move_valid(&SnakeData::from(event), &last_event)
+            .map_err(|e| anyhow!("Event {index} invalid: {e}"))?;
+
+        last_event = Some(event.clone());
+    }
+
+    final_score_matches(session, last_event.map_or(0, |e| e.points))
+}
+
+fn audit_flappy(session: &GameSession, events: &[FlappyScoreEvent]) -> Result<()> {
+    let mut last_event: Option<FlappyScoreEvent> = None;
+
+    for (index, event) in events.iter().enumerate() {
+        flappy_move_valid(&FlappyData::from(event), &last_event)
+            .map_err(|e| anyhow!("Event {index} invalid: {e}"))?;
+
+        last_event = Some(event.clone());
+    }
+
+    final_score_matches(session, last_event.map_or(0, |e| e.points))
+}
@@ -5,8 +5,7 @@ use actix_web::{Error, HttpRequest, HttpResponse, error};
 use anyhow::anyhow;
 use base64::Engine;
 use base64::engine::general_purpose::STANDARD;
-use dashmap::DashMap;
-use db::models::{Task, TaskCompletion, TaskType, User};
+use db::models::{PointSource, Task, TaskCompletion, TaskType, User};
 use diesel_async::AsyncPgConnection;
 use diesel_async::pooled_connection::bb8::Pool;
 use futures_util::StreamExt;
@@ -15,19 +14,19 @@ use redis::aio::ConnectionManager;
 use reqwest::Client;
 use serde::Deserialize;
 use std::path::Path;
-use std::sync::Arc;
 use std::time::Duration;
 use tokio::task::spawn;
 use tokio::time::sleep;
 
-use crate::auth::CodeVerifier;
 use crate::ws::delete_old_photo;
 use crate::ws::jwt::validate_token;
+use crate::ws::models::load_replay;
 use crate::ws::redis_ops::{
-    USER_KEY, USER_TASK_KEY, get_task_details, mark_task_completed, update_user_photo,
+    USER_KEY, USER_TASK_KEY, get_code_verifier, get_task_details, mark_task_completed,
+    update_user_photo,
 };
 use crate::ws::server::{ConnId, Server, ServerInterface};
-use crate::{IMAGEKIT_PRIVATE, UserIpAgent};
+use crate::{IMAGEKIT_PRIVATE, KEY_RING, UserIpAgent, client_ip};
 
 const MAX_IMAGE_SIZE: usize = 5 * 1024 * 1024;
 
@@ -54,33 +53,21 @@ pub async fn upload_avatar(
         .unwrap_or_default()
         .to_string();
 
-    let forwarded_ip = req
-        .headers()
-        .get("X-Forwarded-For")
-        .map(|v| v.to_str().unwrap_or_default())
-        .unwrap_or_default()
-        .split(',')
-        .next()
-        .unwrap_or("")
-        .trim()
-        .to_string();
-
-    let ip = if forwarded_ip.is_empty() {
-        req.peer_addr()
-            .map(|addr| addr.ip().to_string())
-            .unwrap_or_default()
-    } else {
-        forwarded_ip
-    };
+    let ip = client_ip(&req);
 
     let user_ip_agent = UserIpAgent { ip, user_agent };
 
-    let claims = validate_token(&token, &user_ip_agent).map_err(|e| {
-        error!("Failed to validate token: {}", e);
-        error::ErrorUnauthorized("Invalid or expired token. Please reload the site and try again")
-    })?;
+    let mut redis = redis_conn.as_ref().clone();
+    let claims = validate_token(&mut redis, &token, &user_ip_agent)
+        .await
+        .map_err(|e| {
+            error!("Failed to validate token: {}", e);
+            error::ErrorUnauthorized(
+                "Invalid or expired token. Please reload the site and try again",
+            )
+        })?;
 
-    let user_id = claims.1.sub;
+    let user_id = claims.sub;
 
     let mut image_bytes = Vec::new();
     let mut filename = format!("{user_id}.jpg");
@@ -179,7 +166,7 @@ pub async fn upload_avatar(
     Ok(HttpResponse::Ok().json(serde_json::json!({ "url": resp.url })))
 }
 
-fn extract_token(headers: &HeaderMap) -> Option<String> {
+pub(crate) fn extract_token(headers: &HeaderMap) -> Option<String> {
     headers
         .get("Authorization")?
         .to_str()
@@ -194,7 +181,6 @@ pub async fn task_redirect(
     redis_conn: Data<ConnectionManager>,
     server: Data<Server>,
     handler: Data<ServerInterface>,
-    verifier_list: Data<Arc<DashMap<String, CodeVerifier>>>,
 ) -> Result<HttpResponse, Error> {
     let Some(task_id) = query.get("task_id").and_then(|c| c.as_str()) else {
         return Err(error::ErrorBadRequest("Redirect URL invalid"));
@@ -204,7 +190,16 @@ pub async fn task_redirect(
         return Err(error::ErrorBadRequest("Redirect URL invalid"));
     };
 
-    let Some(code_verifier) = verifier_list.get(state) else {
+    let mut redis_conn = redis_conn.as_ref().clone();
+
+    let code_verifier = get_code_verifier(&mut redis_conn, state)
+        .await
+        .map_err(|e| {
+            error!("Failed to get code verifier: {:?}", e);
+            error::ErrorInternalServerError("Internal server error")
+        })?;
+
+    let Some(code_verifier) = code_verifier else {
         return Err(error::ErrorBadRequest(
             "This link has expired. Please go back, reload the page and try again",
         ));
@@ -212,8 +207,6 @@ pub async fn task_redirect(
 
     let conn_id = code_verifier.conn_id;
 
-    let mut redis_conn = redis_conn.as_ref().clone();
-
     let Ok(task_details) = get_task_details(&mut redis_conn, task_id).await else {
         return Err(error::ErrorBadRequest(
             "The task link has expired. Please go back, reload the page and try again",
@@ -239,6 +232,35 @@ pub async fn task_redirect(
         .finish())
 }
 
+pub async fn get_replay(
+    id: web::Path<String>,
+    conn: Data<Pool<AsyncPgConnection>>,
+) -> Result<HttpResponse, Error> {
+    let id = id.into_inner();
+
+    let mut conn = conn.get().await.map_err(|e| {
+        error!("Failed to get db connection: {}", e);
+        error::ErrorInternalServerError("Internal server error")
+    })?;
+
+    let replay = load_replay(&id, &mut conn).await.map_err(|e| {
+        error!("Failed to load replay {id}: {:?}", e);
+        error::ErrorInternalServerError("Internal server error")
+    })?;
+
+    match replay {
+        Some(replay) => Ok(HttpResponse::Ok().json(replay)),
+        None => Err(error::ErrorNotFound("Replay not found")),
+    }
+}
+
+/// Public keys our access tokens are signed with, for services verifying them on their own
+pub async fn jwks() -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header(("Cache-Control", "public, max-age=300"))
+        .json(KEY_RING.get().unwrap().jwks())
+}
+
 async fn check_task_completion(
     conn_id: ConnId,
     mut redis_conn: ConnectionManager,
@@ -261,7 +283,10 @@ async fn check_task_completion(
     let proof_required = task.proof_required();
 
     if mark_as_complete {
-        let Some(user) = server.logged_in.get(&conn_id) else {
+        let mut server = server.as_ref().clone();
+
+        // The socket may be held by another instance
+        let Some(user) = server.conn_user(conn_id).await? else {
             return Err(anyhow!("{conn_id} not logged in"));
         };
         let mut conn = conn.get().await?;
@@ -276,15 +301,19 @@ async fn check_task_completion(
                 .await?;
 
             if !proof_required {
-                let mut server = server.as_ref().clone();
                 server
-                    .increase_point(task.reward_point, &user, false)
+                    .increase_point(
+                        task.reward_point,
+                        &user,
+                        PointSource::Task,
+                        Some(task.id.as_str()),
+                    )
                     .await?;
                 mark_task_completed(&mut redis_conn, &user_task_key, &task.id).await?;
             }
 
             sleep(Duration::from_secs(5)).await;
-            handler.tasks(conn_id);
+            handler.push_tasks(conn_id);
         }
     }
     Ok(())
@@ -0,0 +1,95 @@
+pub mod admin;
+pub mod auth;
+pub mod endpoints;
+pub mod ws;
+
+use actix_web::HttpRequest;
+use sha2::{Digest, Sha256};
+use std::sync::atomic::AtomicBool;
+use std::sync::{LazyLock, OnceLock};
+use ulid::Ulid;
+
+use crate::ws::jwt::TokenBinding;
+use crate::ws::keyring::KeyRing;
+
+pub static KEY_RING: OnceLock<KeyRing> = OnceLock::new();
+pub static TOKEN_BINDING: OnceLock<TokenBinding> = OnceLock::new();
+pub static REDIS_URL: OnceLock<String> = OnceLock::new();
+
+pub static IMAGEKIT_PUBLIC: OnceLock<String> = OnceLock::new();
+pub static IMAGEKIT_PRIVATE: OnceLock<String> = OnceLock::new();
+pub static IMAGEKIT_URL: OnceLock<String> = OnceLock::new();
+
+pub static DISCORD_CLIENT_ID: OnceLock<String> = OnceLock::new();
+pub static DISCORD_CLIENT_SECRET: OnceLock<String> = OnceLock::new();
+pub static DISCORD_REDIRECT_URI: OnceLock<String> = OnceLock::new();
+pub static DISCORD_REDIRECT_FULL: OnceLock<String> = OnceLock::new();
+pub static DISCORD_TOKEN: OnceLock<String> = OnceLock::new();
+
+pub static TELEGRAM_REDIRECT: OnceLock<String> = OnceLock::new();
+pub static TELEGRAM_TOKEN: OnceLock<String> = OnceLock::new();
+
+pub static BACKEND_URL: OnceLock<String> = OnceLock::new();
+
+/// Site the wallet sign in messages are issued for. Wallets compare its domain with the page
+/// asking for the signature.
+pub static SIGN_IN_URI: OnceLock<String> = OnceLock::new();
+
+/// Bearer token of the admin API. The admin API is closed when it is not set.
+pub static ADMIN_TOKEN: OnceLock<String> = OnceLock::new();
+
+/// Discord webhook for alerts. Alerts are only logged when it is not set.
+pub static ALERT_WEBHOOK: OnceLock<String> = OnceLock::new();
+
+/// Proxies in front of the server, each appending the address it got the request from to
+/// `X-Forwarded-For`. Without any the peer address is the client.
+pub static TRUSTED_PROXIES: OnceLock<usize> = OnceLock::new();
+
+/// Identifies this server process among the replicas sharing the same redis
+pub static INSTANCE_ID: LazyLock<String> = LazyLock::new(|| Ulid::new().to_string());
+
+/// Set once the process started shutting down. New sockets and commands are refused from then on.
+pub static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Debug)]
+pub struct UserIpAgent {
+    pub ip: String,
+    pub user_agent: String,
+}
+
+impl UserIpAgent {
+    /// Identifies the device without the IP so it survives network changes
+    #[must_use]
+    pub fn device_fingerprint(&self) -> String {
+        hex::encode(Sha256::digest(self.user_agent.as_bytes()))
+    }
+}
+
+/// Address of the client that sent the request. Only the `X-Forwarded-For` hops added by the
+/// trusted proxies are read, anything before them may be forged by the client.
+#[must_use]
+pub fn client_ip(req: &HttpRequest) -> String {
+    let peer = req
+        .peer_addr()
+        .map_or_else(|| "unknown".to_string(), |addr| addr.ip().to_string());
+
+    let trusted = TRUSTED_PROXIES.get().copied().unwrap_or_default();
+    if trusted == 0 {
+        return peer;
+    }
+
+    let hops: Vec<&str> = req
+        .headers()
+        .get_all("X-Forwarded-For")
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .filter(|hop| !hop.is_empty())
+        .collect();
+
+    // The outermost proxy appends the client, the ones after it append the proxy before them
+    hops.len()
+        .checked_sub(trusted)
+        .and_then(|i| hops.get(i))
+        .map_or(peer, |hop| (*hop).to_string())
+}
@@ -1,15 +1,10 @@
-mod auth;
-mod endpoints;
-mod ws;
-
 use actix_cors::Cors;
 use actix_files::Files;
 use actix_web::dev;
-use actix_web::web::{Data, Json, Path};
+use actix_web::web::{Data, Json, Path, Query};
 use actix_web::{App, Error, HttpRequest, HttpResponse, HttpServer, http, web};
 use app::App;
 use chrono::{Days, Utc};
-use dashmap::DashMap;
 use db::{get_connection as get_db_connection, get_redis_connection};
 use dev::Service;
 use leptos::config::get_configuration;
@@ -18,9 +13,13 @@ use leptos_actix::{LeptosRoutes, generate_route_list};
 use leptos_meta::MetaTags;
 use log::{LevelFilter, error, info};
 use reqwest::Client;
+use serde::Deserialize;
 use std::env::var;
-use std::sync::{Arc, OnceLock};
+use std::sync::atomic::Ordering;
 use std::time::Duration;
+use tokio::select;
+use tokio::signal::ctrl_c;
+use tokio::signal::unix::{SignalKind, signal};
 use tokio::task::{spawn, spawn_local};
 use tokio::time::sleep;
 use vial_shared::CreateSecretRequest;
@@ -29,27 +28,23 @@ use vial_srv::db::{Handler, get_connection};
 use vial_srv::errors::ServerError;
 use web::{Payload, resource};
 
-use crate::auth::{clean_up_verifier_code, discord_callback};
-use crate::endpoints::{task_redirect, upload_avatar};
-use crate::ws::server::{Server, ServerInterface, handler};
-
-pub static JWT_SECRET: OnceLock<String> = OnceLock::new();
-pub static REDIS_URL: OnceLock<String> = OnceLock::new();
-
-pub static IMAGEKIT_PUBLIC: OnceLock<String> = OnceLock::new();
-pub static IMAGEKIT_PRIVATE: OnceLock<String> = OnceLock::new();
-pub static IMAGEKIT_URL: OnceLock<String> = OnceLock::new();
-
-pub static DISCORD_CLIENT_ID: OnceLock<String> = OnceLock::new();
-pub static DISCORD_CLIENT_SECRET: OnceLock<String> = OnceLock::new();
-pub static DISCORD_REDIRECT_URI: OnceLock<String> = OnceLock::new();
-pub static DISCORD_REDIRECT_FULL: OnceLock<String> = OnceLock::new();
-pub static DISCORD_TOKEN: OnceLock<String> = OnceLock::new();
-
-pub static TELEGRAM_REDIRECT: OnceLock<String> = OnceLock::new();
-pub static TELEGRAM_TOKEN: OnceLock<String> = OnceLock::new();
-
-pub static BACKEND_URL: OnceLock<String> = OnceLock::new();
+use server::admin::{
+    adjust_points, create_season, create_task, deactivate_task, disconnect_user, edit_task,
+    leaderboard_archive, list_seasons, list_tasks, lookup_user, set_user_status,
+    user_point_history, user_sessions, user_task_completions,
+};
+use server::auth::discord_callback;
+use server::endpoints::{get_replay, jwks, task_redirect, upload_avatar};
+use server::ws::keyring::KeyRing;
+use server::ws::models::Encoding;
+use server::ws::rate_limit::RateLimits;
+use server::ws::server::{Server, ServerInterface, handler};
+use server::{
+    ADMIN_TOKEN, ALERT_WEBHOOK, BACKEND_URL, DISCORD_CLIENT_ID, DISCORD_CLIENT_SECRET,
+    DISCORD_REDIRECT_FULL, DISCORD_REDIRECT_URI, DISCORD_TOKEN, IMAGEKIT_PRIVATE, IMAGEKIT_PUBLIC,
+    IMAGEKIT_URL, KEY_RING, REDIS_URL, SHUTTING_DOWN, SIGN_IN_URI, TELEGRAM_REDIRECT,
+    TELEGRAM_TOKEN, TOKEN_BINDING, TRUSTED_PROXIES, UserIpAgent, client_ip,
+};
 
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
@@ -84,7 +79,7 @@ async fn main() -> std::io::Result<()> {
 
     let database_url_tbd = var("DATABASE_URL_TBD").expect("DATABASE_URL_TBD must be set");
 
-    let jwt_secret = var("JWT_SECRET").expect("JWT_SECRET must be set");
+    let key_ring = KeyRing::from_env().expect("JWT keys must be valid");
 
     let redis_url = var("REDIS_URL").expect("REDIS_URL must be set");
 
@@ -111,9 +106,12 @@ async fn main() -> std::io::Result<()> {
 
     let backend_url = var("BACKEND_URL").expect("BACKEND_URL must be set");
 
-    JWT_SECRET
-        .set(jwt_secret)
-        .expect("JWT_SECRET must be set only once");
+    let sign_in_uri = var("SIGN_IN_URI").unwrap_or_else(|_| backend_url.clone());
+
+    assert!(
+        KEY_RING.set(key_ring).is_ok(),
+        "KEY_RING must be set only once"
+    );
 
     REDIS_URL
         .set(redis_url.clone())
@@ -159,27 +157,66 @@ async fn main() -> std::io::Result<()> {
         .set(backend_url)
         .expect("BACKEND_URL must be set only once");
 
+    SIGN_IN_URI
+        .set(sign_in_uri)
+        .expect("SIGN_IN_URI must be set only once");
+
     DISCORD_TOKEN
         .set(discord_token)
         .expect("DISCORD_TOKEN must be set only once");
 
-    let verifier_list = Arc::new(DashMap::new());
+    if let Ok(admin_token) = var("ADMIN_TOKEN") {
+        ADMIN_TOKEN
+            .set(admin_token)
+            .expect("ADMIN_TOKEN must be set only once");
+    }
 
-    spawn(clean_up_verifier_code(verifier_list.clone()));
+    if let Ok(token_binding) = var("TOKEN_BINDING") {
+        TOKEN_BINDING
+            .set(
+                token_binding
+                    .parse()
+                    .expect("TOKEN_BINDING must be ip or device"),
+            )
+            .expect("TOKEN_BINDING must be set only once");
+    }
+
+    if let Ok(alert_webhook) = var("ALERT_WEBHOOK") {
+        ALERT_WEBHOOK
+            .set(alert_webhook)
+            .expect("ALERT_WEBHOOK must be set only once");
+    }
+
+    let trusted_proxies = match var("TRUSTED_PROXIES") {
+        Ok(count) => count
+            .parse()
+            .expect("TRUSTED_PROXIES must be a number of proxies"),
+        Err(_) => 0,
+    };
+
+    TRUSTED_PROXIES
+        .set(trusted_proxies)
+        .expect("TRUSTED_PROXIES must be set only once");
 
     let pool = get_db_connection(&database_url_tbd).await;
     let redis_conn = get_redis_connection(&redis_url).await;
 
-    let (server, handler, cmd_rx) =
-        Server::new(pool.clone(), redis_conn.clone(), verifier_list.clone());
+    let rate_limits = match var("RATE_LIMITS") {
+        Ok(overrides) => RateLimits::default()
+            .with_overrides(&overrides)
+            .expect("RATE_LIMITS must be valid"),
+        Err(_) => RateLimits::default(),
+    };
+
+    let (server, handler, cmd_rx) = Server::new(pool.clone(), redis_conn.clone(), rate_limits);
 
     let server_clone = server.clone();
+    let shutdown_server = server.clone();
     spawn(server.run(cmd_rx));
 
-    HttpServer::new(move || {
+    let http_server = HttpServer::new(move || {
         let server_clone = server_clone.clone();
         let handler_clone = handler.clone();
-        let verifier_list = verifier_list.clone();
         let pool = pool.clone();
         let redis_conn = redis_conn.clone();
 
@@ -251,12 +288,33 @@ async fn main() -> std::io::Result<()> {
             .app_data(web::JsonConfig::default().limit(config.get_max_size_verified()))
             .app_data(Data::new(handler_clone))
             .app_data(Data::new(server_clone))
-            .app_data(Data::new(verifier_list))
             .app_data(Data::new(pool))
             .app_data(Data::new(redis_conn))
             .service(resource("/ws").route(web::get().to(start_ws)))
             .service(resource("/auth/discord").route(web::get().to(discord_callback)))
             .service(resource("/redirect").route(web::get().to(task_redirect)))
+            .service(resource("/api/replays/{id}").route(web::get().to(get_replay)))
+            .service(resource("/.well-known/jwks.json").route(web::get().to(jwks)))
+            .service(
+                web::scope("/admin")
+                    .route("/tasks", web::get().to(list_tasks))
+                    .route("/tasks", web::post().to(create_task))
+                    .route("/tasks/{id}", web::put().to(edit_task))
+                    .route("/tasks/{id}/deactivate", web::post().to(deactivate_task))
+                    .route("/users", web::get().to(lookup_user))
+                    .route("/users/{id}/sessions", web::get().to(user_sessions))
+                    .route("/users/{id}/tasks", web::get().to(user_task_completions))
+                    .route("/users/{id}/points", web::get().to(user_point_history))
+                    .route("/users/{id}/points", web::post().to(adjust_points))
+                    .route("/users/{id}/status", web::post().to(set_user_status))
+                    .route("/users/{id}/disconnect", web::post().to(disconnect_user))
+                    .route("/seasons", web::get().to(list_seasons))
+                    .route("/seasons", web::post().to(create_season))
+                    .route(
+                        "/archives/{window}/{period}",
+                        web::get().to(leaderboard_archive),
+                    ),
+            )
             .service(
                 web::scope("/upload-avatar")
                     .wrap(cors_conf)
@@ -298,8 +356,27 @@ async fn main() -> std::io::Result<()> {
             .app_data(web::Data::new(leptos_options.to_owned()))
     })
     .bind(&addr)?
-    .run()
-    .await
+    .disable_signals()
+    .run();
+
+    spawn(shutdown_on_signal(shutdown_server, http_server.handle()));
+
+    http_server.await
+}
+
+/// Waits for SIGTERM or Ctrl+C, saves the live state and then stops the HTTP server
+async fn shutdown_on_signal(server: Server, http_handle: dev::ServerHandle) {
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+
+    select! {
+        _ = terminate.recv() => {}
+        _ = ctrl_c() => {}
+    }
+
+    info!("Shutdown signal received");
+
+    server.shutdown().await;
+    http_handle.stop(true).await;
 }
 
 #[actix_web::get("favicon.ico")]
@@ -438,22 +515,26 @@ fn server_error_to_response(e: ServerError) -> HttpResponse {
     }
 }
 
-#[derive(Clone, Debug)]
-pub struct UserIpAgent {
-    pub ip: String,
-    pub user_agent: String,
+/// Query parameters of the WebSocket upgrade
+#[derive(Deserialize)]
+struct WsOptions {
+    #[serde(default)]
+    encoding: Encoding,
 }
 
 async fn start_ws(
     req: HttpRequest,
     stream: Payload,
     handler: Data<ServerInterface>,
+    options: Query<WsOptions>,
 ) -> Result<HttpResponse, Error> {
+    if SHUTTING_DOWN.load(Ordering::SeqCst) {
+        return Ok(HttpResponse::ServiceUnavailable().finish());
+    }
+
     let (response, session, msg_stream) = actix_ws::handle(&req, stream)?;
 
-    let ip_local = req
-        .peer_addr()
-        .map_or_else(|| "unknown".to_string(), |addr| addr.ip().to_string());
+    let ip = client_ip(&req);
 
     let user_agent = req
         .headers()
@@ -462,19 +543,6 @@ async fn start_ws(
         .unwrap_or_default()
         .to_string();
 
-    let forwarded_ip = req
-        .headers()
-        .get("X-Forwarded-For")
-        .map(|v| v.to_str().unwrap_or_default())
-        .unwrap_or_default()
-        .to_string();
-
-    let ip = if forwarded_ip.is_empty() {
-        ip_local
-    } else {
-        forwarded_ip
-    };
-
     let user_ip_agent = UserIpAgent { ip, user_agent };
 
     spawn_local(handler::handle_ws(
@@ -482,6 +550,7 @@ async fn start_ws(
         session,
         msg_stream,
         user_ip_agent,
+        options.encoding,
     ));
     Ok(response)
 }
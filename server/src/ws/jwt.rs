@@ -1,60 +1,170 @@
 use anyhow::{Result, anyhow};
 use chrono::{Duration, Utc};
 use jsonwebtoken::errors::ErrorKind;
-use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation, decode, encode};
+use jsonwebtoken::{Header, Validation, decode, decode_header, encode};
+use rand::RngExt as _;
+use rand::distr::Alphanumeric;
+use redis::aio::ConnectionManager;
+use sha2::{Digest, Sha256};
+use std::str::FromStr;
+use ulid::Ulid;
 
-use crate::{JWT_SECRET, UserIpAgent, ws::models::Claims};
+use crate::ws::models::{Claims, TokenPair};
+use crate::ws::redis_ops::{
+    RefreshSession, is_jti_revoked, store_refresh_token, take_refresh_token, track_jti,
+};
+use crate::{KEY_RING, TOKEN_BINDING, UserIpAgent};
 
-pub fn validate_token(token: &str, ip_agent: &UserIpAgent) -> Result<(Option<String>, Claims)> {
-    let token_data = decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(JWT_SECRET.get().unwrap().as_ref()),
-        &Validation::default(),
-    )
-    .map_err(|err| match *err.kind() {
-        ErrorKind::ExpiredSignature => anyhow!("JWT Token has expired"),
-        _ => anyhow!("Error validating token: {err}"),
-    })?;
+/// Access tokens are short lived, clients get a new one with their refresh token
+pub const ACCESS_TOKEN_TTL: Duration = Duration::minutes(15);
 
-    let claims = token_data.claims;
+/// Refresh tokens that are not used within this time expire
+const REFRESH_TOKEN_TTL: Duration = Duration::days(30);
+
+const REFRESH_TOKEN_LENGTH: usize = 48;
+
+/// What a token is bound to besides its signature
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TokenBinding {
+    /// The IP and the device must both match. Users are logged out when their IP changes.
+    #[default]
+    Ip,
+    /// Only the device fingerprint must match
+    Device,
+}
+
+impl FromStr for TokenBinding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "ip" => Ok(TokenBinding::Ip),
+            "device" => Ok(TokenBinding::Device),
+            _ => Err(anyhow!("Unknown token binding {s}, expected ip or device")),
+        }
+    }
+}
 
-    if claims.ip != ip_agent.ip || claims.user_agent != ip_agent.user_agent {
+/// Checks a token issued for `ip` and `device` against the current connection
+fn check_binding(ip: &str, device: &str, ip_agent: &UserIpAgent) -> Result<()> {
+    let binding = TOKEN_BINDING.get().copied().unwrap_or_default();
+
+    if device != ip_agent.device_fingerprint() {
         return Err(anyhow!(
-            "IP or User-Agent mismatch. Expected: {}, {}, Got: {}, {}",
-            claims.ip,
-            claims.user_agent,
-            ip_agent.ip,
+            "Device mismatch. Expected: {device}, Got: {}",
             ip_agent.user_agent
         ));
     }
 
-    let now = Utc::now().timestamp() as usize;
-    let expiry_threshold = claims.exp.saturating_sub(now);
+    if binding == TokenBinding::Ip && ip != ip_agent.ip {
+        return Err(anyhow!("IP mismatch. Expected: {ip}, Got: {}", ip_agent.ip));
+    }
+
+    Ok(())
+}
+
+fn hash_refresh_token(refresh_token: &str) -> String {
+    hex::encode(Sha256::digest(refresh_token.as_bytes()))
+}
+
+pub async fn validate_token(
+    redis: &mut ConnectionManager,
+    token: &str,
+    ip_agent: &UserIpAgent,
+) -> Result<Claims> {
+    let kid = decode_header(token)?
+        .kid
+        .ok_or(anyhow!("JWT Token has no kid"))?;
+
+    let key = KEY_RING.get().unwrap().verifying_key(&kid).ok_or(anyhow!(
+        "JWT Token signed with unknown or expired key {kid}"
+    ))?;
+
+    // Pinning the algorithm of the key keeps a token from picking a weaker one
+    let token_data = decode::<Claims>(token, &key.decoding, &Validation::new(key.algorithm))
+        .map_err(|err| match *err.kind() {
+            ErrorKind::ExpiredSignature => anyhow!("JWT Token has expired"),
+            _ => anyhow!("Error validating token: {err}"),
+        })?;
 
-    // If expiry is in <1 day, renew token
-    if expiry_threshold < 24 * 3600 {
-        let new_token = issue_token(claims.sub.clone(), ip_agent)?;
-        return Ok((Some(new_token), claims));
+    let claims = token_data.claims;
+
+    check_binding(&claims.ip, &claims.device, ip_agent)?;
+
+    if is_jti_revoked(redis, &claims.jti).await? {
+        return Err(anyhow!("JWT Token {} has been revoked", claims.jti));
     }
 
-    Ok((None, claims))
+    Ok(claims)
 }
 
-pub fn issue_token(user_id: String, ip_agent: &UserIpAgent) -> Result<String> {
+/// Issues a new access token and refresh token for the user
+pub async fn issue_tokens(
+    redis: &mut ConnectionManager,
+    user_id: &str,
+    ip_agent: &UserIpAgent,
+) -> Result<TokenPair> {
     let now = Utc::now();
-    let exp = now + Duration::days(2);
+    let exp = now + ACCESS_TOKEN_TTL;
+    let device = ip_agent.device_fingerprint();
 
     let claims = Claims {
-        sub: user_id,
+        sub: user_id.to_string(),
+        jti: Ulid::new().to_string(),
         ip: ip_agent.ip.to_string(),
-        user_agent: ip_agent.user_agent.to_string(),
+        device: device.clone(),
         iat: now.timestamp() as usize,
         exp: exp.timestamp() as usize,
     };
 
-    Ok(encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(JWT_SECRET.get().unwrap().as_ref()),
-    )?)
+    let key = KEY_RING.get().unwrap().signing_key()?;
+    let mut header = Header::new(key.algorithm);
+    header.kid = Some(key.kid.clone());
+
+    let access_token = encode(&header, &claims, &key.encoding)?;
+
+    track_jti(redis, user_id, &claims.jti, exp.timestamp()).await?;
+
+    let refresh_token: String = rand::rng()
+        .sample_iter(&Alphanumeric)
+        .take(REFRESH_TOKEN_LENGTH)
+        .map(char::from)
+        .collect();
+
+    let session = RefreshSession {
+        user_id: user_id.to_string(),
+        ip: ip_agent.ip.to_string(),
+        device,
+    };
+
+    store_refresh_token(
+        redis,
+        &hash_refresh_token(&refresh_token),
+        &session,
+        REFRESH_TOKEN_TTL.num_seconds(),
+    )
+    .await?;
+
+    Ok(TokenPair {
+        access_token,
+        refresh_token,
+        expires_in: ACCESS_TOKEN_TTL.num_seconds(),
+    })
+}
+
+/// Exchanges a refresh token for a new pair. Returns the user id the token belonged to.
+pub async fn rotate_refresh_token(
+    redis: &mut ConnectionManager,
+    refresh_token: &str,
+    ip_agent: &UserIpAgent,
+) -> Result<(String, TokenPair)> {
+    let Some(session) = take_refresh_token(redis, &hash_refresh_token(refresh_token)).await? else {
+        return Err(anyhow!("Refresh token is unknown, expired or already used"));
+    };
+
+    check_binding(&session.ip, &session.device, ip_agent)?;
+
+    let tokens = issue_tokens(redis, &session.user_id, ip_agent).await?;
+
+    Ok((session.user_id, tokens))
 }
@@ -0,0 +1,177 @@
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Utc};
+use jsonwebtoken::jwk::{Jwk, JwkSet, PublicKeyUse};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use crate::ws::jwt::ACCESS_TOKEN_TTL;
+
+/// Key id given to `JWT_SECRET` when no key ring is configured
+const SECRET_KID: &str = "secret";
+
+/// A key as written in the key ring config. The key material is either inline in `key` or read
+/// from `key_file`: the secret for HS256, the private key PEM for RS256 and EdDSA.
+///
+/// A key starts signing at `not_before` and stops verifying at `expires_at`. It stops signing one
+/// access token lifetime earlier so no token outlives its key. Publishing the next key ahead of
+/// its `not_before` lets verifiers fetch it before the first token signed with it.
+#[derive(Deserialize)]
+struct KeyConfig {
+    kid: String,
+    algorithm: Algorithm,
+    key: Option<String>,
+    key_file: Option<PathBuf>,
+    not_before: Option<DateTime<Utc>>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+pub struct SigningKey {
+    pub kid: String,
+    pub algorithm: Algorithm,
+    pub encoding: EncodingKey,
+    pub decoding: DecodingKey,
+    /// Public half for the JWKS. Secrets are never published.
+    jwk: Option<Jwk>,
+    not_before: Option<DateTime<Utc>>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+impl SigningKey {
+    fn from_config(config: KeyConfig) -> Result<Self> {
+        let material = match (config.key, config.key_file) {
+            (Some(key), None) => key,
+            (None, Some(path)) => std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read key file {}", path.display()))?,
+            _ => {
+                return Err(anyhow!(
+                    "Key {} needs exactly one of key or key_file",
+                    config.kid
+                ));
+            }
+        };
+
+        let encoding = match config.algorithm {
+            Algorithm::HS256 => EncodingKey::from_secret(material.as_bytes()),
+            Algorithm::RS256 => EncodingKey::from_rsa_pem(material.as_bytes())?,
+            Algorithm::EdDSA => EncodingKey::from_ed_pem(material.as_bytes())?,
+            other => return Err(anyhow!("Key {} uses unsupported {other:?}", config.kid)),
+        };
+
+        let (decoding, jwk) = if config.algorithm == Algorithm::HS256 {
+            (DecodingKey::from_secret(material.as_bytes()), None)
+        } else {
+            let mut jwk = Jwk::from_encoding_key(&encoding, config.algorithm)
+                .with_context(|| format!("Failed to derive the public key of {}", config.kid))?;
+            jwk.common.key_id = Some(config.kid.clone());
+            jwk.common.public_key_use = Some(PublicKeyUse::Signature);
+
+            (DecodingKey::from_jwk(&jwk)?, Some(jwk))
+        };
+
+        Ok(Self {
+            kid: config.kid,
+            algorithm: config.algorithm,
+            encoding,
+            decoding,
+            jwk,
+            not_before: config.not_before,
+            expires_at: config.expires_at,
+        })
+    }
+
+    fn can_verify(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_none_or(|expires_at| now < expires_at)
+    }
+
+    fn can_sign(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at
+            .is_none_or(|expires_at| now < expires_at - ACCESS_TOKEN_TTL)
+            && self.not_before.is_none_or(|not_before| not_before <= now)
+    }
+}
+
+/// Every key tokens may be signed with, looked up by the `kid` header
+pub struct KeyRing {
+    keys: Vec<SigningKey>,
+}
+
+impl KeyRing {
+    /// Loads the keys from the JSON list in `JWT_KEYS_FILE` or `JWT_KEYS`. Falls back to a
+    /// single HS256 key from `JWT_SECRET` when neither is set.
+    pub fn from_env() -> Result<Self> {
+        let config = if let Ok(path) = std::env::var("JWT_KEYS_FILE") {
+            std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read JWT_KEYS_FILE {path}"))?
+        } else if let Ok(config) = std::env::var("JWT_KEYS") {
+            config
+        } else {
+            let secret = std::env::var("JWT_SECRET")
+                .context("One of JWT_KEYS_FILE, JWT_KEYS or JWT_SECRET must be set")?;
+
+            let key = SigningKey::from_config(KeyConfig {
+                kid: SECRET_KID.to_string(),
+                algorithm: Algorithm::HS256,
+                key: Some(secret),
+                key_file: None,
+                not_before: None,
+                expires_at: None,
+            })?;
+
+            return Ok(Self { keys: vec![key] });
+        };
+
+        let configs: Vec<KeyConfig> =
+            serde_json::from_str(&config).context("Invalid JWT key ring config")?;
+
+        let keys = configs
+            .into_iter()
+            .map(SigningKey::from_config)
+            .collect::<Result<Vec<_>>>()?;
+
+        if keys.is_empty() {
+            return Err(anyhow!("JWT key ring is empty"));
+        }
+
+        let mut kids = HashSet::new();
+        if let Some(key) = keys.iter().find(|key| !kids.insert(key.kid.as_str())) {
+            return Err(anyhow!("JWT key id {} is used more than once", key.kid));
+        }
+
+        Ok(Self { keys })
+    }
+
+    /// The key that signs new tokens: the one that most recently started signing
+    pub fn signing_key(&self) -> Result<&SigningKey> {
+        let now = Utc::now();
+
+        self.keys
+            .iter()
+            .filter(|key| key.can_sign(now))
+            .max_by_key(|key| key.not_before)
+            .ok_or(anyhow!("No JWT key is valid for signing right now"))
+    }
+
+    pub fn verifying_key(&self, kid: &str) -> Option<&SigningKey> {
+        let now = Utc::now();
+
+        self.keys
+            .iter()
+            .find(|key| key.kid == kid && key.can_verify(now))
+    }
+
+    /// Public keys other services can verify our tokens with
+    pub fn jwks(&self) -> JwkSet {
+        let now = Utc::now();
+
+        JwkSet {
+            keys: self
+                .keys
+                .iter()
+                .filter(|key| key.can_verify(now))
+                .filter_map(|key| key.jwk.clone())
+                .collect(),
+        }
+    }
+}
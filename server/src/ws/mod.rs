@@ -1,10 +1,14 @@
 mod hash_verifier;
 pub mod jwt;
-mod models;
+pub mod keyring;
+pub mod models;
+pub mod outbox;
+pub mod rate_limit;
 pub mod redis_ops;
 mod request_handlers;
 pub mod server;
+pub mod sign_in;
 mod utils;
-mod validator;
+pub mod validator;
 
 pub use utils::*;
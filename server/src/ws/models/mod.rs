@@ -1,9 +1,8 @@
-mod request;
-mod response;
+mod replay;
 mod sessions;
 mod shared;
 
-pub use request::*;
-pub use response::*;
+pub use self::shared::*;
+pub use ::shared::protocol::*;
+pub use replay::*;
 pub use sessions::*;
-pub use shared::*;
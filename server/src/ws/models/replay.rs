@@ -0,0 +1,79 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use db::models::{
+    FlappyScoreEvent, GameSession, GameType, SnakeFoodEvent, TetrisSnapshot, Two048MoveEvent,
+};
+use diesel_async::AsyncPgConnection;
+
+use crate::ws::models::{FlappyFrame, Replay, ReplayFrames, SnakeFrame, TetrisFrame, Two048Frame};
+
+/// Loads a committed session and its events. Returns `None` if the session does not exist.
+pub async fn load_replay(session_id: &str, conn: &mut AsyncPgConnection) -> Result<Option<Replay>> {
+    let Some(session) = GameSession::get_by_id(session_id, conn).await? else {
+        return Ok(None);
+    };
+
+    let start = session.start_time;
+    let offset = |timestamp: DateTime<Utc>| (timestamp - start).num_milliseconds();
+
+    let frames = match session.game {
+        GameType::Tetris => ReplayFrames::Tetris(
+            TetrisSnapshot::get_by_session_id(session_id, conn)
+                .await?
+                .into_iter()
+                .map(|e| {
+                    TetrisFrame(
+                        offset(e.timestamp),
+                        e.piece,
+                        e.rotation,
+                        e.column_index,
+                        e.drop_kind,
+                        e.points,
+                        e.lines,
+                    )
+                })
+                .collect(),
+        ),
+        GameType::Snake => ReplayFrames::Snake(
+            SnakeFoodEvent::get_by_session_id(session_id, conn)
+                .await?
+                .into_iter()
+                .map(|e| SnakeFrame(offset(e.timestamp), e.points, e.length))
+                .collect(),
+        ),
+        GameType::Two048 => ReplayFrames::Two048(
+            Two048MoveEvent::get_by_session_id(session_id, conn)
+                .await?
+                .into_iter()
+                .map(|e| {
+                    Two048Frame(
+                        offset(e.timestamp),
+                        e.direction,
+                        e.spawn_row,
+                        e.spawn_col,
+                        e.spawn_value,
+                        e.points,
+                    )
+                })
+                .collect(),
+        ),
+        GameType::Flappy => ReplayFrames::Flappy(
+            FlappyScoreEvent::get_by_session_id(session_id, conn)
+                .await?
+                .into_iter()
+                .map(|e| FlappyFrame(offset(e.timestamp), e.points, e.pipes))
+                .collect(),
+        ),
+    };
+
+    Ok(Some(Replay {
+        session_id: session.id,
+        user_id: session.user_id,
+        game_type: session.game,
+        start_time: session.start_time,
+        end_time: session.end_time,
+        final_score: session.final_score,
+        seed: session.seed,
+        frames,
+    }))
+}
@@ -1,19 +1,30 @@
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use chrono::{DateTime, Utc};
 use db::models::{
     FlappyScoreEvent, GameSession, GameType, SnakeFoodEvent, TetrisSnapshot, Two048MoveEvent,
 };
 use diesel_async::AsyncPgConnection;
 use log::error;
+use rand::{RngExt as _, rng};
+use serde::{Deserialize, Serialize};
 
-use crate::ws::models::Two048Data;
+use crate::ws::models::{TetrisData, TetrisPlacement, Two048Data, Two048Move};
+use crate::ws::validator::rng::SeededRng;
+use crate::ws::validator::tetris_engine::TetrisEngine;
+use crate::ws::validator::two048::{two048_initial_state, two048_next_state};
 
+/// Serializable so a session can be parked in redis while its user reconnects
+#[derive(Serialize, Deserialize)]
 pub struct GameInProgress {
     session: GameSession,
     snapshots: Vec<GameEvent>,
+    /// Server owned randomness for games where the server decides the outcome of a move
+    rng: Option<SeededRng>,
+    two048_start: Option<Two048Data>,
+    tetris: Option<TetrisEngine>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum GameEvent {
     Tetris(TetrisSnapshot),
     Snake(SnakeFoodEvent),
@@ -50,38 +61,58 @@ where
 
 impl GameInProgress {
     pub fn new_tetris(user_id: String, start_time: DateTime<Utc>) -> Self {
-        let session = GameSession::new(user_id, GameType::Tetris, start_time);
+        let seed: u64 = rng().random();
+        let session = GameSession::new(user_id, GameType::Tetris, start_time, Some(seed as i64));
+
+        let mut seeded_rng = SeededRng::new(seed);
+        let engine = TetrisEngine::new(&mut seeded_rng);
 
         Self {
             session,
             snapshots: Vec::new(),
+            rng: Some(seeded_rng),
+            two048_start: None,
+            tetris: Some(engine),
         }
     }
 
     pub fn new_snake(user_id: String, start_time: DateTime<Utc>) -> Self {
-        let session = GameSession::new(user_id, GameType::Snake, start_time);
+        let session = GameSession::new(user_id, GameType::Snake, start_time, None);
 
         Self {
             session,
             snapshots: Vec::new(),
+            rng: None,
+            two048_start: None,
+            tetris: None,
         }
     }
 
     pub fn new_two048(user_id: String, start_time: DateTime<Utc>) -> Self {
-        let session = GameSession::new(user_id, GameType::Two048, start_time);
+        let seed: u64 = rng().random();
+        let session = GameSession::new(user_id, GameType::Two048, start_time, Some(seed as i64));
+
+        let mut seeded_rng = SeededRng::new(seed);
+        let two048_start = two048_initial_state(start_time, &mut seeded_rng);
 
         Self {
             session,
             snapshots: Vec::new(),
+            rng: Some(seeded_rng),
+            two048_start: Some(two048_start),
+            tetris: None,
         }
     }
 
     pub fn new_flappy(user_id: String, start_time: DateTime<Utc>) -> Self {
-        let session = GameSession::new(user_id, GameType::Flappy, start_time);
+        let session = GameSession::new(user_id, GameType::Flappy, start_time, None);
 
         Self {
             session,
             snapshots: Vec::new(),
+            rng: None,
+            two048_start: None,
+            tetris: None,
         }
     }
 
@@ -123,9 +154,10 @@ impl GameInProgress {
             }
             GameType::Two048 => {
                 let snapshots = filter_events(self.snapshots, |e| match e {
-                    GameEvent::Two048(s) => Some(s.to_two048_move_event(
+                    GameEvent::Two048(s) => Some(Two048MoveEvent::from_data(
                         self.session.id.clone(),
                         self.session.user_id.clone(),
+                        &s,
                     )),
                     _ => None,
                 });
@@ -147,14 +179,27 @@ impl GameInProgress {
         &self.session.id
     }
 
+    pub fn get_game(&self) -> GameType {
+        self.session.game
+    }
+
+    pub fn get_user_id(&self) -> &str {
+        &self.session.user_id
+    }
+
     fn get_last_event(&self) -> Option<GameEvent> {
         self.snapshots.last().cloned()
     }
 
-    pub fn get_last_tetris(&self) -> Option<TetrisSnapshot> {
+    pub fn get_tetris_engine(&self) -> Option<&TetrisEngine> {
+        self.tetris.as_ref()
+    }
+
+    /// Returns the last tetris state or the empty board if no piece has been placed yet
+    pub fn get_last_tetris(&self) -> Option<TetrisData> {
         match self.get_last_event() {
-            Some(GameEvent::Tetris(e)) => Some(e),
-            None => None,
+            Some(GameEvent::Tetris(e)) => Some(self.tetris.as_ref()?.state_from_snapshot(&e)),
+            None => Some(self.tetris.as_ref()?.initial_state(self.session.start_time)),
             other => {
                 error!(
                     "Get last tetris was called but the state is not a tetris game. {:?} {other:?}",
@@ -165,6 +210,44 @@ impl GameInProgress {
         }
     }
 
+    /// Applies a validated placement, advancing the board and the session RNG
+    pub fn next_tetris(&mut self, data: &TetrisPlacement) -> Result<(TetrisData, TetrisSnapshot)> {
+        let last_move = self
+            .get_last_tetris()
+            .ok_or(anyhow!("No tetris state found for the session"))?;
+
+        let rng = self
+            .rng
+            .as_mut()
+            .ok_or(anyhow!("Tetris session has no seeded RNG"))?;
+
+        let engine = self
+            .tetris
+            .as_mut()
+            .ok_or(anyhow!("Tetris session has no board"))?;
+
+        let result = engine.place(
+            data.piece,
+            data.rotation,
+            data.column,
+            data.drop_kind,
+            last_move.level,
+            rng,
+        )?;
+
+        let new_state = engine.state_after(data, &last_move, &result);
+        let snapshot = TetrisSnapshot::from_data(
+            self.session.id.clone(),
+            self.session.user_id.clone(),
+            &new_state,
+            data,
+            result.line_points,
+            result.drop_points,
+        );
+
+        Ok((new_state, snapshot))
+    }
+
     pub fn get_last_snake(&self) -> Option<SnakeFoodEvent> {
         match self.get_last_event() {
             Some(GameEvent::Snake(e)) => Some(e),
@@ -179,10 +262,11 @@ impl GameInProgress {
         }
     }
 
+    /// Returns the last 2048 move or the starting board if no move has been made yet
     pub fn get_last_two048(&self) -> Option<Two048Data> {
         match self.get_last_event() {
             Some(GameEvent::Two048(e)) => Some(e),
-            None => None,
+            None => self.two048_start.clone(),
             other => {
                 error!(
                     "Get last two048 was called but the state is not a two048 game. {:?} {other:?}",
@@ -193,6 +277,20 @@ impl GameInProgress {
         }
     }
 
+    /// Computes the next 2048 state from a validated move, advancing the session RNG
+    pub fn next_two048(&mut self, data: &Two048Move) -> Result<Two048Data> {
+        let last_move = self
+            .get_last_two048()
+            .ok_or(anyhow!("No 2048 state found for the session"))?;
+
+        let rng = self
+            .rng
+            .as_mut()
+            .ok_or(anyhow!("2048 session has no seeded RNG"))?;
+
+        Ok(two048_next_state(data, &last_move, rng))
+    }
+
     pub fn get_last_flappy(&self) -> Option<FlappyScoreEvent> {
         match self.get_last_event() {
             Some(GameEvent::Flappy(e)) => Some(e),
@@ -0,0 +1,172 @@
+use db::models::GameType;
+use shared::protocol::{Encoding, WsResponse};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::select;
+use tokio::sync::Notify;
+use tokio::sync::mpsc::{self, Receiver, Sender, error::TrySendError};
+
+/// Messages a connection may have waiting before new ones are dropped
+const OUTBOX_CAPACITY: usize = 256;
+
+/// How long the queue may stay full before the client is treated as a slow consumer
+const SLOW_CONSUMER_GRACE: Duration = Duration::from_secs(15);
+
+/// The client stopped reading and should be disconnected
+#[derive(Debug)]
+pub struct SlowConsumer;
+
+/// A response encoded for the socket. Cheap to clone so one broadcast frame can be queued for
+/// many connections.
+#[derive(Clone, Debug)]
+pub enum Frame {
+    Text(Arc<str>),
+    Binary(Arc<[u8]>),
+}
+
+impl Frame {
+    #[must_use]
+    pub fn encode(response: &WsResponse, encoding: Encoding) -> Self {
+        match encoding {
+            Encoding::Json => Frame::Text(response.json().into()),
+            Encoding::MessagePack => Frame::Binary(response.msgpack().into()),
+        }
+    }
+}
+
+/// Sending half of a connection's outbound queue.
+///
+/// Responses go through a bounded queue. Leaderboard frames are kept apart with at most one
+/// pending frame per leaderboard, so a client that falls behind skips stale ones.
+#[derive(Clone, Debug)]
+pub struct Outbox {
+    encoding: Encoding,
+    queue: Sender<Frame>,
+    leaderboards: Arc<Mutex<HashMap<Option<GameType>, Frame>>>,
+    notify: Arc<Notify>,
+    full_since: Arc<Mutex<Option<Instant>>>,
+}
+
+#[derive(Debug)]
+pub struct OutboxReceiver {
+    queue: Receiver<Frame>,
+    leaderboards: Arc<Mutex<HashMap<Option<GameType>, Frame>>>,
+    notify: Arc<Notify>,
+}
+
+#[must_use]
+pub fn outbox(encoding: Encoding) -> (Outbox, OutboxReceiver) {
+    let (tx, rx) = mpsc::channel(OUTBOX_CAPACITY);
+    let leaderboards = Arc::new(Mutex::new(HashMap::new()));
+    let notify = Arc::new(Notify::new());
+
+    let outbox = Outbox {
+        encoding,
+        queue: tx,
+        leaderboards: leaderboards.clone(),
+        notify: notify.clone(),
+        full_since: Arc::new(Mutex::new(None)),
+    };
+
+    let receiver = OutboxReceiver {
+        queue: rx,
+        leaderboards,
+        notify,
+    };
+
+    (outbox, receiver)
+}
+
+impl Outbox {
+    #[must_use]
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Queues a pushed response. It is dropped if the queue is full, and an error is returned
+    /// once the queue has stayed full for too long.
+    pub fn send(&self, response: &WsResponse) -> Result<(), SlowConsumer> {
+        let mut full_since = self.full_since.lock().unwrap();
+
+        match self.queue.try_send(Frame::encode(response, self.encoding)) {
+            Ok(()) => {
+                *full_since = None;
+                Ok(())
+            }
+            Err(TrySendError::Full(_)) => {
+                let since = full_since.get_or_insert_with(Instant::now);
+
+                if since.elapsed() > SLOW_CONSUMER_GRACE {
+                    Err(SlowConsumer)
+                } else {
+                    Ok(())
+                }
+            }
+            // The socket is closing, disconnect takes care of the rest
+            Err(TrySendError::Closed(_)) => Ok(()),
+        }
+    }
+
+    /// Queues the reply to a request. Replies are never dropped: the client is waiting on it,
+    /// so a full queue makes it a slow consumer right away and it resyncs on reconnect.
+    pub fn send_reply(&self, response: &WsResponse) -> Result<(), SlowConsumer> {
+        let mut full_since = self.full_since.lock().unwrap();
+
+        match self.queue.try_send(Frame::encode(response, self.encoding)) {
+            Ok(()) => {
+                *full_since = None;
+                Ok(())
+            }
+            Err(TrySendError::Full(_)) => Err(SlowConsumer),
+            Err(TrySendError::Closed(_)) => Ok(()),
+        }
+    }
+
+    /// Queues a leaderboard delta. If the previous frame of the leaderboard has not been read
+    /// yet, both are replaced by `snapshot` as deltas can't be skipped.
+    pub fn send_leaderboard(&self, game: Option<GameType>, delta: &Frame, snapshot: &Frame) {
+        let mut leaderboards = self.leaderboards.lock().unwrap();
+
+        let frame = if leaderboards.contains_key(&game) {
+            snapshot
+        } else {
+            delta
+        };
+
+        leaderboards.insert(game, frame.clone());
+        self.notify.notify_one();
+    }
+}
+
+impl OutboxReceiver {
+    /// The next frame to write to the socket. `None` once the server dropped the connection.
+    pub async fn recv(&mut self) -> Option<Frame> {
+        loop {
+            select! {
+                biased;
+
+                message = self.queue.recv() => return message,
+                () = self.notify.notified() => {
+                    if let Some(frame) = self.take_leaderboard() {
+                        return Some(frame);
+                    }
+                }
+            }
+        }
+    }
+
+    fn take_leaderboard(&self) -> Option<Frame> {
+        let mut leaderboards = self.leaderboards.lock().unwrap();
+
+        let game = *leaderboards.keys().next()?;
+        let frame = leaderboards.remove(&game);
+
+        // Only one permit is stored, wake up again for the rest
+        if !leaderboards.is_empty() {
+            self.notify.notify_one();
+        }
+
+        frame
+    }
+}
@@ -0,0 +1,283 @@
+use anyhow::{Context, Result, anyhow};
+use dashmap::DashMap;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::ws::server::{ConnId, Work};
+
+/// Limit of any work without an entry of its own
+const DEFAULT_LIMIT: RateLimit = RateLimit::new(10.0, 2.0);
+
+/// A user may have a few tabs open, so their buckets are this many times larger than a
+/// connection's
+const USER_MULTIPLIER: f64 = 2.0;
+
+/// Many users can share an IP behind a NAT
+const IP_MULTIPLIER: f64 = 8.0;
+
+/// Rejections a connection may collect before it is disconnected
+const STRIKE_LIMIT: RateLimit = RateLimit::new(20.0, 0.2);
+
+/// Buckets untouched for this long are full again and can be dropped
+const IDLE_BUCKET: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimit {
+    pub burst: f64,
+    pub per_second: f64,
+}
+
+impl RateLimit {
+    #[must_use]
+    pub const fn new(burst: f64, per_second: f64) -> Self {
+        Self { burst, per_second }
+    }
+
+    fn scaled(self, multiplier: f64) -> Self {
+        Self::new(self.burst * multiplier, self.per_second * multiplier)
+    }
+}
+
+/// Token bucket limits for each kind of work
+#[derive(Clone, Debug)]
+pub struct RateLimits {
+    limits: HashMap<String, RateLimit>,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        let limits = [
+            ("Auth", RateLimit::new(5.0, 0.2)),
+            ("AuthToken", RateLimit::new(5.0, 0.2)),
+            ("Tetris", RateLimit::new(20.0, 10.0)),
+            ("Two048", RateLimit::new(20.0, 10.0)),
+            ("Snake", RateLimit::new(20.0, 10.0)),
+            ("Flappy", RateLimit::new(20.0, 10.0)),
+            ("LeaderboardIn", RateLimit::new(5.0, 0.5)),
+            ("LeaderboardPage", RateLimit::new(5.0, 1.0)),
+            ("LeaderboardAround", RateLimit::new(5.0, 1.0)),
+            ("WindowLeaderboard", RateLimit::new(5.0, 1.0)),
+            ("GetActivity", RateLimit::new(5.0, 1.0)),
+            ("GetReplay", RateLimit::new(5.0, 1.0)),
+            ("MeWithRankSocials", RateLimit::new(5.0, 0.5)),
+            ("SocialLinks", RateLimit::new(5.0, 0.5)),
+            ("Tasks", RateLimit::new(5.0, 0.5)),
+            ("CheckTask", RateLimit::new(3.0, 0.1)),
+            ("CheckReferralStatus", RateLimit::new(3.0, 0.1)),
+            ("UsernameUpdate", RateLimit::new(3.0, 0.1)),
+            ("Telegram", RateLimit::new(3.0, 0.1)),
+            ("BindWallet", RateLimit::new(3.0, 0.1)),
+            ("InvalidRequest", RateLimit::new(5.0, 0.5)),
+        ];
+
+        Self {
+            limits: limits
+                .into_iter()
+                .map(|(work, limit)| (work.to_string(), limit))
+                .collect(),
+        }
+    }
+}
+
+impl RateLimits {
+    /// Applies overrides in the form `Work=burst:per_second,...` on top of the defaults, e.g.
+    /// `CheckTask=3:0.1,LeaderboardIn=5:0.5`
+    pub fn with_overrides(mut self, overrides: &str) -> Result<Self> {
+        for entry in overrides
+            .split(',')
+            .map(str::trim)
+            .filter(|e| !e.is_empty())
+        {
+            let (work, limit) = entry
+                .split_once('=')
+                .ok_or(anyhow!("Rate limit {entry} is missing ="))?;
+            let (burst, per_second) = limit
+                .split_once(':')
+                .ok_or(anyhow!("Rate limit {entry} is missing :"))?;
+
+            if !Work::RATE_LIMIT_KEYS.contains(&work) {
+                return Err(anyhow!("Rate limit {entry} is for unknown work {work}"));
+            }
+
+            let burst: f64 = burst
+                .parse()
+                .with_context(|| format!("Invalid burst in {entry}"))?;
+            let per_second: f64 = per_second
+                .parse()
+                .with_context(|| format!("Invalid rate in {entry}"))?;
+
+            // A bucket holding less than a token never admits anything
+            if !burst.is_finite() || burst < 1.0 {
+                return Err(anyhow!(
+                    "Burst in {entry} must be a finite number of at least 1"
+                ));
+            }
+
+            if !per_second.is_finite() || per_second <= 0.0 {
+                return Err(anyhow!("Rate in {entry} must be a finite positive number"));
+            }
+
+            self.limits
+                .insert(work.to_string(), RateLimit::new(burst, per_second));
+        }
+
+        Ok(self)
+    }
+
+    fn get(&self, work: &str) -> RateLimit {
+        self.limits.get(work).copied().unwrap_or(DEFAULT_LIMIT)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(limit: RateLimit) -> Self {
+        Self {
+            tokens: limit.burst,
+            updated: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, limit: RateLimit) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * limit.per_second).min(limit.burst);
+        self.updated = now;
+    }
+
+    /// Time until a token is available
+    fn wait(&self, limit: RateLimit) -> Duration {
+        if limit.per_second <= 0.0 {
+            return Duration::MAX;
+        }
+
+        // Tiny rates give waits beyond what a duration can hold
+        Duration::try_from_secs_f64((1.0 - self.tokens).max(0.0) / limit.per_second)
+            .unwrap_or(Duration::MAX)
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+enum Scope {
+    Conn(ConnId),
+    User(String),
+    Ip(String),
+}
+
+/// What happened to a command that went through the limiter
+pub enum Verdict {
+    Allowed,
+    /// Rejected, the caller may retry after the duration
+    Limited(Duration),
+    /// Rejected too many times, the connection should be dropped
+    Abusive,
+}
+
+/// Token buckets per connection, user and IP for each kind of work.
+///
+/// State is local to this instance. A user spread over several instances gets the user
+/// limit on each of them.
+#[derive(Clone)]
+pub struct RateLimiter {
+    limits: Arc<RateLimits>,
+    buckets: Arc<DashMap<(Scope, &'static str), Bucket>>,
+    strikes: Arc<DashMap<ConnId, Bucket>>,
+    conn_ips: Arc<DashMap<ConnId, String>>,
+}
+
+impl RateLimiter {
+    #[must_use]
+    pub fn new(limits: RateLimits) -> Self {
+        Self {
+            limits: Arc::new(limits),
+            buckets: Arc::new(DashMap::new()),
+            strikes: Arc::new(DashMap::new()),
+            conn_ips: Arc::new(DashMap::new()),
+        }
+    }
+
+    pub fn track_connection(&self, conn_id: ConnId, ip: String) {
+        self.conn_ips.insert(conn_id, ip);
+    }
+
+    pub fn forget_connection(&self, conn_id: ConnId) {
+        self.conn_ips.remove(&conn_id);
+        self.strikes.remove(&conn_id);
+        self.buckets
+            .retain(|(scope, _), _| *scope != Scope::Conn(conn_id));
+    }
+
+    /// Takes a token for `work` from the connection, user and IP buckets. Nothing is taken
+    /// unless all of them have one to spare.
+    pub fn check(&self, conn_id: ConnId, user_id: Option<&str>, work: &'static str) -> Verdict {
+        let limit = self.limits.get(work);
+
+        let mut scopes = vec![(Scope::Conn(conn_id), limit)];
+
+        if let Some(user_id) = user_id {
+            scopes.push((
+                Scope::User(user_id.to_string()),
+                limit.scaled(USER_MULTIPLIER),
+            ));
+        }
+
+        if let Some(ip) = self.conn_ips.get(&conn_id) {
+            scopes.push((Scope::Ip(ip.clone()), limit.scaled(IP_MULTIPLIER)));
+        }
+
+        let mut wait = Duration::ZERO;
+
+        for (scope, limit) in &scopes {
+            let mut bucket = self
+                .buckets
+                .entry((scope.clone(), work))
+                .or_insert_with(|| Bucket::full(*limit));
+
+            bucket.refill(*limit);
+
+            if bucket.tokens < 1.0 {
+                wait = wait.max(bucket.wait(*limit));
+            }
+        }
+
+        if wait.is_zero() {
+            for (scope, _) in scopes {
+                if let Some(mut bucket) = self.buckets.get_mut(&(scope, work)) {
+                    bucket.tokens -= 1.0;
+                }
+            }
+
+            return Verdict::Allowed;
+        }
+
+        let mut strikes = self
+            .strikes
+            .entry(conn_id)
+            .or_insert_with(|| Bucket::full(STRIKE_LIMIT));
+
+        strikes.refill(STRIKE_LIMIT);
+
+        if strikes.tokens < 1.0 {
+            return Verdict::Abusive;
+        }
+
+        strikes.tokens -= 1.0;
+
+        Verdict::Limited(wait)
+    }
+
+    /// Drops buckets that have been idle long enough to be full again
+    pub fn prune(&self) {
+        self.buckets
+            .retain(|_, bucket| bucket.updated.elapsed() < IDLE_BUCKET);
+        self.strikes
+            .retain(|_, bucket| bucket.updated.elapsed() < IDLE_BUCKET);
+    }
+}
@@ -0,0 +1,86 @@
+use anyhow::{Context, Result};
+use chrono::Utc;
+use db::models::GameType;
+use redis::AsyncCommands;
+use redis::aio::ConnectionManager;
+
+use crate::ws::models::GameInProgress;
+
+pub const SESSION_CHECKPOINT_KEY: &str = "session_checkpoint";
+pub const SESSION_CHECKPOINTS_KEY: &str = "session_checkpoints";
+
+/// Seconds a disconnected user has to reattach to an unfinished game session
+pub const RESUME_GRACE: i64 = 120;
+
+/// Checkpoints outlive the grace window so an instance can still commit them afterwards
+const CHECKPOINT_TTL: i64 = 60 * 60 * 24;
+
+/// The checkpointed session of a user for one game
+pub fn session_checkpoint_key(user_id: &str, game: GameType) -> String {
+    format!("{SESSION_CHECKPOINT_KEY}:{user_id}:{}", game.as_str())
+}
+
+/// Stores a session and adds it to the sorted set of checkpoints, scored by when the grace
+/// window closes in millis
+pub async fn checkpoint_session(
+    conn: &mut ConnectionManager,
+    session: &GameInProgress,
+) -> Result<()> {
+    let key = session_checkpoint_key(session.get_user_id(), session.get_game());
+    let session_json = serde_json::to_string(session)?;
+    let resume_until = Utc::now().timestamp_millis() + RESUME_GRACE * 1000;
+
+    let _: () = redis::pipe()
+        .atomic()
+        .set_ex(&key, session_json, CHECKPOINT_TTL as u64)
+        .ignore()
+        .zadd(SESSION_CHECKPOINTS_KEY, &key, resume_until)
+        .ignore()
+        .query_async(conn)
+        .await
+        .with_context(|| format!("Failed to checkpoint session {key}"))?;
+
+    Ok(())
+}
+
+/// Removes and returns a checkpoint. Only one caller can get a given checkpoint.
+pub async fn take_session_checkpoint(
+    conn: &mut ConnectionManager,
+    key: &str,
+) -> Result<Option<GameInProgress>> {
+    let session_json: Option<String> = conn.get_del(key).await?;
+    let _: () = conn.zrem(SESSION_CHECKPOINTS_KEY, key).await?;
+
+    session_json
+        .map(|json| serde_json::from_str(&json).context("Failed to parse session checkpoint"))
+        .transpose()
+}
+
+/// Games of a user with a checkpoint that can still be resumed
+pub async fn resumable_games(conn: &mut ConnectionManager, user_id: &str) -> Result<Vec<GameType>> {
+    let mut pipe = redis::pipe();
+
+    for game in GameType::ALL {
+        pipe.zscore(
+            SESSION_CHECKPOINTS_KEY,
+            session_checkpoint_key(user_id, game),
+        );
+    }
+
+    let scores: Vec<Option<f64>> = pipe.query_async(conn).await?;
+    let now = Utc::now().timestamp_millis() as f64;
+
+    Ok(GameType::ALL
+        .into_iter()
+        .zip(scores)
+        .filter_map(|(game, resume_until)| resume_until.filter(|t| *t > now).map(|_| game))
+        .collect())
+}
+
+/// Checkpoint keys whose grace window has closed
+pub async fn expired_session_checkpoints(conn: &mut ConnectionManager) -> Result<Vec<String>> {
+    let now = Utc::now().timestamp_millis();
+    Ok(conn
+        .zrangebyscore(SESSION_CHECKPOINTS_KEY, "-inf", now)
+        .await?)
+}
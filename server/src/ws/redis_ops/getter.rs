@@ -1,15 +1,17 @@
 use anyhow::{Context, Result};
 use chrono::DateTime;
-use db::models::User;
+use db::models::{GameType, User};
 use redis::AsyncCommands;
 use redis::aio::ConnectionManager;
 use std::collections::HashSet;
 
+use crate::auth::CodeVerifier;
 use crate::ws::models::{UserWithRank, UserWithRankSocials, UserWithSocials};
 use crate::ws::redis_ops::{
-    ALL_TASKS_KEY, DIRTY_KEY, HSET_DISCORD, HSET_DISCORD_ID, HSET_EVM_WALLET, HSET_JOINED_AT,
-    HSET_NAME, HSET_PHOTO, HSET_POINTS, HSET_REFERRAL, HSET_SOL_WALLET, HSET_TELEGRAM,
-    HSET_TELEGRAM_ID, HSET_TWITTER, HSET_TWITTER_ID, LEADERBOARD_KEY, MAX_LEADERBOARD_SIZE,
+    ALL_TASKS_KEY, CODE_VERIFIER_KEY, DIRTY_KEY, HSET_DISCORD, HSET_DISCORD_ID, HSET_EVM_WALLET,
+    HSET_JOINED_AT, HSET_NAME, HSET_PHOTO, HSET_POINTS, HSET_REFERRAL, HSET_SOL_WALLET,
+    HSET_TELEGRAM, HSET_TELEGRAM_ID, HSET_TWITTER, HSET_TWITTER_ID, LEADERBOARD_KEY,
+    MAX_LEADERBOARD_SIZE, game_leaderboard_key,
 };
 
 pub async fn get_leaderboard_entries(conn: &mut ConnectionManager) -> Result<Vec<String>> {
@@ -18,6 +20,117 @@ pub async fn get_leaderboard_entries(conn: &mut ConnectionManager) -> Result<Vec
         .await?)
 }
 
+pub async fn get_game_leaderboard_entries(
+    conn: &mut ConnectionManager,
+    game: GameType,
+) -> Result<Vec<(String, i32)>> {
+    Ok(conn
+        .zrevrange_withscores(game_leaderboard_key(game), 0, MAX_LEADERBOARD_SIZE - 1)
+        .await?)
+}
+
+pub async fn user_in_game_leaderboard(
+    conn: &mut ConnectionManager,
+    game: GameType,
+    user_id: &str,
+) -> Result<Option<i32>> {
+    Ok(conn.zscore(game_leaderboard_key(game), user_id).await?)
+}
+
+/// Stores the score only if it beats the user's current best. Returns whether it did.
+pub async fn set_game_best_score(
+    conn: &mut ConnectionManager,
+    game: GameType,
+    user_id: &str,
+    score: i32,
+) -> Result<bool> {
+    let changed: i32 = redis::cmd("ZADD")
+        .arg(game_leaderboard_key(game))
+        .arg("GT")
+        .arg("CH")
+        .arg(score)
+        .arg(user_id)
+        .query_async(conn)
+        .await
+        .context("Failed to set game best score")?;
+
+    Ok(changed > 0)
+}
+
+/// Keeps only the top `MAX_LEADERBOARD_SIZE` scores of a game
+pub async fn trim_game_leaderboard(conn: &mut ConnectionManager, game: GameType) -> Result<()> {
+    let _: () = conn
+        .zremrangebyrank(game_leaderboard_key(game), 0, -(MAX_LEADERBOARD_SIZE + 1))
+        .await?;
+    Ok(())
+}
+
+/// Replaces a window leaderboard with fresh standings that expire at `expire_at`
+pub async fn set_window_leaderboard(
+    conn: &mut ConnectionManager,
+    key: &str,
+    entries: &[(String, i32)],
+    expire_at: i64,
+) -> Result<()> {
+    let mut pipe = redis::pipe();
+    pipe.atomic().del(key).ignore();
+
+    if !entries.is_empty() {
+        let members: Vec<(i32, &str)> = entries
+            .iter()
+            .map(|(user_id, score)| (*score, user_id.as_str()))
+            .collect();
+
+        pipe.zadd_multiple(key, &members)
+            .ignore()
+            .expire_at(key, expire_at)
+            .ignore();
+    }
+
+    let _: () = pipe
+        .query_async(conn)
+        .await
+        .context("Failed to set window leaderboard")?;
+
+    Ok(())
+}
+
+pub async fn get_window_leaderboard_entries(
+    conn: &mut ConnectionManager,
+    key: &str,
+) -> Result<Option<Vec<(String, i32)>>> {
+    let exists: bool = conn.exists(key).await?;
+
+    if !exists {
+        return Ok(None);
+    }
+
+    Ok(Some(
+        conn.zrevrange_withscores(key, 0, MAX_LEADERBOARD_SIZE - 1)
+            .await?,
+    ))
+}
+
+/// One SCAN step over the keys matching `pattern`. The scan is done once the returned cursor is 0.
+pub async fn scan_keys(
+    conn: &mut ConnectionManager,
+    cursor: u64,
+    pattern: &str,
+    count: usize,
+) -> Result<(u64, Vec<String>)> {
+    let (cursor, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+        .arg(cursor)
+        .arg("MATCH")
+        .arg(pattern)
+        .arg("COUNT")
+        .arg(count)
+        .query_async(conn)
+        .await
+        .with_context(|| format!("Failed to scan keys matching {pattern}"))?;
+
+    Ok((cursor, keys))
+}
+
 pub async fn get_user_points(conn: &mut ConnectionManager, user_key: &str) -> Result<Option<i32>> {
     let points: Option<i32> = conn.hget(user_key, HSET_POINTS).await?;
     Ok(points)
@@ -79,11 +192,6 @@ pub async fn is_user_added(conn: &mut ConnectionManager, user_key: &str) -> Resu
     Ok(conn.exists(user_key).await?)
 }
 
-pub async fn delete_user(conn: &mut ConnectionManager, user_key: &str) -> Result<()> {
-    let _: () = conn.del(user_key).await.context("Failed to delete user")?;
-    Ok(())
-}
-
 pub async fn delete_dirty_user(conn: &mut ConnectionManager, user_id: &str) -> Result<()> {
     let _: () = conn
         .srem(DIRTY_KEY, user_id)
@@ -92,6 +200,54 @@ pub async fn delete_dirty_user(conn: &mut ConnectionManager, user_id: &str) -> R
     Ok(())
 }
 
+/// One SSCAN step over the dirty users. The scan is done once the returned cursor is 0.
+pub async fn scan_dirty_users(
+    conn: &mut ConnectionManager,
+    cursor: u64,
+    count: usize,
+) -> Result<(u64, Vec<String>)> {
+    let (cursor, user_ids): (u64, Vec<String>) = redis::cmd("SSCAN")
+        .arg(DIRTY_KEY)
+        .arg(cursor)
+        .arg("COUNT")
+        .arg(count)
+        .query_async(conn)
+        .await
+        .context("Failed to scan dirty users")?;
+
+    Ok((cursor, user_ids))
+}
+
+/// Removes the user from the dirty list unless their points moved away from `points`.
+/// Returns whether it was removed.
+pub async fn clear_dirty_if_unchanged(
+    conn: &mut ConnectionManager,
+    user_key: &str,
+    user_id: &str,
+    points: i32,
+) -> Result<bool> {
+    let script = redis::Script::new(
+        r"
+        if redis.call('HGET', KEYS[1], ARGV[1]) == ARGV[2] then
+            return redis.call('SREM', KEYS[2], ARGV[3])
+        end
+        return 0
+        ",
+    );
+
+    let removed: i32 = script
+        .key(user_key)
+        .key(DIRTY_KEY)
+        .arg(HSET_POINTS)
+        .arg(points)
+        .arg(user_id)
+        .invoke_async(conn)
+        .await
+        .context("Failed to clear dirty user")?;
+
+    Ok(removed > 0)
+}
+
 pub async fn set_user_leaderboard_points(
     conn: &mut ConnectionManager,
     user_key: &str,
@@ -155,6 +311,23 @@ pub async fn add_new_user(
     points: i32,
     referral_code: Option<String>,
 ) -> Result<()> {
+    let user_details = user_details(user_with_socials, points, referral_code);
+
+    let _: () = conn
+        .hset_multiple(user_key, &user_details)
+        .await
+        .context("Failed to add new user")?;
+
+    set_user_completed_tasks(conn, user_task_key, tasks).await?;
+    Ok(())
+}
+
+/// The hash fields a cached user is stored with
+pub fn user_details(
+    user_with_socials: UserWithSocials,
+    points: i32,
+    referral_code: Option<String>,
+) -> Vec<(&'static str, String)> {
     let user = user_with_socials.user;
     let mut user_details = Vec::new();
 
@@ -200,13 +373,7 @@ pub async fn add_new_user(
     user_details.push((HSET_PHOTO, user.photo_url));
     user_details.push((HSET_POINTS, points.to_string()));
 
-    let _: () = conn
-        .hset_multiple(user_key, &user_details)
-        .await
-        .context("Failed to add new user")?;
-
-    set_user_completed_tasks(conn, user_task_key, tasks).await?;
-    Ok(())
+    user_details
 }
 
 pub async fn update_user_username(
@@ -325,6 +492,16 @@ pub async fn set_all_tasks(
     Ok(())
 }
 
+/// Removes tasks from the cached task list
+pub async fn remove_tasks(conn: &mut ConnectionManager, task_ids: &[String]) -> Result<()> {
+    if task_ids.is_empty() {
+        return Ok(());
+    }
+
+    let _: () = conn.hdel(ALL_TASKS_KEY, task_ids).await?;
+    Ok(())
+}
+
 pub async fn get_all_tasks(conn: &mut ConnectionManager) -> Result<Vec<(String, String)>> {
     let tasks: Vec<(String, String)> = conn.hgetall(ALL_TASKS_KEY).await?;
     Ok(tasks)
@@ -344,6 +521,42 @@ pub async fn update_task_details(
     Ok(())
 }
 
+/// Stores an OAuth or redirect state until its lifetime runs out
+pub async fn set_code_verifier(
+    conn: &mut ConnectionManager,
+    state: &str,
+    verifier: &CodeVerifier,
+) -> Result<()> {
+    let verifier_json = serde_json::to_string(verifier)?;
+
+    let _: () = conn
+        .set_ex(
+            format!("{CODE_VERIFIER_KEY}:{state}"),
+            verifier_json,
+            u64::from(verifier.lifetime) * 60,
+        )
+        .await
+        .context("Failed to set code verifier")?;
+
+    Ok(())
+}
+
+pub async fn get_code_verifier(
+    conn: &mut ConnectionManager,
+    state: &str,
+) -> Result<Option<CodeVerifier>> {
+    let verifier_json: Option<String> = conn.get(format!("{CODE_VERIFIER_KEY}:{state}")).await?;
+
+    verifier_json
+        .map(|json| serde_json::from_str(&json).context("Failed to parse code verifier"))
+        .transpose()
+}
+
+pub async fn delete_code_verifier(conn: &mut ConnectionManager, state: &str) -> Result<()> {
+    let _: () = conn.del(format!("{CODE_VERIFIER_KEY}:{state}")).await?;
+    Ok(())
+}
+
 pub async fn get_user_telegram_id(
     conn: &mut ConnectionManager,
     user_key: &str,
@@ -1,6 +1,15 @@
+mod checkpoint;
 mod getter;
+mod nonces;
+mod presence;
 mod pubsub;
 mod store;
+mod tokens;
+mod warmup;
 
+pub use checkpoint::*;
 pub use getter::*;
+pub use nonces::*;
+pub use presence::*;
 pub use store::*;
+pub use tokens::*;
@@ -0,0 +1,41 @@
+use anyhow::{Context, Result};
+use redis::AsyncCommands;
+use redis::aio::ConnectionManager;
+
+use crate::ws::sign_in::{CHALLENGE_TTL, SignInMessage};
+
+pub const SIGN_IN_NONCE_KEY: &str = "sign_in_nonce";
+
+fn nonce_key(nonce: &str) -> String {
+    format!("{SIGN_IN_NONCE_KEY}:{nonce}")
+}
+
+/// Keeps an issued sign in message until it is used or expires
+pub async fn store_sign_in_message(
+    conn: &mut ConnectionManager,
+    message: &SignInMessage,
+) -> Result<()> {
+    let _: () = conn
+        .set_ex(
+            nonce_key(&message.nonce),
+            serde_json::to_string(message)?,
+            CHALLENGE_TTL.num_seconds() as u64,
+        )
+        .await
+        .context("Failed to store sign in nonce")?;
+
+    Ok(())
+}
+
+/// Removes the message issued with `nonce` so it can't be signed in with again
+pub async fn take_sign_in_message(
+    conn: &mut ConnectionManager,
+    nonce: &str,
+) -> Result<Option<SignInMessage>> {
+    let message: Option<String> = conn.get_del(nonce_key(nonce)).await?;
+
+    message
+        .map(|message| serde_json::from_str(&message))
+        .transpose()
+        .context("Failed to parse sign in message")
+}
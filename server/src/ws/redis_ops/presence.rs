@@ -0,0 +1,307 @@
+use anyhow::{Context, Result};
+use chrono::Utc;
+use redis::AsyncCommands;
+use redis::aio::ConnectionManager;
+use std::collections::HashSet;
+
+use crate::ws::redis_ops::{DIRTY_KEY, HSET_POINTS, LEADERBOARD_KEY, USER_KEY, USER_TASK_KEY};
+use crate::ws::server::ConnId;
+
+pub const CONN_KEY: &str = "conn";
+pub const USER_CONNS_KEY: &str = "user_conns";
+pub const INSTANCE_SUB: &str = "instance";
+
+pub const HSET_INSTANCE: &str = "instance";
+pub const HSET_USER_ID: &str = "user_id";
+
+/// Presence that is not refreshed within this many seconds is treated as gone
+pub const PRESENCE_TTL: i64 = 30;
+
+/// Channel where other instances forward work for connections held by `instance_id`
+pub fn instance_channel(instance_id: &str) -> String {
+    format!("{INSTANCE_SUB}:{instance_id}")
+}
+
+/// Hash with the instance and the user of a logged in connection
+fn conn_key(conn_id: ConnId) -> String {
+    format!("{CONN_KEY}:{conn_id}")
+}
+
+/// Sorted set of the connection ids of a user, scored by when they expire in millis
+fn user_conns_key(user_id: &str) -> String {
+    format!("{USER_CONNS_KEY}:{user_id}")
+}
+
+/// Registers a logged in connection. Returns how many live connections the user has now.
+pub async fn add_presence(
+    conn: &mut ConnectionManager,
+    instance_id: &str,
+    conn_id: ConnId,
+    user_id: &str,
+) -> Result<usize> {
+    let conn_key = conn_key(conn_id);
+    let user_key = user_conns_key(user_id);
+    let now = Utc::now().timestamp_millis();
+
+    let (count,): (usize,) = redis::pipe()
+        .atomic()
+        .hset_multiple(
+            &conn_key,
+            &[(HSET_INSTANCE, instance_id), (HSET_USER_ID, user_id)],
+        )
+        .ignore()
+        .expire(&conn_key, PRESENCE_TTL)
+        .ignore()
+        .zadd(&user_key, conn_id, now + PRESENCE_TTL * 1000)
+        .ignore()
+        .zrembyscore(&user_key, "-inf", now)
+        .ignore()
+        .expire(&user_key, PRESENCE_TTL)
+        .ignore()
+        .zcard(&user_key)
+        .query_async(conn)
+        .await
+        .context("Failed to add connection presence")?;
+
+    Ok(count)
+}
+
+/// Removes a connection. Returns how many live connections the user has left.
+pub async fn remove_presence(
+    conn: &mut ConnectionManager,
+    conn_id: ConnId,
+    user_id: &str,
+) -> Result<usize> {
+    let user_key = user_conns_key(user_id);
+    let now = Utc::now().timestamp_millis();
+
+    let (count,): (usize,) = redis::pipe()
+        .atomic()
+        .del(conn_key(conn_id))
+        .ignore()
+        .zrem(&user_key, conn_id)
+        .ignore()
+        .zrembyscore(&user_key, "-inf", now)
+        .ignore()
+        .zcard(&user_key)
+        .query_async(conn)
+        .await
+        .context("Failed to remove connection presence")?;
+
+    Ok(count)
+}
+
+/// Extends the presence of every `(conn_id, user_id)` held by an instance
+pub async fn refresh_presence(
+    conn: &mut ConnectionManager,
+    instance_id: &str,
+    entries: &[(ConnId, String)],
+) -> Result<()> {
+    if entries.is_empty() {
+        return Ok(());
+    }
+
+    let expires_at = Utc::now().timestamp_millis() + PRESENCE_TTL * 1000;
+    let mut pipe = redis::pipe();
+
+    for (conn_id, user_id) in entries {
+        let conn_key = conn_key(*conn_id);
+        let user_key = user_conns_key(user_id);
+
+        pipe.hset_multiple(
+            &conn_key,
+            &[(HSET_INSTANCE, instance_id), (HSET_USER_ID, user_id)],
+        )
+        .ignore()
+        .expire(&conn_key, PRESENCE_TTL)
+        .ignore()
+        .zadd(&user_key, *conn_id, expires_at)
+        .ignore()
+        .expire(&user_key, PRESENCE_TTL)
+        .ignore();
+    }
+
+    let _: () = pipe
+        .query_async(conn)
+        .await
+        .context("Failed to refresh connection presence")?;
+
+    Ok(())
+}
+
+/// The `(instance_id, user_id)` holding a connection, if it is still alive
+pub async fn get_conn_presence(
+    conn: &mut ConnectionManager,
+    conn_id: ConnId,
+) -> Result<Option<(String, String)>> {
+    let (instance, user_id): (Option<String>, Option<String>) = conn
+        .hmget(conn_key(conn_id), &[HSET_INSTANCE, HSET_USER_ID])
+        .await?;
+
+    Ok(instance.zip(user_id))
+}
+
+/// Live connections of a user across all instances
+pub async fn user_connection_count(conn: &mut ConnectionManager, user_id: &str) -> Result<usize> {
+    let now = Utc::now().timestamp_millis();
+    Ok(conn.zcount(user_conns_key(user_id), now, "+inf").await?)
+}
+
+/// What redis holds of a cached user, compared with Postgres when reconciling
+pub struct CachedUserState {
+    pub dirty: bool,
+    pub connections: usize,
+    pub points: Option<i32>,
+    pub ranked_points: Option<i32>,
+    pub completed_tasks: HashSet<String>,
+}
+
+/// Reads the cached state of many users in one round trip, in the order of `user_ids`
+pub async fn get_cached_user_states(
+    conn: &mut ConnectionManager,
+    user_ids: &[String],
+) -> Result<Vec<CachedUserState>> {
+    let now = Utc::now().timestamp_millis();
+    let mut pipe = redis::pipe();
+
+    for user_id in user_ids {
+        let user_key = format!("{USER_KEY}:{user_id}");
+
+        pipe.sismember(DIRTY_KEY, user_id)
+            .zcount(user_conns_key(user_id), now, "+inf")
+            .hget(&user_key, HSET_POINTS)
+            .zscore(LEADERBOARD_KEY, &user_key)
+            .smembers(format!("{USER_TASK_KEY}:{user_id}"));
+    }
+
+    let states: Vec<(bool, usize, Option<i32>, Option<i32>, HashSet<String>)> = pipe
+        .query_async(conn)
+        .await
+        .context("Failed to get cached user states")?;
+
+    Ok(states
+        .into_iter()
+        .map(
+            |(dirty, connections, points, ranked_points, completed_tasks)| CachedUserState {
+                dirty,
+                connections,
+                points,
+                ranked_points,
+                completed_tasks,
+            },
+        )
+        .collect())
+}
+
+/// Live connection ids of a user across all instances
+pub async fn user_connections(conn: &mut ConnectionManager, user_id: &str) -> Result<Vec<ConnId>> {
+    let now = Utc::now().timestamp_millis();
+    Ok(conn
+        .zrangebyscore(user_conns_key(user_id), now, "+inf")
+        .await?)
+}
+
+/// Drops the cached user and their completed tasks, but only if the user has no live connection
+/// on any instance, no unflushed points and no leaderboard spot. The checks and the delete run as
+/// one script so a login or a point change on another instance can't slip in between.
+pub async fn delete_user_if_idle(conn: &mut ConnectionManager, user_id: &str) -> Result<bool> {
+    let script = redis::Script::new(
+        r"
+        if redis.call('ZCOUNT', KEYS[3], ARGV[1], '+inf') > 0 then
+            return 0
+        end
+        if redis.call('SISMEMBER', KEYS[4], ARGV[2]) == 1 then
+            return 0
+        end
+        if redis.call('ZSCORE', KEYS[5], KEYS[1]) then
+            return 0
+        end
+        return redis.call('DEL', KEYS[1], KEYS[2])
+        ",
+    );
+
+    let deleted: i32 = script
+        .key(format!("{USER_KEY}:{user_id}"))
+        .key(format!("{USER_TASK_KEY}:{user_id}"))
+        .key(user_conns_key(user_id))
+        .key(DIRTY_KEY)
+        .key(LEADERBOARD_KEY)
+        .arg(Utc::now().timestamp_millis())
+        .arg(user_id)
+        .invoke_async(conn)
+        .await
+        .context("Failed to delete idle user")?;
+
+    Ok(deleted > 0)
+}
+
+/// Rewrites a cached user with `details` and `tasks` from Postgres, keeping their leaderboard
+/// spot at `points`. Nothing is written if the user has a live connection, unflushed points, or
+/// cached points other than `cached_points`, as then redis may be ahead of what was read.
+pub async fn replace_idle_user(
+    conn: &mut ConnectionManager,
+    user_id: &str,
+    cached_points: Option<i32>,
+    points: i32,
+    details: &[(&str, String)],
+    tasks: &[String],
+) -> Result<bool> {
+    let script = redis::Script::new(
+        r"
+        if redis.call('ZCOUNT', KEYS[3], ARGV[1], '+inf') > 0 then
+            return 0
+        end
+        if redis.call('SISMEMBER', KEYS[4], ARGV[2]) == 1 then
+            return 0
+        end
+        if (redis.call('HGET', KEYS[1], ARGV[3]) or '') ~= ARGV[4] then
+            return 0
+        end
+
+        local tasks_from = 7 + tonumber(ARGV[6]) * 2
+
+        redis.call('DEL', KEYS[1], KEYS[2])
+        redis.call('HSET', KEYS[1], unpack(ARGV, 7, tasks_from - 1))
+        if #ARGV >= tasks_from then
+            redis.call('SADD', KEYS[2], unpack(ARGV, tasks_from))
+        end
+        if redis.call('ZSCORE', KEYS[5], KEYS[1]) then
+            redis.call('ZADD', KEYS[5], ARGV[5], KEYS[1])
+        end
+        return 1
+        ",
+    );
+
+    let mut invocation = script.prepare_invoke();
+    invocation
+        .key(format!("{USER_KEY}:{user_id}"))
+        .key(format!("{USER_TASK_KEY}:{user_id}"))
+        .key(user_conns_key(user_id))
+        .key(DIRTY_KEY)
+        .key(LEADERBOARD_KEY)
+        .arg(Utc::now().timestamp_millis())
+        .arg(user_id)
+        .arg(HSET_POINTS)
+        .arg(
+            cached_points
+                .map(|points| points.to_string())
+                .unwrap_or_default(),
+        )
+        .arg(points)
+        .arg(details.len());
+
+    for (field, value) in details {
+        invocation.arg(*field).arg(value);
+    }
+
+    for task in tasks {
+        invocation.arg(task);
+    }
+
+    let replaced: i32 = invocation
+        .invoke_async(conn)
+        .await
+        .context("Failed to replace cached user")?;
+
+    Ok(replaced > 0)
+}
@@ -1,18 +1,16 @@
 use anyhow::{Context, Result};
-use db::models::User;
+use db::models::GameType;
 use log::{error, info};
 use redis::{AsyncCommands, PushKind, Value};
 use tokio::sync::mpsc::unbounded_channel;
 use tokio::time::{Duration, sleep};
 
-use crate::REDIS_URL;
 use crate::ws::get_pubsub_conn;
-use crate::ws::models::WsResponse;
 use crate::ws::redis_ops::{
-    DISCONNECTED_SUB, LEADERBOARD_SUB, USER_KEY, delete_dirty_user, delete_user, get_user_points,
-    user_in_leaderboard,
+    LEADERBOARD_SUB, TASKS_SUB, delete_user_if_idle, game_leaderboard_channel, instance_channel,
 };
 use crate::ws::server::Server;
+use crate::{INSTANCE_ID, REDIS_URL};
 
 impl Server {
     pub async fn subscribe_for_updates(mut self) {
@@ -76,81 +74,40 @@ impl Server {
             String::from_utf8(other_value.into()).context("Failed to parse byte to string")?;
 
         match channel_name.as_ref() {
-            LEADERBOARD_SUB => self
-                .broadcast_leaderboard_update()
-                .await
-                .context("Failed to broadcast leaderboard update")?,
-            DISCONNECTED_SUB => {
-                let user_id = &message;
-
-                self.cleanup_disconnected(user_id)
-                    .await
-                    .with_context(|| format!("Failed to cleanup disconnected user {user_id}"))?;
+            LEADERBOARD_SUB => self.mark_leaderboard_dirty(None),
+            // Reads redis once per logged in user, kept off the loop handling the other messages
+            TASKS_SUB => {
+                tokio::spawn(self.clone().push_tasks());
             }
-            _ => {
-                error!("Unexpected channel: {channel_name}");
-            }
-        }
-        Ok(())
-    }
-
-    async fn broadcast_leaderboard_update(&mut self) -> Result<()> {
-        let resp = WsResponse::leaderboard(self.create_leaderboard().await?);
-
-        for id in self.subscribed.iter() {
-            if let Some(tx) = self.sessions.get(&id) {
-                let _ = tx.send(resp.clone().json());
+            channel if channel == instance_channel(&INSTANCE_ID) => self
+                .handle_routed_command(&message)
+                .context("Failed to handle routed command")?,
+            channel => {
+                let Some(game) = GameType::ALL
+                    .into_iter()
+                    .find(|game| game_leaderboard_channel(*game) == channel)
+                else {
+                    error!("Unexpected channel: {channel_name}");
+                    return Ok(());
+                };
+
+                self.mark_leaderboard_dirty(Some(game));
             }
         }
         Ok(())
     }
 
+    /// Writes the points of a user that left their last connection to Postgres and drops them
+    /// from redis unless they are still needed there.
     pub async fn cleanup_disconnected(&mut self, user_id: &str) -> Result<()> {
         let mut conn = self.pool.get().await?;
 
-        let user_key = format!("{USER_KEY}:{user_id}");
-
-        let latest_points = get_user_points(&mut self.redis, &user_key).await?;
-
-        let Some(points) = latest_points else {
-            error!("No points found for disconnected user {user_id}");
-            delete_dirty_user(&mut self.redis, user_id).await?;
+        if let Err(e) = self.flush_user_points(&mut conn, user_id).await {
+            error!("Failed to flush points of disconnected user {user_id}. Reason: {e:?}");
             return Ok(());
-        };
-
-        let result = User::set_points(&mut conn, user_id, points).await;
-
-        match result {
-            Ok(updated_user) => {
-                if updated_user.points != points {
-                    // No update = point less than the current. Investigation required if ever
-                    // happens but this should not happen
-                    error!(
-                        "Points mismatch for user {user_id} {:?}: redit points {points}, db points {}",
-                        updated_user
-                            .sol_wallet
-                            .as_deref()
-                            .unwrap_or("No Sol Wallet"),
-                        updated_user.points
-                    );
-                }
-            }
-            Err(e) => {
-                error!(
-                    "Failed to update dirty user DB. Does the DB have more points than the redis points? \
-                    Investigation required. User ID {user_id} Redis points {points}. \
-                    Reason {e}"
-                );
-            }
         }
 
-        delete_dirty_user(&mut self.redis, user_id).await?;
-
-        let exists = user_in_leaderboard(&mut self.redis, &user_key).await?;
-
-        if exists.is_none() {
-            delete_user(&mut self.redis, &user_key).await?;
-        }
+        delete_user_if_idle(&mut self.redis, user_id).await?;
 
         Ok(())
     }
@@ -1,25 +1,35 @@
 use anyhow::{Context, Error, Result, anyhow};
-use db::models::{Task, TaskCompletion, User, UserSocial};
+use db::models::{
+    GameSession, GameType, LeaderboardWindow, NewPointTransaction, PointSource, TaskCompletion,
+    User, UserSocial, UserStatus,
+};
 use diesel_async::AsyncConnection;
-use log::{error, info};
+use log::info;
 use redis::AsyncCommands;
+use std::collections::HashMap;
 
-use crate::ws::models::UserWithSocials;
+use crate::ws::models::{LeaderboardEntry, UserWithSocials};
 use crate::ws::redis_ops::{
-    add_new_user, add_user_to_leaderboard, get_full_user, get_leaderboard_entries,
-    increase_points_if_exists, increase_user_points_by_with_dirty, is_user_added, set_all_tasks,
-    set_user_leaderboard_points, user_in_leaderboard,
+    add_new_user, add_user_to_leaderboard, get_full_user, get_game_leaderboard_entries,
+    get_leaderboard_entries, get_user_points, increase_points_if_exists,
+    increase_user_points_by_with_dirty, is_user_added, set_game_best_score,
+    set_user_leaderboard_points, trim_game_leaderboard, user_in_game_leaderboard,
+    user_in_leaderboard,
 };
 use crate::ws::server::Server;
 
 pub const LEADERBOARD_SUB: &str = "leaderboard_updates";
-pub const DISCONNECTED_SUB: &str = "DISCONNECTED";
+pub const GAME_LEADERBOARD_SUB: &str = "game_leaderboard_updates";
+pub const TASKS_SUB: &str = "tasks_updates";
 
 pub const LEADERBOARD_KEY: &str = "leaderboard";
+pub const GAME_LEADERBOARD_KEY: &str = "game_leaderboard";
+pub const WINDOW_LEADERBOARD_KEY: &str = "window_leaderboard";
 pub const USER_KEY: &str = "user";
 pub const DIRTY_KEY: &str = "dirty_users";
 pub const USER_TASK_KEY: &str = "user_task";
 pub const ALL_TASKS_KEY: &str = "tasks";
+pub const CODE_VERIFIER_KEY: &str = "code_verifier";
 
 pub const MAX_LEADERBOARD_SIZE: isize = 50;
 
@@ -37,163 +47,239 @@ pub const HSET_DISCORD_ID: &str = "discord_id";
 pub const HSET_TELEGRAM_ID: &str = "telegram_id";
 pub const HSET_REFERRAL: &str = "referral_code";
 
+/// Sorted set of the best `final_score` per user id for a game
+pub fn game_leaderboard_key(game: GameType) -> String {
+    format!("{GAME_LEADERBOARD_KEY}:{}", game.as_str())
+}
+
+pub fn game_leaderboard_channel(game: GameType) -> String {
+    format!("{GAME_LEADERBOARD_SUB}:{}", game.as_str())
+}
+
+/// Sorted set of the points earned per user id within one period of a window
+pub fn window_leaderboard_key(window: LeaderboardWindow, period: &str) -> String {
+    format!("{WINDOW_LEADERBOARD_KEY}:{}:{period}", window.as_str())
+}
+
 impl Server {
+    /// Reconciles redis with Postgres on boot. Cached state is kept and only what diverged is
+    /// repaired, so other instances keep working while this one starts.
     pub async fn initialize(&mut self) {
-        self.dirty_user_cleanup().await.unwrap();
-        info!("Cleaned up old dirty users");
+        // Only flushed, other instances may still serve these users
+        self.flush_dirty_round()
+            .await
+            .expect("Failed to flush dirty users");
+        info!("Flushed dirty users");
+
+        self.reconcile_cached_users()
+            .await
+            .expect("Failed to reconcile cached users");
+
+        self.remove_orphan_user_tasks()
+            .await
+            .expect("Failed to remove orphan user tasks");
+
+        self.reconcile_leaderboard()
+            .await
+            .expect("Failed to reconcile leaderboard");
+        info!("Leaderboard data initialized");
 
-        let _: () = self.redis.del(LEADERBOARD_KEY).await.unwrap();
-        let _: () = self.redis.del(ALL_TASKS_KEY).await.unwrap();
-        let keys: Vec<String> = self.redis.keys(format!("{USER_KEY}*")).await.unwrap();
+        self.reconcile_game_leaderboards()
+            .await
+            .expect("Failed to reconcile game leaderboards");
+        info!("Game leaderboard data initialized");
+
+        self.reconcile_tasks()
+            .await
+            .expect("Failed to reconcile tasks");
+        info!("All tasks initialized");
+    }
+
+    /// Creates the leaderboard from the redis data to send to the client.
+    pub async fn create_leaderboard(&mut self) -> Result<Vec<LeaderboardEntry>> {
+        let leaderboard_entries = get_leaderboard_entries(&mut self.redis).await?;
+        let mut leaderboard = Vec::new();
+
+        for user_key in leaderboard_entries {
+            let user_id = user_key
+                .strip_prefix(&format!("{USER_KEY}:"))
+                .ok_or(anyhow!("Failed to parse user_id from key {user_key}"))?;
 
-        for key in keys {
-            let _: () = self.redis.del(key).await.unwrap();
+            let full_user = get_full_user(&mut self.redis, &user_key, user_id).await?;
+            leaderboard.push(LeaderboardEntry::new(full_user.points, full_user.into()));
         }
+        Ok(leaderboard)
+    }
+
+    /// Creates the best score leaderboard of a game. User details come from the DB as most of
+    /// these users are not kept in redis.
+    pub async fn create_game_leaderboard(
+        &mut self,
+        game: GameType,
+    ) -> Result<Vec<LeaderboardEntry>> {
+        let entries = get_game_leaderboard_entries(&mut self.redis, game).await?;
+        self.entries_with_users(entries).await
+    }
 
-        let keys: Vec<String> = self.redis.keys(format!("{USER_TASK_KEY}*")).await.unwrap();
+    /// Attaches user details from the DB to `(user_id, score)` pairs, keeping their order
+    pub async fn entries_with_users(
+        &mut self,
+        entries: Vec<(String, i32)>,
+    ) -> Result<Vec<LeaderboardEntry>> {
+        let user_ids: Vec<String> = entries.iter().map(|(user_id, _)| user_id.clone()).collect();
+
+        let mut conn = self.pool.get().await?;
+        let mut users: HashMap<String, User> = User::get_by_ids(&mut conn, &user_ids)
+            .await?
+            .into_iter()
+            .map(|user| (user.user_id.clone(), user))
+            .collect();
+
+        Ok(entries
+            .into_iter()
+            .filter_map(|(user_id, score)| {
+                users
+                    .remove(&user_id)
+                    .map(|user| LeaderboardEntry::new(score, user.into()))
+            })
+            .collect())
+    }
 
-        for key in keys {
-            let _: () = self.redis.del(key).await.unwrap();
+    /// Records the final score of a finished session and notifies subscribers if it is a new
+    /// best score that made it to the game leaderboard.
+    pub async fn update_game_leaderboard(&mut self, session: &GameSession) -> Result<()> {
+        if session.final_score <= 0 {
+            return Ok(());
         }
 
-        info!("Old leaderboard data deleted");
+        let improved = set_game_best_score(
+            &mut self.redis,
+            session.game,
+            &session.user_id,
+            session.final_score,
+        )
+        .await
+        .context("Failed to set game best score")?;
+
+        if !improved {
+            return Ok(());
+        }
 
-        let mut conn = self.pool.get().await.unwrap();
-        let leaderboard_data = User::get_leaderboard(&mut conn, MAX_LEADERBOARD_SIZE as i64)
+        trim_game_leaderboard(&mut self.redis, session.game)
             .await
-            .unwrap();
+            .context("Failed to trim game leaderboard")?;
 
-        for user in leaderboard_data {
-            let user_key = format!("{USER_KEY}:{}", user.user_id);
-            let user_task_key = format!("{USER_TASK_KEY}:{}", user.user_id);
+        let still_ranked =
+            user_in_game_leaderboard(&mut self.redis, session.game, &session.user_id)
+                .await?
+                .is_some();
 
-            let user_socials = UserSocial::get_user_socials(&mut conn, &user.user_id)
+        if still_ranked {
+            let _: () = self
+                .redis
+                .publish(
+                    game_leaderboard_channel(session.game),
+                    "Leaderboard updated",
+                )
                 .await
-                .expect("Failed to get user socials");
+                .context("Failed to publish game leaderboard update")?;
+        }
 
-            let user_completed_tasks =
-                TaskCompletion::get_user_completed_tasks(&mut conn, &user.user_id)
-                    .await
-                    .unwrap();
+        Ok(())
+    }
 
-            let user_with_socials = UserWithSocials::from_user_social(user.clone(), user_socials);
+    /// Takes a user off the global and every game leaderboard, used when they get banned or
+    /// shadow banned
+    pub async fn remove_from_leaderboards(&mut self, user_id: &str) -> Result<()> {
+        let user_key = format!("{USER_KEY}:{user_id}");
 
-            add_new_user(
-                &mut self.redis,
-                &user_key,
-                &user_task_key,
-                user_with_socials,
-                user_completed_tasks,
-                user.points,
-                user.referral_code,
-            )
+        let removed: i32 = self
+            .redis
+            .zrem(LEADERBOARD_KEY, &user_key)
             .await
-            .expect("Failed to add new user");
+            .context("Failed to remove user from leaderboard")?;
 
-            add_user_to_leaderboard(&mut self.redis, &user_key, user.points)
+        if removed > 0 {
+            let _: () = self
+                .redis
+                .publish(LEADERBOARD_SUB, "Leaderboard updated")
                 .await
-                .expect("Failed to add user to leaderboard");
+                .context("Failed to publish leaderboard update")?;
         }
-        info!("Leaderboard data initialized");
 
-        let all_tasks = Task::get_active(&mut conn).await.unwrap();
-        let mut task_id_json_list = Vec::with_capacity(all_tasks.len());
-
-        for task in all_tasks {
-            let task_id = &task.id;
-            let json_string = task.json_string();
+        for game in GameType::ALL {
+            let removed: i32 = self
+                .redis
+                .zrem(game_leaderboard_key(game), user_id)
+                .await
+                .context("Failed to remove user from game leaderboard")?;
 
-            task_id_json_list.push((task_id.clone(), json_string));
+            if removed > 0 {
+                let _: () = self
+                    .redis
+                    .publish(game_leaderboard_channel(game), "Leaderboard updated")
+                    .await
+                    .context("Failed to publish game leaderboard update")?;
+            }
         }
 
-        set_all_tasks(&mut self.redis, task_id_json_list)
-            .await
-            .expect("Failed to set all tasks");
-
-        info!("All tasks initialized");
+        Ok(())
     }
 
-    /// Cleans up the dirty users from the redis data.
-    async fn dirty_user_cleanup(&mut self) -> Result<()> {
-        let dirty_users: Vec<String> = self.redis.smembers(DIRTY_KEY).await?;
+    /// Puts a user whose status is active again back on the global and game leaderboards they
+    /// qualify for
+    pub async fn restore_to_leaderboards(&mut self, user: &User) -> Result<()> {
+        let user_key = format!("{USER_KEY}:{}", user.user_id);
 
-        if !dirty_users.is_empty() {
-            let mut conn = self.pool.get().await?;
-            for user_id in &dirty_users {
-                let redis_key = format!("{USER_KEY}:{user_id}");
-                let user_task_key = format!("{USER_TASK_KEY}:{user_id}");
+        // Leaderboard entries are read from the cached user
+        self.ensure_user_cached(user).await?;
 
-                let latest_points: Option<i32> = self
-                    .redis
-                    .hget(&redis_key, HSET_POINTS)
-                    .await
-                    .unwrap_or(None);
+        let points = get_user_points(&mut self.redis, &user_key)
+            .await?
+            .unwrap_or(user.points);
 
-                let Some(points) = latest_points else {
-                    error!("No points found for user {user_id}");
-                    let _: () = self
-                        .redis
-                        .srem(DIRTY_KEY, user_id)
-                        .await
-                        .unwrap_or_default();
-                    continue;
-                };
+        add_user_to_leaderboard(&mut self.redis, &user_key, points).await?;
 
-                let result = User::set_points(&mut conn, user_id, points).await;
+        let leaderboard_count: isize = self.redis.zcard(LEADERBOARD_KEY).await?;
 
-                if let Err(err) = result {
-                    error!("Error while updating dirty points: {err}, u_id {user_id}");
-                    continue;
-                }
+        if leaderboard_count > MAX_LEADERBOARD_SIZE {
+            // Trimming makes room for one more user, which is not needed here
+            self.trim_leaderboard(leaderboard_count - 1)
+                .await
+                .context("Failed to trim leaderboard")?;
+        }
 
-                let updated_user = result.unwrap();
-
-                if updated_user.points != points {
-                    // No update = point less than the current. Investigation required if ever
-                    // happens but this should not happen
-                    error!(
-                        "Points mismatch for user {user_id} {}: redit points {points}, db points {}",
-                        updated_user
-                            .sol_wallet
-                            .as_deref()
-                            .unwrap_or("No Sol Wallet"),
-                        updated_user.points
-                    );
-                }
+        let _: () = self
+            .redis
+            .publish(LEADERBOARD_SUB, "Leaderboard updated")
+            .await
+            .context("Failed to publish leaderboard update")?;
 
-                let _: () = self
-                    .redis
-                    .srem(DIRTY_KEY, user_id)
-                    .await
-                    .unwrap_or_default();
+        let pool = self.pool.clone();
+        let mut conn = pool.get().await?;
 
-                let exists_result = user_in_leaderboard(&mut self.redis, &redis_key).await;
+        let best_scores = GameSession::get_user_best_scores(&user.user_id, &mut conn)
+            .await
+            .context("Failed to get user best scores")?;
 
-                if let Ok(exists) = exists_result
-                    && exists.is_none()
-                {
-                    let _: () = self.redis.del(redis_key).await.unwrap_or_default();
-                    let _: () = self.redis.del(user_task_key).await.unwrap_or_default();
-                }
-            }
-        }
-        Ok(())
-    }
+        drop(conn);
 
-    /// Creates the leaderboard from the redis data to send to the client.
-    pub async fn create_leaderboard(&mut self) -> Result<Vec<User>> {
-        let leaderboard_entries = get_leaderboard_entries(&mut self.redis).await?;
-        let mut leaderboard = Vec::new();
+        for (game, score) in best_scores {
+            let Some(score) = score.filter(|score| *score > 0) else {
+                continue;
+            };
 
-        for user_key in leaderboard_entries {
-            let user_id = user_key
-                .strip_prefix(&format!("{USER_KEY}:"))
-                .ok_or(anyhow!("Failed to parse user_id from key {user_key}"))?;
+            set_game_best_score(&mut self.redis, game, &user.user_id, score).await?;
+            trim_game_leaderboard(&mut self.redis, game).await?;
 
-            let full_user = get_full_user(&mut self.redis, &user_key, user_id).await?;
-            leaderboard.push(full_user);
+            let _: () = self
+                .redis
+                .publish(game_leaderboard_channel(game), "Leaderboard updated")
+                .await
+                .context("Failed to publish game leaderboard update")?;
         }
-        Ok(leaderboard)
+
+        Ok(())
     }
 
     /// Trims the leaderboard to the top `MAX_LEADERBOARD_SIZE` users.
@@ -212,34 +298,86 @@ impl Server {
 
         for removed_user in removed_users {
             let user_id = removed_user.split_once(&format!("{USER_KEY}:")).unwrap().1;
-            if !self.is_user_logged_in(user_id).await {
+            if !self.is_user_logged_in(user_id).await? {
                 let _: () = self.redis.del(&removed_user).await.unwrap_or_default();
             }
         }
         Ok(())
     }
 
+    async fn record_point_transaction(&self, transaction: &NewPointTransaction) -> Result<()> {
+        let mut conn = self.pool.get().await?;
+        transaction
+            .insert(&mut conn)
+            .await
+            .context("Failed to insert point transaction")?;
+        Ok(())
+    }
+
     /// Increase the points of a user and update the leaderboard if necessary and publish
-    /// notification.
+    /// notification. Task points are recorded in the point ledger under `source` and
+    /// `reference`. Game points are recorded once per session when it is committed, referral
+    /// bonuses and admin changes with their DB update.
     pub async fn increase_point(
         &mut self,
         to_add: i32,
         user: &User,
-        is_referral: bool,
+        source: PointSource,
+        reference: Option<&str>,
     ) -> Result<i32> {
         let user_key = format!("{USER_KEY}:{}", user.user_id);
 
-        let total_points = if is_referral {
+        // Referral bonuses and admin changes are already applied in the DB, redis only follows
+        // if it has the user
+        let total_points = if matches!(source, PointSource::Referral | PointSource::Admin) {
             increase_points_if_exists(&mut self.redis, &user_key, to_add)
                 .await
-                .context("Failed to increase points for with referral")?
+                .context("Failed to increase points already applied in the DB")?
                 .unwrap_or(user.points + to_add)
-        } else {
+        } else if source == PointSource::GameSession {
             increase_user_points_by_with_dirty(&mut self.redis, &user_key, &user.user_id, to_add)
                 .await
                 .context("Failed to increase points with dirty")?
+        } else {
+            let total_points = increase_user_points_by_with_dirty(
+                &mut self.redis,
+                &user_key,
+                &user.user_id,
+                to_add,
+            )
+            .await
+            .context("Failed to increase points with dirty")?;
+
+            let transaction = NewPointTransaction::new(
+                user.user_id.clone(),
+                source,
+                reference.map(String::from),
+                to_add,
+                total_points,
+            );
+
+            if let Err(e) = self.record_point_transaction(&transaction).await {
+                // Taken back out so redis never holds points the ledger doesn't know about
+                increase_user_points_by_with_dirty(
+                    &mut self.redis,
+                    &user_key,
+                    &user.user_id,
+                    -to_add,
+                )
+                .await
+                .context("Failed to revert points of unrecorded transaction")?;
+
+                return Err(e);
+            }
+
+            total_points
         };
 
+        // Banned and shadow banned users keep their points but never get ranked
+        if user.status != UserStatus::Active {
+            return Ok(total_points);
+        }
+
         let leaderboard_count: isize = self.redis.zcard(LEADERBOARD_KEY).await.unwrap_or(0);
 
         if leaderboard_count > MAX_LEADERBOARD_SIZE {
@@ -0,0 +1,145 @@
+use anyhow::{Context, Result};
+use chrono::Utc;
+use redis::AsyncCommands;
+use redis::aio::ConnectionManager;
+use serde::{Deserialize, Serialize};
+
+pub const REFRESH_TOKEN_KEY: &str = "refresh_token";
+pub const USER_REFRESH_KEY: &str = "user_refresh_tokens";
+pub const USER_JTI_KEY: &str = "user_jtis";
+pub const REVOKED_JTI_KEY: &str = "revoked_jtis";
+
+/// What a refresh token was issued for. Stored under the hash of the token, never the token.
+#[derive(Serialize, Deserialize)]
+pub struct RefreshSession {
+    pub user_id: String,
+    pub ip: String,
+    pub device: String,
+}
+
+fn refresh_key(token_hash: &str) -> String {
+    format!("{REFRESH_TOKEN_KEY}:{token_hash}")
+}
+
+/// Sorted set of the refresh token hashes of a user, scored by when they expire in seconds
+fn user_refresh_key(user_id: &str) -> String {
+    format!("{USER_REFRESH_KEY}:{user_id}")
+}
+
+/// Sorted set of the access token ids of a user, scored by when they expire in seconds
+fn user_jti_key(user_id: &str) -> String {
+    format!("{USER_JTI_KEY}:{user_id}")
+}
+
+pub async fn store_refresh_token(
+    conn: &mut ConnectionManager,
+    token_hash: &str,
+    session: &RefreshSession,
+    ttl: i64,
+) -> Result<()> {
+    let user_key = user_refresh_key(&session.user_id);
+    let now = Utc::now().timestamp();
+
+    let _: () = redis::pipe()
+        .atomic()
+        .set_ex(
+            refresh_key(token_hash),
+            serde_json::to_string(session)?,
+            ttl as u64,
+        )
+        .ignore()
+        .zadd(&user_key, token_hash, now + ttl)
+        .ignore()
+        .zrembyscore(&user_key, "-inf", now)
+        .ignore()
+        .expire(&user_key, ttl)
+        .ignore()
+        .query_async(conn)
+        .await
+        .context("Failed to store refresh token")?;
+
+    Ok(())
+}
+
+/// Removes a refresh token and returns what it was issued for. A token can only be taken once.
+pub async fn take_refresh_token(
+    conn: &mut ConnectionManager,
+    token_hash: &str,
+) -> Result<Option<RefreshSession>> {
+    let session: Option<String> = conn.get_del(refresh_key(token_hash)).await?;
+
+    let Some(session) = session else {
+        return Ok(None);
+    };
+
+    let session: RefreshSession = serde_json::from_str(&session)?;
+    let _: () = conn
+        .zrem(user_refresh_key(&session.user_id), token_hash)
+        .await?;
+
+    Ok(Some(session))
+}
+
+/// Remembers an issued access token so it can be revoked before it expires
+pub async fn track_jti(
+    conn: &mut ConnectionManager,
+    user_id: &str,
+    jti: &str,
+    expires_at: i64,
+) -> Result<()> {
+    let user_key = user_jti_key(user_id);
+    let now = Utc::now().timestamp();
+
+    let _: () = redis::pipe()
+        .atomic()
+        .zadd(&user_key, jti, expires_at)
+        .ignore()
+        .zrembyscore(&user_key, "-inf", now)
+        .ignore()
+        .expire_at(&user_key, expires_at)
+        .ignore()
+        .query_async(conn)
+        .await
+        .context("Failed to track token id")?;
+
+    Ok(())
+}
+
+pub async fn is_jti_revoked(conn: &mut ConnectionManager, jti: &str) -> Result<bool> {
+    let expires_at: Option<i64> = conn.zscore(REVOKED_JTI_KEY, jti).await?;
+    Ok(expires_at.is_some())
+}
+
+/// Revokes every live access token and deletes every refresh token of a user
+pub async fn revoke_user_tokens(conn: &mut ConnectionManager, user_id: &str) -> Result<()> {
+    let jti_key = user_jti_key(user_id);
+    let refresh_set_key = user_refresh_key(user_id);
+    let now = Utc::now().timestamp();
+
+    let live_jtis: Vec<(String, i64)> =
+        conn.zrangebyscore_withscores(&jti_key, now, "+inf").await?;
+    let refresh_hashes: Vec<String> = conn.zrange(&refresh_set_key, 0, -1).await?;
+
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+
+    // Revoked ids are kept until the token would have expired anyway
+    for (jti, expires_at) in &live_jtis {
+        pipe.zadd(REVOKED_JTI_KEY, jti, *expires_at).ignore();
+    }
+
+    for token_hash in &refresh_hashes {
+        pipe.del(refresh_key(token_hash)).ignore();
+    }
+
+    let _: () = pipe
+        .zrembyscore(REVOKED_JTI_KEY, "-inf", now)
+        .ignore()
+        .del(&[&jti_key, &refresh_set_key])
+        .ignore()
+        .query_async(conn)
+        .await
+        .context("Failed to revoke user tokens")?;
+
+    Ok(())
+}
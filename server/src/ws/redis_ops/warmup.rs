@@ -0,0 +1,351 @@
+use anyhow::{Context, Result};
+use db::models::{GameSession, GameType, Task, TaskCompletion, User, UserSocial};
+use diesel_async::AsyncPgConnection;
+use log::{info, warn};
+use redis::AsyncCommands;
+use redis::aio::ConnectionManager;
+use std::collections::{HashMap, HashSet};
+
+use crate::ws::models::UserWithSocials;
+use crate::ws::redis_ops::{
+    LEADERBOARD_KEY, MAX_LEADERBOARD_SIZE, USER_KEY, USER_TASK_KEY, add_new_user,
+    add_user_to_leaderboard, game_leaderboard_key, get_all_tasks, get_cached_user_states,
+    get_game_leaderboard_entries, get_user_points, is_user_added, remove_tasks, replace_idle_user,
+    scan_keys, set_all_tasks, set_game_best_score, trim_game_leaderboard, user_details,
+    user_in_leaderboard,
+};
+use crate::ws::server::Server;
+
+/// Keys asked for per SCAN call
+const SCAN_BATCH: usize = 200;
+
+impl Server {
+    /// Compares every cached user with Postgres and rewrites the ones that diverged. Users with
+    /// points that are not flushed yet or with a live connection are skipped as redis is ahead
+    /// of Postgres for them.
+    pub async fn reconcile_cached_users(&mut self) -> Result<()> {
+        let pattern = format!("{USER_KEY}:*");
+        let mut cursor = 0;
+        let mut checked = 0;
+        let mut repaired = 0;
+
+        loop {
+            let (next_cursor, user_keys) =
+                scan_keys(&mut self.redis, cursor, &pattern, SCAN_BATCH).await?;
+
+            checked += user_keys.len();
+            repaired += self.reconcile_user_batch(user_keys).await?;
+
+            if next_cursor == 0 {
+                break;
+            }
+            cursor = next_cursor;
+        }
+
+        info!("Checked {checked} cached users, repaired {repaired}");
+
+        Ok(())
+    }
+
+    async fn reconcile_user_batch(&mut self, user_keys: Vec<String>) -> Result<usize> {
+        let prefix = format!("{USER_KEY}:");
+        let user_ids: Vec<String> = user_keys
+            .iter()
+            .filter_map(|key| key.strip_prefix(&prefix))
+            .map(String::from)
+            .collect();
+
+        if user_ids.is_empty() {
+            return Ok(0);
+        }
+
+        let pool = self.pool.clone();
+        let mut conn = pool.get().await?;
+
+        let users: HashMap<String, User> = User::get_by_ids(&mut conn, &user_ids)
+            .await?
+            .into_iter()
+            .map(|user| (user.user_id.clone(), user))
+            .collect();
+
+        // Read up front, the rewrite checks again that the user is still idle
+        let states = get_cached_user_states(&mut self.redis, &user_ids).await?;
+
+        let mut completed = TaskCompletion::get_users_completed_tasks(&mut conn, &user_ids)
+            .await
+            .context("Failed to get completed tasks of users")?;
+
+        let mut repaired = 0;
+
+        for (user_id, state) in user_ids.into_iter().zip(states) {
+            let user_key = format!("{USER_KEY}:{user_id}");
+            let user_task_key = format!("{USER_TASK_KEY}:{user_id}");
+
+            let Some(user) = users.get(&user_id) else {
+                warn!("Cached user {user_id} does not exist in the DB, removing it");
+
+                let _: () = self.redis.del(&[&user_key, &user_task_key]).await?;
+                let _: () = self.redis.zrem(LEADERBOARD_KEY, &user_key).await?;
+
+                repaired += 1;
+                continue;
+            };
+
+            // Redis is ahead of Postgres for these, live users are left to their instance
+            if state.dirty || state.connections > 0 {
+                continue;
+            }
+
+            let cached_points = state.points;
+            let ranked_points = state.ranked_points;
+            let completed_tasks = completed.remove(&user_id).unwrap_or_default();
+
+            let tasks_match = state.completed_tasks.len() == completed_tasks.len()
+                && completed_tasks
+                    .iter()
+                    .all(|task| state.completed_tasks.contains(task));
+
+            let points_match = cached_points == Some(user.points)
+                && ranked_points.is_none_or(|points| points == user.points);
+
+            if points_match && tasks_match {
+                continue;
+            }
+
+            warn!(
+                "Cached user {user_id} diverged from the DB: redis points {cached_points:?}, leaderboard points {ranked_points:?}, db points {}, tasks match {tasks_match}",
+                user.points
+            );
+
+            let user_socials = UserSocial::get_user_socials(&mut conn, &user_id)
+                .await
+                .context("Failed to get user socials")?;
+
+            let details = user_details(
+                UserWithSocials::from_user_social(user.clone(), user_socials),
+                user.points,
+                user.referral_code.clone(),
+            );
+
+            // Checked again with the rewrite, the user may have logged in or scored meanwhile
+            let replaced = replace_idle_user(
+                &mut self.redis,
+                &user_id,
+                cached_points,
+                user.points,
+                &details,
+                &completed_tasks,
+            )
+            .await?;
+
+            if replaced {
+                repaired += 1;
+            }
+        }
+
+        Ok(repaired)
+    }
+
+    /// Caches a user from Postgres unless redis already has them
+    pub async fn ensure_user_cached(&mut self, user: &User) -> Result<()> {
+        let user_key = format!("{USER_KEY}:{}", user.user_id);
+
+        if is_user_added(&mut self.redis, &user_key).await? {
+            return Ok(());
+        }
+
+        let pool = self.pool.clone();
+        let mut conn = pool.get().await?;
+
+        let completed_tasks = TaskCompletion::get_user_completed_tasks(&mut conn, &user.user_id)
+            .await
+            .context("Failed to get user completed tasks")?;
+
+        cache_user(&mut self.redis, &mut conn, user, completed_tasks).await
+    }
+
+    /// Deletes completed task sets whose user is no longer cached
+    pub async fn remove_orphan_user_tasks(&mut self) -> Result<()> {
+        let pattern = format!("{USER_TASK_KEY}:*");
+        let prefix = format!("{USER_TASK_KEY}:");
+        let mut cursor = 0;
+        let mut removed = 0;
+
+        loop {
+            let (next_cursor, task_keys) =
+                scan_keys(&mut self.redis, cursor, &pattern, SCAN_BATCH).await?;
+
+            for task_key in task_keys {
+                let Some(user_id) = task_key.strip_prefix(&prefix) else {
+                    continue;
+                };
+
+                let user_key = format!("{USER_KEY}:{user_id}");
+
+                if !is_user_added(&mut self.redis, &user_key).await? {
+                    let _: () = self.redis.del(&task_key).await?;
+                    removed += 1;
+                }
+            }
+
+            if next_cursor == 0 {
+                break;
+            }
+            cursor = next_cursor;
+        }
+
+        info!("Removed {removed} orphan user task sets");
+
+        Ok(())
+    }
+
+    /// Caches and ranks the top users from Postgres that are missing, and drops ranked users
+    /// that are no longer cached.
+    pub async fn reconcile_leaderboard(&mut self) -> Result<()> {
+        let pool = self.pool.clone();
+        let mut conn = pool.get().await?;
+
+        let top_users = User::get_leaderboard(&mut conn, MAX_LEADERBOARD_SIZE as i64).await?;
+
+        for user in top_users {
+            let user_key = format!("{USER_KEY}:{}", user.user_id);
+
+            if !is_user_added(&mut self.redis, &user_key).await? {
+                let completed_tasks =
+                    TaskCompletion::get_user_completed_tasks(&mut conn, &user.user_id)
+                        .await
+                        .context("Failed to get user completed tasks")?;
+
+                cache_user(&mut self.redis, &mut conn, &user, completed_tasks).await?;
+            }
+
+            if user_in_leaderboard(&mut self.redis, &user_key)
+                .await?
+                .is_none()
+            {
+                // A dirty user may already be ahead of the DB
+                let points = get_user_points(&mut self.redis, &user_key)
+                    .await?
+                    .unwrap_or(user.points);
+
+                add_user_to_leaderboard(&mut self.redis, &user_key, points).await?;
+            }
+        }
+
+        let ranked: Vec<String> = self.redis.zrange(LEADERBOARD_KEY, 0, -1).await?;
+
+        for user_key in ranked {
+            if !is_user_added(&mut self.redis, &user_key).await? {
+                let _: () = self.redis.zrem(LEADERBOARD_KEY, &user_key).await?;
+            }
+        }
+
+        let leaderboard_count: isize = self.redis.zcard(LEADERBOARD_KEY).await?;
+
+        if leaderboard_count > MAX_LEADERBOARD_SIZE {
+            // Trimming makes room for one more user, which is not needed here
+            self.trim_leaderboard(leaderboard_count - 1)
+                .await
+                .context("Failed to trim leaderboard")?;
+        }
+
+        Ok(())
+    }
+
+    /// Brings the best score leaderboards in line with the sessions stored in Postgres
+    pub async fn reconcile_game_leaderboards(&mut self) -> Result<()> {
+        let pool = self.pool.clone();
+        let mut conn = pool.get().await?;
+
+        for game in GameType::ALL {
+            let best_scores: HashMap<String, i32> =
+                GameSession::get_best_scores(game, MAX_LEADERBOARD_SIZE as i64, &mut conn)
+                    .await?
+                    .into_iter()
+                    .filter_map(|(user_id, score)| {
+                        score
+                            .filter(|score| *score > 0)
+                            .map(|score| (user_id, score))
+                    })
+                    .collect();
+
+            let cached = get_game_leaderboard_entries(&mut self.redis, game).await?;
+
+            for (user_id, score) in cached {
+                let stale = best_scores
+                    .get(&user_id)
+                    .is_none_or(|best_score| score > *best_score);
+
+                if stale {
+                    let _: () = self
+                        .redis
+                        .zrem(game_leaderboard_key(game), &user_id)
+                        .await?;
+                }
+            }
+
+            for (user_id, score) in best_scores {
+                set_game_best_score(&mut self.redis, game, &user_id, score).await?;
+            }
+
+            trim_game_leaderboard(&mut self.redis, game).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Stores the active tasks and removes the ones that are no longer active
+    pub async fn reconcile_tasks(&mut self) -> Result<()> {
+        let pool = self.pool.clone();
+        let mut conn = pool.get().await?;
+
+        let active_tasks = Task::get_active(&mut conn).await?;
+        let active_ids: HashSet<&String> = active_tasks.iter().map(|task| &task.id).collect();
+
+        let stale_ids: Vec<String> = get_all_tasks(&mut self.redis)
+            .await?
+            .into_iter()
+            .map(|(task_id, _)| task_id)
+            .filter(|task_id| !active_ids.contains(task_id))
+            .collect();
+
+        remove_tasks(&mut self.redis, &stale_ids).await?;
+
+        let task_id_json_list = active_tasks
+            .iter()
+            .map(|task| (task.id.clone(), task.json_string()))
+            .collect();
+
+        set_all_tasks(&mut self.redis, task_id_json_list).await?;
+
+        Ok(())
+    }
+}
+
+/// Writes a user with their socials and completed tasks from Postgres to redis
+async fn cache_user(
+    redis: &mut ConnectionManager,
+    conn: &mut AsyncPgConnection,
+    user: &User,
+    completed_tasks: Vec<String>,
+) -> Result<()> {
+    let user_key = format!("{USER_KEY}:{}", user.user_id);
+    let user_task_key = format!("{USER_TASK_KEY}:{}", user.user_id);
+
+    let user_socials = UserSocial::get_user_socials(conn, &user.user_id)
+        .await
+        .context("Failed to get user socials")?;
+
+    let user_with_socials = UserWithSocials::from_user_social(user.clone(), user_socials);
+
+    add_new_user(
+        redis,
+        &user_key,
+        &user_task_key,
+        user_with_socials,
+        completed_tasks,
+        user.points,
+        user.referral_code.clone(),
+    )
+    .await
+}
@@ -1,11 +1,17 @@
 use anyhow::{Context, Result, anyhow};
+use db::models::FlappyScoreEvent;
 
-use crate::ws::models::{FlappyData, GameEvent, GameInProgress, WsResponse};
-use crate::ws::server::{ConnId, Server};
+use crate::ws::models::{FlappyData, GameEvent, GameInProgress, PointSource, WsResponse};
+use crate::ws::server::{ConnId, Server, validation_failed};
 use crate::ws::validator::flappy::flappy_move_valid;
 
 impl Server {
-    pub async fn flappy(&mut self, conn_id: ConnId, data: FlappyData) -> Result<()> {
+    pub async fn flappy(
+        &mut self,
+        conn_id: ConnId,
+        request_id: Option<String>,
+        data: FlappyData,
+    ) -> Result<()> {
         let user = self
             .logged_in
             .get(&conn_id)
@@ -36,28 +42,40 @@ impl Server {
 
             if let Err(e) = move_valid {
                 let to_convert = if let Some(last_move) = last_move {
-                    FlappyData::from_flappy_score_event(&last_move)
+                    FlappyData::from(&last_move)
                 } else {
                     FlappyData::new()
                 };
 
-                let to_send = WsResponse::new_flappy(to_convert);
-                if let Some(tx) = self.sessions.get(&conn_id) {
-                    let _ = tx.send(to_send.json());
-                }
+                let to_send = WsResponse::new_flappy(to_convert).with_request_id(request_id);
+                self.send_reply(conn_id, to_send);
 
-                return Err(e);
+                return Err(validation_failed(e));
             }
         }
 
         let difference_points = data.points - data.prev_points;
 
-        self.increase_point(difference_points, &user, false).await?;
+        let session_id = self
+            .game_sessions
+            .get(&conn_id)
+            .map(|session| session.get_session_id().to_string());
+
+        self.increase_point(
+            difference_points,
+            &user,
+            PointSource::GameSession,
+            session_id.as_deref(),
+        )
+        .await?;
 
         let mut game_session = self.game_sessions.get_mut(&conn_id).unwrap();
 
-        let event =
-            data.to_flappy_score_event(game_session.get_session_id().to_string(), user_id.clone());
+        let event = FlappyScoreEvent::from_data(
+            game_session.get_session_id().to_string(),
+            user_id.clone(),
+            &data,
+        );
 
         game_session.push(GameEvent::Flappy(event));
 
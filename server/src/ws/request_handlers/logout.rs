@@ -0,0 +1,37 @@
+use anyhow::{Context, Result, anyhow};
+use log::{error, info};
+
+use crate::ws::models::{RoutedWork, WsResponse};
+use crate::ws::redis_ops::{revoke_user_tokens, user_connections};
+use crate::ws::server::{ConnId, Server};
+
+impl Server {
+    /// Revokes every token of the user and closes their other connections on any instance. The
+    /// connection asking for it is closed by the caller once the response is sent.
+    pub async fn logout_everywhere(&mut self, conn_id: ConnId) -> Result<WsResponse> {
+        let user_id = self
+            .logged_in
+            .get(&conn_id)
+            .ok_or(anyhow!("{conn_id} not logged in"))?
+            .user_id
+            .clone();
+
+        revoke_user_tokens(&mut self.redis, &user_id)
+            .await
+            .context("Failed to revoke user tokens")?;
+
+        let conn_ids = user_connections(&mut self.redis, &user_id).await?;
+
+        for other_id in conn_ids.into_iter().filter(|id| *id != conn_id) {
+            if self.sessions.contains_key(&other_id) {
+                self.close_connection(other_id);
+            } else if let Err(e) = self.route_command(other_id, RoutedWork::Close).await {
+                error!("Error closing {other_id} of {user_id}. Reason: {e:?}");
+            }
+        }
+
+        info!("Logged out {user_id} everywhere");
+
+        Ok(WsResponse::logged_out())
+    }
+}
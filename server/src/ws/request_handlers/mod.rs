@@ -1,4 +1,5 @@
 mod flappy;
+mod logout;
 mod snake;
 mod start_connection;
 mod tetris;
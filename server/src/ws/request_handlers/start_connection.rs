@@ -1,13 +1,17 @@
 use anyhow::{Context, Result};
-use db::models::{TaskCompletion, User, UserSocial};
-use log::info;
-
-use crate::UserIpAgent;
-use crate::ws::jwt::{issue_token, validate_token};
-use crate::ws::models::{Chain, UserWithSocials, WsResponse};
-use crate::ws::redis_ops::{USER_KEY, USER_TASK_KEY, add_new_user, get_user_points};
+use db::models::{GameType, TaskCompletion, User, UserSocial, UserStatus};
+use log::{error, info};
+
+use crate::ws::jwt::{issue_tokens, rotate_refresh_token, validate_token};
+use crate::ws::models::{Chain, ChallengeRequest, TokenPair, UserWithSocials, WsResponse};
+use crate::ws::redis_ops::{
+    USER_KEY, USER_TASK_KEY, add_new_user, add_presence, get_user_points, resumable_games,
+    store_sign_in_message,
+};
 use crate::ws::server::Server;
-use crate::ws::{random_photo_url, verify_signature_evm, verify_signature_solana};
+use crate::ws::sign_in::{SignInMessage, verify_sign_in};
+use crate::ws::{parse_evm_address, parse_solana_key, random_photo_url};
+use crate::{INSTANCE_ID, UserIpAgent};
 
 pub type ConnId = u64;
 
@@ -18,15 +22,15 @@ impl Server {
         public_key: String,
         signature: String,
         chain: Chain,
+        nonce: String,
         ip_agent: UserIpAgent,
     ) -> Result<WsResponse> {
-        let signature_ok = match chain {
-            Chain::Solana => verify_signature_solana(&public_key, &signature).is_ok(),
-            Chain::Evm => verify_signature_evm(&public_key, &signature).is_ok(),
-        };
-
-        if !signature_ok {
-            info!("Signature verification failed for conn_id: {conn_id} wallet: {public_key}");
+        if let Err(e) =
+            verify_sign_in(&mut self.redis, chain, &public_key, &nonce, &signature).await
+        {
+            info!(
+                "Signature verification failed for conn_id: {conn_id} wallet: {public_key}. Reason: {e}"
+            );
             return Ok(WsResponse::invalid_sign());
         }
 
@@ -48,9 +52,36 @@ impl Server {
             }
         };
 
-        let new_token = issue_token(user.user_id.clone(), &ip_agent)?;
+        if user.status == UserStatus::Banned {
+            info!("Refused banned user {} on conn_id: {conn_id}", user.user_id);
+            return Ok(WsResponse::banned());
+        }
+
+        let tokens = issue_tokens(&mut self.redis, &user.user_id, &ip_agent).await?;
+
+        self.finish_connection(conn_id, user, Some(tokens), Vec::new())
+            .await
+    }
+
+    /// Hands out a message for the wallet to sign. Its nonce has to come back with the signature.
+    pub async fn sign_in_challenge(&mut self, request: ChallengeRequest) -> Result<WsResponse> {
+        let address = match request.chain {
+            Chain::Solana => parse_solana_key(&request.address).map(|_| request.address.clone()),
+            Chain::Evm => parse_evm_address(&request.address).map(|a| a.to_checksum(None)),
+        };
+
+        let Ok(address) = address else {
+            return Ok(WsResponse::invalid_request(format!(
+                "{} is not a valid {:?} address",
+                request.address, request.chain
+            )));
+        };
+
+        let message = SignInMessage::new(request.chain, address, request.chain_id)?;
 
-        self.finish_connection(conn_id, user, Some(new_token)).await
+        store_sign_in_message(&mut self.redis, &message).await?;
+
+        Ok(WsResponse::sign_in_challenge(message.challenge()))
     }
 
     pub async fn start_connection_token(
@@ -59,33 +90,81 @@ impl Server {
         token: String,
         ip_agent: UserIpAgent,
     ) -> Result<WsResponse> {
-        let result = validate_token(&token, &ip_agent);
+        let claims = match validate_token(&mut self.redis, &token, &ip_agent).await {
+            Ok(claims) => claims,
+            Err(err) => {
+                info!("Token validation failed for conn_id: {conn_id} with error: {err}");
+                return Ok(WsResponse::invalid_jwt());
+            }
+        };
 
-        if let Err(err) = result {
-            info!("Token validation failed for conn_id: {conn_id} with error: {err}");
-            return Ok(WsResponse::invalid_jwt());
-        }
+        self.resume_connection(conn_id, claims.sub, None).await
+    }
 
-        let (new_token, claims) = result.unwrap();
+    pub async fn start_connection_refresh(
+        &mut self,
+        conn_id: ConnId,
+        refresh_token: String,
+        ip_agent: UserIpAgent,
+    ) -> Result<WsResponse> {
+        let (user_id, tokens) =
+            match rotate_refresh_token(&mut self.redis, &refresh_token, &ip_agent).await {
+                Ok(rotated) => rotated,
+                Err(err) => {
+                    info!("Token refresh failed for conn_id: {conn_id} with error: {err}");
+                    return Ok(WsResponse::invalid_jwt());
+                }
+            };
 
-        let user_id = claims.sub;
+        self.resume_connection(conn_id, user_id, Some(tokens)).await
+    }
 
+    /// Logs in a user that authenticated with a token from an earlier connection
+    async fn resume_connection(
+        &mut self,
+        conn_id: ConnId,
+        user_id: String,
+        tokens: Option<TokenPair>,
+    ) -> Result<WsResponse> {
         let mut conn = self.pool.get().await?;
         let user = User::get_user(&mut conn, user_id)
             .await
-            .context("Failed to get user by solana wallet")?;
+            .context("Failed to get user by id")?;
 
         drop(conn);
 
-        self.finish_connection(conn_id, user, new_token).await
+        if user.status == UserStatus::Banned {
+            info!("Refused banned user {} on conn_id: {conn_id}", user.user_id);
+            return Ok(WsResponse::banned());
+        }
+
+        // A reconnecting client can pick up the games its dropped connection left behind
+        let resumable = resumable_games(&mut self.redis, &user.user_id)
+            .await
+            .unwrap_or_else(|e| {
+                error!(
+                    "Failed to get resumable games of {}. Reason: {e:?}",
+                    user.user_id
+                );
+                Vec::new()
+            });
+
+        self.finish_connection(conn_id, user, tokens, resumable)
+            .await
     }
 
     async fn finish_connection(
         &mut self,
         conn_id: ConnId,
         user: User,
-        new_token: Option<String>,
+        tokens: Option<TokenPair>,
+        resumable: Vec<GameType>,
     ) -> Result<WsResponse> {
+        // The entry points check already, this keeps any new one from skipping it
+        if user.status == UserStatus::Banned {
+            return Ok(WsResponse::banned());
+        }
+
         let mut conn = self.pool.get().await?;
 
         let user_socials = UserSocial::get_user_socials(&mut conn, &user.user_id)
@@ -152,17 +231,17 @@ impl Server {
 
         let user_id = user.user_id.clone();
         self.logged_in.insert(conn_id, user.clone());
-        let mut client_num = self.active_client.entry(user_id.clone()).or_insert(0);
-
-        *client_num += 1;
+        let client_num = add_presence(&mut self.redis, &INSTANCE_ID, conn_id, &user_id)
+            .await
+            .context("Failed to add connection presence")?;
 
-        if *client_num > 1 {
+        if client_num > 1 {
             info!(
                 "Total clients for user {} {:?}: {}. ",
-                user_id, user.sol_wallet, *client_num
+                user_id, user.sol_wallet, client_num
             );
         }
 
-        Ok(WsResponse::connection_started(new_token))
+        Ok(WsResponse::connection_started(tokens, resumable))
     }
 }
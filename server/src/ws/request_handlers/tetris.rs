@@ -1,32 +1,82 @@
 use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Utc};
+use db::models::GameType;
+use tokio::sync::mpsc::{self, UnboundedReceiver};
 
-use crate::ws::models::{GameEvent, GameInProgress, TetrisData, WsResponse};
-use crate::ws::server::{ConnId, Server};
-use crate::ws::validator::tetris::tetris_move_valid;
+use crate::ws::models::{
+    ErrorResponse, GameEvent, GameInProgress, PointSource, TetrisPlacement, WsResponse,
+};
+use crate::ws::server::{ConnId, GameStep, Server, validation_failed};
+use crate::ws::validator::tetris::{tetris_move_valid, tetris_start_valid};
 
 impl Server {
-    pub async fn tetris(&mut self, conn_id: ConnId, data: TetrisData) -> Result<()> {
+    async fn tetris_start(
+        &mut self,
+        conn_id: ConnId,
+        timestamp: DateTime<Utc>,
+    ) -> Result<WsResponse> {
         let user = self
             .logged_in
             .get(&conn_id)
             .ok_or(anyhow!("{conn_id} not logged in"))?
             .clone();
 
-        let user_id = &user.user_id;
+        tetris_start_valid(&timestamp).with_context(|| {
+            format!(
+                "Tetris start invalid for user {} {}.",
+                user.user_id,
+                user.sol_wallet.as_deref().unwrap_or("No Sol Wallet")
+            )
+        })?;
 
-        {
-            let game_session = if let Some(session) = self.game_sessions.get(&conn_id) {
-                session
-            } else {
-                let session = GameInProgress::new_tetris(user_id.clone(), data.timestamp);
-                self.game_sessions.insert(conn_id, session);
+        self.commit_to_db(conn_id)
+            .await
+            .context("Failed to commit previous game session")?;
 
-                self.game_sessions.get(&conn_id).unwrap()
-            };
+        let session = GameInProgress::new_tetris(user.user_id.clone(), timestamp);
+        let start = session
+            .get_last_tetris()
+            .ok_or(anyhow!("New tetris session has no starting board"))?;
 
-            let last_move = game_session.get_last_tetris();
+        self.game_sessions.insert(conn_id, session);
+
+        Ok(WsResponse::new_tetris(start))
+    }
 
-            let move_valid = tetris_move_valid(&data, &last_move).with_context(|| {
+    async fn tetris(
+        &mut self,
+        conn_id: ConnId,
+        request_id: Option<String>,
+        data: TetrisPlacement,
+    ) -> Result<WsResponse> {
+        let user = self
+            .logged_in
+            .get(&conn_id)
+            .ok_or(anyhow!("{conn_id} not logged in"))?
+            .clone();
+
+        let user_id = &user.user_id;
+
+        let new_state = {
+            let mut game_session = self
+                .game_sessions
+                .get_mut(&conn_id)
+                .ok_or(ErrorResponse::NotFound {
+                    data: "tetris session".to_string(),
+                })
+                .with_context(|| {
+                    format!("Tetris placement from {conn_id} without a started session")
+                })?;
+
+            let last_move = game_session
+                .get_last_tetris()
+                .ok_or(anyhow!("No tetris state found for {conn_id}"))?;
+
+            let engine = game_session
+                .get_tetris_engine()
+                .ok_or(anyhow!("No tetris board found for {conn_id}"))?;
+
+            let move_valid = tetris_move_valid(&data, &last_move, engine).with_context(|| {
                 format!(
                     "Tetris move invalid for user {} {}",
                     user_id,
@@ -35,37 +85,74 @@ impl Server {
             });
 
             if let Err(e) = move_valid {
-                let to_convert = if let Some(last_move) = last_move {
-                    TetrisData::from_snapshot(&last_move)
-                } else {
-                    TetrisData::new()
-                };
-
-                let to_send = WsResponse::new_tetris(to_convert);
-                if let Some(tx) = self.sessions.get(&conn_id) {
-                    let _ = tx.send(to_send.json());
-                }
-
-                return Err(e);
+                let to_send = WsResponse::new_tetris(last_move).with_request_id(request_id);
+                self.send_reply(conn_id, to_send);
+
+                return Err(validation_failed(e));
             }
-        }
 
-        let difference_points = data.points - data.prev_points;
-        self.increase_point(difference_points, &user, false).await?;
+            let (new_state, snapshot) = game_session.next_tetris(&data)?;
+            game_session.push(GameEvent::Tetris(snapshot));
+
+            new_state
+        };
+
+        let difference_points = new_state.points - new_state.prev_points;
+
+        if difference_points != 0 {
+            let session_id = self
+                .game_sessions
+                .get(&conn_id)
+                .map(|session| session.get_session_id().to_string());
+
+            self.increase_point(
+                difference_points,
+                &user,
+                PointSource::GameSession,
+                session_id.as_deref(),
+            )
+            .await?;
+        }
 
-        let (line_points, drop_points) = data.extract_points();
+        Ok(WsResponse::new_tetris(new_state))
+    }
 
-        let mut game_session = self.game_sessions.get_mut(&conn_id).unwrap();
+    /// Hands a tetris request to the queue of the connection, starting the queue on first use
+    pub fn queue_tetris(
+        &self,
+        conn_id: ConnId,
+        request_id: Option<String>,
+        step: GameStep<TetrisPlacement>,
+    ) {
+        let sender = self
+            .tetris_queue
+            .entry(conn_id)
+            .or_insert_with(|| {
+                let (sender, receiver) = mpsc::unbounded_channel();
+                tokio::spawn(self.clone().tetris_queue(conn_id, receiver));
+                sender
+            })
+            .clone();
 
-        let snapshot = data.to_tetris_snapshot(
-            game_session.get_session_id().to_string(),
-            user_id.clone(),
-            line_points,
-            drop_points,
-        );
+        let _ = sender.send((request_id, step));
+    }
 
-        game_session.push(GameEvent::Tetris(snapshot));
+    async fn tetris_queue(
+        mut self,
+        conn_id: ConnId,
+        mut receiver: UnboundedReceiver<(Option<String>, GameStep<TetrisPlacement>)>,
+    ) {
+        while let Some((request_id, step)) = receiver.recv().await {
+            let response = match step {
+                GameStep::Start { timestamp } => Some(self.tetris_start(conn_id, timestamp).await),
+                GameStep::Resume => Some(self.resume_session(conn_id, GameType::Tetris).await),
+                GameStep::Move(data) => Some(self.tetris(conn_id, request_id.clone(), data).await),
+                GameStep::End => self.commit_to_db(conn_id).await.err().map(Err),
+            };
 
-        Ok(())
+            if let Some(response) = response {
+                self.respond(conn_id, request_id, response, "Tetris");
+            }
+        }
     }
 }
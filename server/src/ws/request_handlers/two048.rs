@@ -1,32 +1,75 @@
 use anyhow::{Context, Result, anyhow};
-use log::error;
-use tokio::sync::mpsc::UnboundedReceiver;
+use chrono::{DateTime, Utc};
+use db::models::GameType;
+use tokio::sync::mpsc::{self, UnboundedReceiver};
 
-use crate::ws::models::{GameEvent, GameInProgress, Two048Data, WsResponse};
-use crate::ws::server::{ConnId, Server};
-use crate::ws::validator::two048::two048_move_valid;
+use crate::ws::models::{
+    ErrorResponse, GameEvent, GameInProgress, PointSource, Two048Move, WsResponse,
+};
+use crate::ws::server::{ConnId, GameStep, Server, validation_failed};
+use crate::ws::validator::two048::{two048_move_valid, two048_start_valid};
 
 impl Server {
-    async fn two048(&mut self, conn_id: ConnId, data: Two048Data) -> Result<()> {
+    async fn two048_start(
+        &mut self,
+        conn_id: ConnId,
+        timestamp: DateTime<Utc>,
+    ) -> Result<WsResponse> {
         let user = self
             .logged_in
             .get(&conn_id)
             .ok_or(anyhow!("{conn_id} not logged in"))?
             .clone();
 
-        let user_id = &user.user_id;
+        two048_start_valid(&timestamp).with_context(|| {
+            format!(
+                "2048 start invalid for user {} {}.",
+                user.user_id,
+                user.sol_wallet.as_deref().unwrap_or("No Sol Wallet")
+            )
+        })?;
 
-        {
-            let game_session = if let Some(session) = self.game_sessions.get(&conn_id) {
-                session
-            } else {
-                let session = GameInProgress::new_two048(user_id.clone(), data.timestamp);
-                self.game_sessions.insert(conn_id, session);
+        // Any unfinished session on this connection is closed before a new board is dealt
+        self.commit_to_db(conn_id)
+            .await
+            .context("Failed to commit previous game session")?;
 
-                self.game_sessions.get(&conn_id).unwrap()
-            };
+        let session = GameInProgress::new_two048(user.user_id.clone(), timestamp);
+        let start = session
+            .get_last_two048()
+            .ok_or(anyhow!("New 2048 session has no starting board"))?;
+
+        self.game_sessions.insert(conn_id, session);
+
+        Ok(WsResponse::new_two048(start))
+    }
+
+    async fn two048(
+        &mut self,
+        conn_id: ConnId,
+        request_id: Option<String>,
+        data: Two048Move,
+    ) -> Result<WsResponse> {
+        let user = self
+            .logged_in
+            .get(&conn_id)
+            .ok_or(anyhow!("{conn_id} not logged in"))?
+            .clone();
 
-            let last_move = game_session.get_last_two048();
+        let user_id = &user.user_id;
+
+        let new_state = {
+            let mut game_session = self
+                .game_sessions
+                .get_mut(&conn_id)
+                .ok_or(ErrorResponse::NotFound {
+                    data: "2048 session".to_string(),
+                })
+                .with_context(|| format!("2048 move from {conn_id} without a started session"))?;
+
+            let last_move = game_session
+                .get_last_two048()
+                .ok_or(anyhow!("No 2048 state found for {conn_id}"))?;
 
             let move_valid = two048_move_valid(&data, &last_move).with_context(|| {
                 format!(
@@ -37,42 +80,75 @@ impl Server {
             });
 
             if let Err(e) = move_valid {
-                let to_convert = if let Some(last_move) = last_move {
-                    last_move
-                } else {
-                    Two048Data::new()
-                };
-
-                let to_send = WsResponse::new_two048(to_convert);
-                if let Some(tx) = self.sessions.get(&conn_id) {
-                    let _ = tx.send(to_send.json());
-                }
-
-                return Err(e);
+                let to_send = WsResponse::new_two048(last_move).with_request_id(request_id);
+                self.send_reply(conn_id, to_send);
+
+                return Err(validation_failed(e));
             }
-        }
 
-        let difference_points = data.points - data.prev_points;
+            let new_state = game_session.next_two048(&data)?;
+
+            // Pushed right away so the RNG position and the stored events never drift apart
+            game_session.push(GameEvent::Two048(new_state.clone()));
+
+            new_state
+        };
+
+        let difference_points = new_state.points - new_state.prev_points;
 
         if difference_points != 0 {
-            self.increase_point(difference_points, &user, false).await?;
+            let session_id = self
+                .game_sessions
+                .get(&conn_id)
+                .map(|session| session.get_session_id().to_string());
+
+            self.increase_point(
+                difference_points,
+                &user,
+                PointSource::GameSession,
+                session_id.as_deref(),
+            )
+            .await?;
         }
 
-        let mut game_session = self.game_sessions.get_mut(&conn_id).unwrap();
+        Ok(WsResponse::new_two048(new_state))
+    }
 
-        game_session.push(GameEvent::Two048(data));
+    /// Hands a 2048 request to the queue of the connection, starting the queue on first use
+    pub fn queue_two048(
+        &self,
+        conn_id: ConnId,
+        request_id: Option<String>,
+        step: GameStep<Two048Move>,
+    ) {
+        let sender = self
+            .two048_queue
+            .entry(conn_id)
+            .or_insert_with(|| {
+                let (sender, receiver) = mpsc::unbounded_channel();
+                tokio::spawn(self.clone().two048_queue(conn_id, receiver));
+                sender
+            })
+            .clone();
 
-        Ok(())
+        let _ = sender.send((request_id, step));
     }
 
-    pub async fn two048_queue(
+    async fn two048_queue(
         mut self,
         conn_id: ConnId,
-        mut receiver: UnboundedReceiver<Two048Data>,
+        mut receiver: UnboundedReceiver<(Option<String>, GameStep<Two048Move>)>,
     ) {
-        while let Some(data) = receiver.recv().await {
-            if let Err(e) = self.two048(conn_id, data).await {
-                error!("Error handling 2048 move. Reason: {:?}", e);
+        while let Some((request_id, step)) = receiver.recv().await {
+            let response = match step {
+                GameStep::Start { timestamp } => Some(self.two048_start(conn_id, timestamp).await),
+                GameStep::Resume => Some(self.resume_session(conn_id, GameType::Two048).await),
+                GameStep::Move(data) => Some(self.two048(conn_id, request_id.clone(), data).await),
+                GameStep::End => self.commit_to_db(conn_id).await.err().map(Err),
+            };
+
+            if let Some(response) = response {
+                self.respond(conn_id, request_id, response, "Two048");
             }
         }
     }
@@ -0,0 +1,237 @@
+use anyhow::Result;
+use db::models::GameType;
+use log::error;
+use std::collections::HashMap;
+use tokio::time::{Duration, sleep};
+
+use crate::ws::models::{Encoding, LeaderboardEntry, RankedLeaderboardEntry, WsResponse};
+use crate::ws::outbox::Frame;
+use crate::ws::server::Server;
+
+/// Score changes within one tick are sent as a single delta
+const LEADERBOARD_TICK: Duration = Duration::from_millis(500);
+
+/// The last leaderboard state sent to subscribers of this instance
+#[derive(Debug, Default)]
+pub struct LeaderboardFeed {
+    pub version: u64,
+    pub entries: Vec<LeaderboardEntry>,
+    /// A score on the leaderboard changed since the last tick
+    pub dirty: bool,
+}
+
+impl Server {
+    /// Schedules a leaderboard to be rebuilt and diffed on the next tick
+    pub fn mark_leaderboard_dirty(&self, game: Option<GameType>) {
+        self.leaderboard_feeds.entry(game).or_default().dirty = true;
+    }
+
+    /// The current version and entries of a leaderboard, as the base for the deltas that follow
+    pub async fn leaderboard_snapshot(
+        &mut self,
+        game: Option<GameType>,
+    ) -> Result<(u64, Vec<LeaderboardEntry>)> {
+        if let Some(feed) = self.leaderboard_feeds.get(&game)
+            && feed.version > 0
+        {
+            return Ok((feed.version, feed.entries.clone()));
+        }
+
+        let entries = self.build_leaderboard(game).await?;
+
+        let mut feed = self.leaderboard_feeds.entry(game).or_default();
+
+        if feed.version == 0 {
+            feed.version = 1;
+            feed.entries = entries;
+        }
+
+        Ok((feed.version, feed.entries.clone()))
+    }
+
+    pub async fn broadcast_leaderboard_deltas(mut self) {
+        loop {
+            sleep(LEADERBOARD_TICK).await;
+
+            let dirty: Vec<Option<GameType>> = self
+                .leaderboard_feeds
+                .iter_mut()
+                .filter_map(|mut feed| {
+                    let dirty = feed.dirty;
+                    feed.dirty = false;
+                    dirty.then_some(*feed.key())
+                })
+                .collect();
+
+            for game in dirty {
+                if let Err(e) = self.broadcast_leaderboard_delta(game).await {
+                    error!("Failed to broadcast {game:?} leaderboard delta. Reason: {e:?}");
+                }
+            }
+        }
+    }
+
+    async fn broadcast_leaderboard_delta(&mut self, game: Option<GameType>) -> Result<()> {
+        let entries = self.build_leaderboard(game).await?;
+
+        let (version, changed, removed) = {
+            let mut feed = self.leaderboard_feeds.entry(game).or_default();
+            let (changed, removed) = leaderboard_diff(&feed.entries, &entries);
+
+            if changed.is_empty() && removed.is_empty() && feed.version > 0 {
+                return Ok(());
+            }
+
+            feed.version += 1;
+            feed.entries = entries.clone();
+
+            (feed.version, changed, removed)
+        };
+
+        let delta = WsResponse::leaderboard_delta(game, version, changed, removed);
+        let snapshot = WsResponse::leaderboard(game, version, entries);
+
+        // Encoded once per encoding in use rather than once per subscriber
+        let mut frames: HashMap<Encoding, (Frame, Frame)> = HashMap::new();
+
+        let subscribers: Vec<_> = match game {
+            Some(game) => self
+                .game_subscribed
+                .iter()
+                .filter(|entry| entry.1 == game)
+                .map(|entry| entry.0)
+                .collect(),
+            None => self.subscribed.iter().map(|id| *id).collect(),
+        };
+
+        for id in subscribers {
+            if let Some(outbox) = self.sessions.get(&id) {
+                let encoding = outbox.encoding();
+                let (delta_frame, snapshot_frame) = frames.entry(encoding).or_insert_with(|| {
+                    (
+                        Frame::encode(&delta, encoding),
+                        Frame::encode(&snapshot, encoding),
+                    )
+                });
+
+                outbox.send_leaderboard(game, delta_frame, snapshot_frame);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn build_leaderboard(&mut self, game: Option<GameType>) -> Result<Vec<LeaderboardEntry>> {
+        match game {
+            Some(game) => self.create_game_leaderboard(game).await,
+            None => self.create_leaderboard().await,
+        }
+    }
+}
+
+/// Entries that are new or moved or changed in `new`, and the user ids that left
+fn leaderboard_diff(
+    old: &[LeaderboardEntry],
+    new: &[LeaderboardEntry],
+) -> (Vec<RankedLeaderboardEntry>, Vec<String>) {
+    let old_ranks: HashMap<&str, (usize, &LeaderboardEntry)> = old
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| (entry.user.user_id.as_str(), (index, entry)))
+        .collect();
+
+    let changed = new
+        .iter()
+        .enumerate()
+        .filter(|(index, entry)| {
+            old_ranks
+                .get(entry.user.user_id.as_str())
+                .is_none_or(|(old_index, old_entry)| old_index != index || *old_entry != *entry)
+        })
+        .map(|(index, entry)| RankedLeaderboardEntry {
+            rank: index as i64 + 1,
+            entry: entry.clone(),
+        })
+        .collect();
+
+    let removed = old
+        .iter()
+        .filter(|entry| !new.iter().any(|e| e.user.user_id == entry.user.user_id))
+        .map(|entry| entry.user.user_id.clone())
+        .collect();
+
+    (changed, removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ws::models::UserProfile;
+
+    fn entry(user_id: &str, score: i32) -> LeaderboardEntry {
+        LeaderboardEntry::new(
+            score,
+            UserProfile {
+                user_id: user_id.to_string(),
+                points: score,
+                ..Default::default()
+            },
+        )
+    }
+
+    fn ranks(changed: &[RankedLeaderboardEntry]) -> Vec<(i64, &str, i32)> {
+        changed
+            .iter()
+            .map(|ranked| {
+                (
+                    ranked.rank,
+                    ranked.entry.user.user_id.as_str(),
+                    ranked.entry.score,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unchanged_board_has_no_delta() {
+        let board = vec![entry("a", 30), entry("b", 20)];
+
+        let (changed, removed) = leaderboard_diff(&board, &board);
+
+        assert!(changed.is_empty());
+        assert!(removed.is_empty());
+    }
+
+    #[test]
+    fn overtaking_moves_both_entries() {
+        let old = vec![entry("a", 30), entry("b", 20), entry("c", 10)];
+        let new = vec![entry("b", 40), entry("a", 30), entry("c", 10)];
+
+        let (changed, removed) = leaderboard_diff(&old, &new);
+
+        assert_eq!(ranks(&changed), vec![(1, "b", 40), (2, "a", 30)]);
+        assert!(removed.is_empty());
+    }
+
+    #[test]
+    fn score_change_keeps_the_rank() {
+        let old = vec![entry("a", 30), entry("b", 20)];
+        let new = vec![entry("a", 30), entry("b", 25)];
+
+        let (changed, removed) = leaderboard_diff(&old, &new);
+
+        assert_eq!(ranks(&changed), vec![(2, "b", 25)]);
+        assert!(removed.is_empty());
+    }
+
+    #[test]
+    fn leaving_entry_is_removed_and_the_rest_move_up() {
+        let old = vec![entry("a", 30), entry("b", 20), entry("c", 10)];
+        let new = vec![entry("a", 30), entry("c", 10)];
+
+        let (changed, removed) = leaderboard_diff(&old, &new);
+
+        assert_eq!(ranks(&changed), vec![(2, "c", 10)]);
+        assert_eq!(removed, vec!["b".to_string()]);
+    }
+}
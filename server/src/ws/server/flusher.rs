@@ -0,0 +1,137 @@
+use anyhow::Result;
+use db::models::User;
+use diesel_async::AsyncPgConnection;
+use log::{error, info};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::time::{Duration, sleep};
+
+use crate::ws::redis_ops::{
+    USER_KEY, clear_dirty_if_unchanged, delete_dirty_user, get_user_points, scan_dirty_users,
+};
+use crate::ws::server::Server;
+use crate::ws::utils::send_alert;
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Dirty users read and written to Postgres per step
+const FLUSH_BATCH: usize = 100;
+
+/// Counters of the dirty point flushes since this instance started
+#[derive(Debug, Default)]
+pub struct FlushMetrics {
+    pub runs: AtomicU64,
+    pub flushed: AtomicU64,
+    pub failed: AtomicU64,
+    pub mismatches: AtomicU64,
+}
+
+impl Server {
+    /// Writes the points of dirty users to Postgres on an interval so long sessions don't keep
+    /// them only in redis until the user disconnects.
+    pub async fn flush_dirty_points(mut self) {
+        loop {
+            sleep(FLUSH_INTERVAL).await;
+
+            if let Err(e) = self.flush_dirty_round().await {
+                error!("Error flushing dirty points. Reason: {e:?}");
+            }
+        }
+    }
+
+    /// Writes the points of every dirty user to Postgres once. Cached users are never removed
+    /// here, other instances may still be serving them.
+    pub async fn flush_dirty_round(&mut self) -> Result<()> {
+        let mut cursor = 0;
+        let mut flushed = 0;
+        let mut failed = 0;
+
+        loop {
+            let (next_cursor, user_ids) =
+                scan_dirty_users(&mut self.redis, cursor, FLUSH_BATCH).await?;
+
+            let pool = self.pool.clone();
+            let mut conn = pool.get().await?;
+
+            for user_id in user_ids {
+                match self.flush_user_points(&mut conn, &user_id).await {
+                    Ok(()) => flushed += 1,
+                    Err(e) => {
+                        error!("Error while flushing dirty points of {user_id}. Reason: {e:?}");
+                        failed += 1;
+                    }
+                }
+            }
+
+            if next_cursor == 0 {
+                break;
+            }
+            cursor = next_cursor;
+        }
+
+        let metrics = &self.flush_metrics;
+        metrics.runs.fetch_add(1, Ordering::Relaxed);
+        metrics.flushed.fetch_add(flushed, Ordering::Relaxed);
+        metrics.failed.fetch_add(failed, Ordering::Relaxed);
+
+        if flushed > 0 || failed > 0 {
+            info!(
+                "Flushed dirty points of {flushed} users, {failed} failed. Totals: {} flushed, {} failed, {} mismatches over {} runs",
+                metrics.flushed.load(Ordering::Relaxed),
+                metrics.failed.load(Ordering::Relaxed),
+                metrics.mismatches.load(Ordering::Relaxed),
+                metrics.runs.load(Ordering::Relaxed),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Writes the cached points of one user to Postgres and clears their dirty flag. Postgres is
+    /// never lowered: a cached balance below it is alerted on and the stored points are kept.
+    pub async fn flush_user_points(
+        &mut self,
+        conn: &mut AsyncPgConnection,
+        user_id: &str,
+    ) -> Result<()> {
+        let user_key = format!("{USER_KEY}:{user_id}");
+
+        let Some(points) = get_user_points(&mut self.redis, &user_key).await? else {
+            error!("No points found for dirty user {user_id}");
+            delete_dirty_user(&mut self.redis, user_id).await?;
+            return Ok(());
+        };
+
+        let updated_user = User::raise_points(conn, user_id, points).await?;
+
+        if updated_user.points != points {
+            self.points_mismatch(&updated_user, points).await;
+        }
+
+        // Points earned since the read keep the user dirty for the next round
+        clear_dirty_if_unchanged(&mut self.redis, &user_key, user_id, points).await?;
+
+        Ok(())
+    }
+
+    /// Redis had fewer points than Postgres so the flush was refused. Should never happen, so it
+    /// is counted and raised as an alert.
+    pub async fn points_mismatch(&self, updated_user: &User, redis_points: i32) {
+        self.flush_metrics
+            .mismatches
+            .fetch_add(1, Ordering::Relaxed);
+
+        let message = format!(
+            "Points mismatch for user {} {}: redis points {redis_points}, db points {}",
+            updated_user.user_id,
+            updated_user
+                .sol_wallet
+                .as_deref()
+                .unwrap_or("No Sol Wallet"),
+            updated_user.points
+        );
+
+        if let Err(e) = send_alert(&message).await {
+            error!("Failed to send alert. Reason: {e:?}");
+        }
+    }
+}
@@ -1,15 +1,16 @@
-use actix_ws::{AggregatedMessage, MessageStream, Session};
+use actix_ws::{AggregatedMessage, CloseCode, CloseReason, MessageStream, Session};
 use futures_util::StreamExt;
 use log::{error, info};
 use std::pin::pin;
+use std::sync::atomic::Ordering;
 use std::time::{Duration, Instant};
 use tokio::select;
-use tokio::sync::mpsc;
 use tokio::time::interval;
 
-use crate::UserIpAgent;
-use crate::ws::models::{AuthPayload, Request};
+use crate::ws::models::{AuthPayload, Encoding, PROTOCOL_VERSION, Request, RequestEnvelope};
+use crate::ws::outbox::{Frame, outbox};
 use crate::ws::server::{ConnId, ServerInterface};
+use crate::{SHUTTING_DOWN, UserIpAgent};
 
 /// How often heartbeat pings are sent
 const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
@@ -33,13 +34,14 @@ pub async fn handle_ws(
     mut session: Session,
     msg_stream: MessageStream,
     ip_agent: UserIpAgent,
+    encoding: Encoding,
 ) {
     let mut last_heartbeat = Instant::now();
     let mut interval = interval(HEARTBEAT_INTERVAL);
 
-    let (conn_tx, mut conn_rx) = mpsc::unbounded_channel();
+    let (conn_tx, mut conn_rx) = outbox(encoding);
 
-    let conn_id = interface.connect(conn_tx).await;
+    let conn_id = interface.connect(conn_tx, ip_agent.ip.clone()).await;
     let _guard = ConnectionGuard {
         interface: &interface,
         conn_id,
@@ -72,11 +74,21 @@ pub async fn handle_ws(
                     }
 
                     AggregatedMessage::Text(text) => {
-                        process_text_msg(&interface, &text, conn_id, &ip_agent);
+                        let request = RequestEnvelope::from_json(&text).map_err(|e| {
+                            error!("{text} from {conn_id} is not a valid command");
+                            e.to_string()
+                        });
+
+                        process_request(&interface, request, conn_id, &ip_agent);
                     }
 
-                    AggregatedMessage::Binary(_bin) => {
-                        log::warn!("unexpected binary message");
+                    AggregatedMessage::Binary(bin) => {
+                        let request = RequestEnvelope::from_msgpack(&bin).map_err(|e| {
+                            error!("{} bytes from {conn_id} are not a valid command", bin.len());
+                            e.to_string()
+                        });
+
+                        process_request(&interface, request, conn_id, &ip_agent);
                     }
 
                     AggregatedMessage::Close(reason) => {
@@ -85,9 +97,19 @@ pub async fn handle_ws(
                 }
             }
 
-            // An internal message to be sent to the client
-            Some(chat_msg) = msg_rx => {
-                if session.text(chat_msg).await.is_err() {
+            // An internal message to be sent to the client. The channel only closes early when
+            // the server drops the connection or is shutting down.
+            chat_msg = msg_rx => {
+                let sent = match chat_msg {
+                    Some(Frame::Text(text)) => session.text(text.to_string()).await,
+                    Some(Frame::Binary(bytes)) => session.binary(bytes.to_vec()).await,
+                    None if SHUTTING_DOWN.load(Ordering::SeqCst) => {
+                        break Some(CloseReason::from(CloseCode::Restart));
+                    }
+                    None => break Some(CloseReason::from(CloseCode::Policy)),
+                };
+
+                if sent.is_err() {
                     break None;
                 }
             }
@@ -123,88 +145,140 @@ pub async fn handle_ws(
     let _ = session.close(close_reason).await;
 }
 
-fn process_text_msg(
+fn process_request(
     interface: &ServerInterface,
-    text: &str,
+    request: Result<RequestEnvelope, String>,
     conn_id: ConnId,
     ip_agent: &UserIpAgent,
 ) {
-    let Ok(request) = Request::from_json(text) else {
-        error!("{text} from {conn_id} is not a valid command");
-        return;
+    let RequestEnvelope {
+        request,
+        request_id,
+    } = match request {
+        Ok(envelope) => envelope,
+        Err(reason) => {
+            interface.invalid_request(conn_id, reason);
+            return;
+        }
     };
 
     match request {
-        Request::Auth { data } => match data {
-            AuthPayload::Signed {
-                public_key,
-                signature,
-                chain,
-            } => {
-                interface.auth(conn_id, public_key, signature, chain, ip_agent.clone());
+        Request::SignInChallenge { data } => {
+            interface.sign_in_challenge(conn_id, request_id, data);
+        }
+        Request::Auth { data } => {
+            if data
+                .protocol_version()
+                .is_some_and(|version| version != PROTOCOL_VERSION)
+            {
+                interface.unsupported_protocol(conn_id, request_id);
+                return;
             }
-            AuthPayload::Token { token } => {
-                interface.auth_token(conn_id, token, ip_agent.clone());
+
+            match data {
+                AuthPayload::Signed {
+                    public_key,
+                    signature,
+                    chain,
+                    nonce,
+                    ..
+                } => {
+                    interface.auth(
+                        conn_id,
+                        request_id,
+                        public_key,
+                        signature,
+                        chain,
+                        nonce,
+                        ip_agent.clone(),
+                    );
+                }
+                AuthPayload::Token { token, .. } => {
+                    interface.auth_token(conn_id, request_id, token, ip_agent.clone());
+                }
+                AuthPayload::Refresh { refresh_token, .. } => {
+                    interface.auth_refresh(conn_id, request_id, refresh_token, ip_agent.clone());
+                }
             }
-        },
+        }
         Request::InitialPoints => {
-            interface.initial_points(conn_id);
+            interface.initial_points(conn_id, request_id);
         }
         Request::Me => {
-            interface.me(conn_id);
+            interface.me(conn_id, request_id);
         }
         Request::MeWithRankSocials => {
-            interface.me_with_rank_socials(conn_id);
+            interface.me_with_rank_socials(conn_id, request_id);
         }
         Request::GetActivity => {
-            interface.get_activity(conn_id);
+            interface.get_activity(conn_id, request_id);
+        }
+        Request::TetrisStart { data } => {
+            interface.tetris_start(conn_id, request_id, data);
         }
         Request::Tetris { data } => {
-            interface.tetris(conn_id, data);
+            interface.tetris(conn_id, request_id, data);
         }
         Request::TetrisEnd => {
-            interface.tetris_end(conn_id);
+            interface.tetris_end(conn_id, request_id);
         }
         Request::Snake { data } => {
-            interface.snake(conn_id, data);
+            interface.snake(conn_id, request_id, data);
         }
         Request::SnakeEnd => {
-            interface.snake_end(conn_id);
+            interface.snake_end(conn_id, request_id);
+        }
+        Request::Two048Start { data } => {
+            interface.two048_start(conn_id, request_id, data);
         }
         Request::Two048 { data } => {
-            interface.two048(conn_id, data);
+            interface.two048(conn_id, request_id, data);
         }
         Request::Two048End => {
-            interface.two048_end(conn_id);
+            interface.two048_end(conn_id, request_id);
         }
         Request::Flappy { data } => {
-            interface.flappy(conn_id, data);
+            interface.flappy(conn_id, request_id, data);
         }
         Request::FlappyEnd => {
-            interface.flappy_end(conn_id);
+            interface.flappy_end(conn_id, request_id);
         }
-        Request::LeaderboardIn => {
-            interface.leaderboard_in(conn_id);
+        Request::LeaderboardIn { game } => {
+            interface.leaderboard_in(conn_id, request_id, game);
         }
-        Request::LeaderboardOut => {
-            interface.leaderboard_out(conn_id);
+        Request::LeaderboardOut { game } => {
+            interface.leaderboard_out(conn_id, request_id, game);
+        }
+        Request::LeaderboardPage { data } => {
+            interface.leaderboard_page(conn_id, request_id, data);
+        }
+        Request::LeaderboardAround { count } => {
+            interface.leaderboard_around(conn_id, request_id, count);
+        }
+        Request::WindowLeaderboard { window } => {
+            interface.window_leaderboard(conn_id, request_id, window);
         }
         Request::UsernameUpdate { data } => {
-            interface.username_update(conn_id, data);
+            interface.username_update(conn_id, request_id, data);
         }
         Request::SocialLinks => {
-            interface.social_links(conn_id);
+            interface.social_links(conn_id, request_id);
         }
         Request::Telegram { data } => {
-            interface.telegram(conn_id, data);
+            interface.telegram(conn_id, request_id, data);
         }
         Request::Tasks => {
-            interface.tasks(conn_id);
+            interface.tasks(conn_id, request_id);
         }
         Request::CheckTask { data } => {
-            interface.check_task(conn_id, data);
+            interface.check_task(conn_id, request_id, data);
+        }
+        Request::CheckReferral { data } => {
+            interface.check_referral_status(conn_id, request_id, data)
         }
-        Request::CheckReferral { data } => interface.check_referral_status(conn_id, data),
-        Request::BindWallet { data } => interface.bind_wallet(conn_id, data),
+        Request::BindWallet { data } => interface.bind_wallet(conn_id, request_id, data),
+        Request::GetReplay { session_id } => interface.get_replay(conn_id, request_id, session_id),
+        Request::ResumeSession { game } => interface.resume_session(conn_id, request_id, game),
+        Request::LogoutEverywhere => interface.logout_everywhere(conn_id, request_id),
     }
 }
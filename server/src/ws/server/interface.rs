@@ -1,10 +1,15 @@
+use chrono::{DateTime, Utc};
+use db::models::{GameType, LeaderboardWindow};
 use tokio::sync::{mpsc::UnboundedSender, oneshot};
 
 use crate::UserIpAgent;
-use crate::ws::models::{BindWallet, Chain, TaskCheck, TelegramUser};
+use crate::ws::models::{
+    BindWallet, Chain, ChallengeRequest, LeaderboardPageQuery, TaskCheck, TelegramUser,
+};
+use crate::ws::outbox::Outbox;
 use crate::ws::{
-    models::{FlappyData, SnakeData, TetrisData, Two048Data},
-    server::{Command, ConnId, Work},
+    models::{FlappyData, SnakeData, TetrisPlacement, Two048Move},
+    server::{Command, ConnId, Origin, Work},
 };
 
 #[derive(Clone)]
@@ -13,12 +18,18 @@ pub struct ServerInterface {
 }
 
 impl ServerInterface {
-    pub async fn connect(&self, conn_tx: UnboundedSender<String>) -> ConnId {
+    pub async fn connect(&self, conn_tx: Outbox, ip: String) -> ConnId {
         let (sender, receiver) = oneshot::channel();
 
         let command = Command {
             conn_id: 0,
-            work: Work::Connect { conn_tx, sender },
+            request_id: None,
+            origin: Origin::Socket,
+            work: Work::Connect {
+                conn_tx,
+                sender,
+                ip,
+            },
         };
         self.cmd_tx.send(command).unwrap();
 
@@ -28,204 +39,469 @@ impl ServerInterface {
     pub fn disconnect(&self, conn_id: ConnId) {
         let command = Command {
             conn_id,
+            request_id: None,
+            origin: Origin::Socket,
             work: Work::Disconnect,
         };
         self.cmd_tx.send(command).unwrap();
     }
 
+    /// Closes a connection held by any instance
+    pub fn close(&self, conn_id: ConnId) {
+        let command = Command {
+            conn_id,
+            request_id: None,
+            origin: Origin::Server,
+            work: Work::Close,
+        };
+        self.cmd_tx.send(command).unwrap();
+    }
+
+    pub fn sign_in_challenge(
+        &self,
+        conn_id: ConnId,
+        request_id: Option<String>,
+        data: ChallengeRequest,
+    ) {
+        let command = Command {
+            conn_id,
+            request_id,
+            origin: Origin::Socket,
+            work: Work::SignInChallenge { data },
+        };
+        self.cmd_tx.send(command).unwrap();
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn auth(
         &self,
         conn_id: ConnId,
+        request_id: Option<String>,
         public_key: String,
         signature: String,
         chain: Chain,
+        nonce: String,
         ip_agent: UserIpAgent,
     ) {
         let command = Command {
             conn_id,
+            request_id,
+            origin: Origin::Socket,
             work: Work::Auth {
                 public_key,
                 signature,
                 chain,
+                nonce,
                 ip_agent,
             },
         };
         self.cmd_tx.send(command).unwrap();
     }
 
-    pub fn auth_token(&self, conn_id: ConnId, token: String, ip_agent: UserIpAgent) {
+    pub fn auth_token(
+        &self,
+        conn_id: ConnId,
+        request_id: Option<String>,
+        token: String,
+        ip_agent: UserIpAgent,
+    ) {
         let command = Command {
             conn_id,
+            request_id,
+            origin: Origin::Socket,
             work: Work::AuthToken { token, ip_agent },
         };
         self.cmd_tx.send(command).unwrap();
     }
 
-    pub fn leaderboard_in(&self, conn_id: ConnId) {
+    pub fn auth_refresh(
+        &self,
+        conn_id: ConnId,
+        request_id: Option<String>,
+        refresh_token: String,
+        ip_agent: UserIpAgent,
+    ) {
+        let command = Command {
+            conn_id,
+            request_id,
+            origin: Origin::Socket,
+            work: Work::AuthRefresh {
+                refresh_token,
+                ip_agent,
+            },
+        };
+        self.cmd_tx.send(command).unwrap();
+    }
+
+    pub fn logout_everywhere(&self, conn_id: ConnId, request_id: Option<String>) {
         let command = Command {
             conn_id,
-            work: Work::LeaderboardIn,
+            request_id,
+            origin: Origin::Socket,
+            work: Work::LogoutEverywhere,
         };
         self.cmd_tx.send(command).unwrap();
     }
 
-    pub fn leaderboard_out(&self, conn_id: ConnId) {
+    pub fn leaderboard_in(
+        &self,
+        conn_id: ConnId,
+        request_id: Option<String>,
+        game: Option<GameType>,
+    ) {
         let command = Command {
             conn_id,
-            work: Work::LeaderboardOut,
+            request_id,
+            origin: Origin::Socket,
+            work: Work::LeaderboardIn { game },
         };
         self.cmd_tx.send(command).unwrap();
     }
 
-    pub fn initial_points(&self, conn_id: ConnId) {
+    pub fn leaderboard_out(
+        &self,
+        conn_id: ConnId,
+        request_id: Option<String>,
+        game: Option<GameType>,
+    ) {
+        let command = Command {
+            conn_id,
+            request_id,
+            origin: Origin::Socket,
+            work: Work::LeaderboardOut { game },
+        };
+        self.cmd_tx.send(command).unwrap();
+    }
+
+    pub fn leaderboard_page(
+        &self,
+        conn_id: ConnId,
+        request_id: Option<String>,
+        data: LeaderboardPageQuery,
+    ) {
+        let command = Command {
+            conn_id,
+            request_id,
+            origin: Origin::Socket,
+            work: Work::LeaderboardPage { data },
+        };
+        self.cmd_tx.send(command).unwrap();
+    }
+
+    pub fn leaderboard_around(
+        &self,
+        conn_id: ConnId,
+        request_id: Option<String>,
+        count: Option<i64>,
+    ) {
+        let command = Command {
+            conn_id,
+            request_id,
+            origin: Origin::Socket,
+            work: Work::LeaderboardAround { count },
+        };
+        self.cmd_tx.send(command).unwrap();
+    }
+
+    pub fn window_leaderboard(
+        &self,
+        conn_id: ConnId,
+        request_id: Option<String>,
+        window: LeaderboardWindow,
+    ) {
         let command = Command {
             conn_id,
+            request_id,
+            origin: Origin::Socket,
+            work: Work::WindowLeaderboard { window },
+        };
+        self.cmd_tx.send(command).unwrap();
+    }
+
+    pub fn initial_points(&self, conn_id: ConnId, request_id: Option<String>) {
+        let command = Command {
+            conn_id,
+            request_id,
+            origin: Origin::Socket,
             work: Work::InitialPoints,
         };
         self.cmd_tx.send(command).unwrap();
     }
 
-    pub fn tetris(&self, conn_id: ConnId, data: TetrisData) {
+    pub fn tetris_start(
+        &self,
+        conn_id: ConnId,
+        request_id: Option<String>,
+        timestamp: DateTime<Utc>,
+    ) {
         let command = Command {
             conn_id,
+            request_id,
+            origin: Origin::Socket,
+            work: Work::TetrisStart { timestamp },
+        };
+        self.cmd_tx.send(command).unwrap();
+    }
+
+    pub fn tetris(&self, conn_id: ConnId, request_id: Option<String>, data: TetrisPlacement) {
+        let command = Command {
+            conn_id,
+            request_id,
+            origin: Origin::Socket,
             work: Work::Tetris { data },
         };
         self.cmd_tx.send(command).unwrap();
     }
 
-    pub fn tetris_end(&self, conn_id: ConnId) {
+    pub fn tetris_end(&self, conn_id: ConnId, request_id: Option<String>) {
         let command = Command {
             conn_id,
+            request_id,
+            origin: Origin::Socket,
             work: Work::TetrisEnd,
         };
         self.cmd_tx.send(command).unwrap();
     }
 
-    pub fn snake(&self, conn_id: ConnId, data: SnakeData) {
+    pub fn snake(&self, conn_id: ConnId, request_id: Option<String>, data: SnakeData) {
         let command = Command {
             conn_id,
+            request_id,
+            origin: Origin::Socket,
             work: Work::Snake { data },
         };
         self.cmd_tx.send(command).unwrap();
     }
 
-    pub fn snake_end(&self, conn_id: ConnId) {
+    pub fn snake_end(&self, conn_id: ConnId, request_id: Option<String>) {
         let command = Command {
             conn_id,
+            request_id,
+            origin: Origin::Socket,
             work: Work::SnakeEnd,
         };
         self.cmd_tx.send(command).unwrap();
     }
 
-    pub fn two048(&self, conn_id: ConnId, data: Two048Data) {
+    pub fn two048_start(
+        &self,
+        conn_id: ConnId,
+        request_id: Option<String>,
+        timestamp: DateTime<Utc>,
+    ) {
+        let command = Command {
+            conn_id,
+            request_id,
+            origin: Origin::Socket,
+            work: Work::Two048Start { timestamp },
+        };
+        self.cmd_tx.send(command).unwrap();
+    }
+
+    pub fn two048(&self, conn_id: ConnId, request_id: Option<String>, data: Two048Move) {
         let command = Command {
             conn_id,
+            request_id,
+            origin: Origin::Socket,
             work: Work::Two048 { data },
         };
         self.cmd_tx.send(command).unwrap();
     }
 
-    pub fn two048_end(&self, conn_id: ConnId) {
+    pub fn two048_end(&self, conn_id: ConnId, request_id: Option<String>) {
         let command = Command {
             conn_id,
+            request_id,
+            origin: Origin::Socket,
             work: Work::Two048End,
         };
         self.cmd_tx.send(command).unwrap();
     }
 
-    pub fn flappy(&self, conn_id: ConnId, data: FlappyData) {
+    pub fn flappy(&self, conn_id: ConnId, request_id: Option<String>, data: FlappyData) {
         let command = Command {
             conn_id,
+            request_id,
+            origin: Origin::Socket,
             work: Work::Flappy { data },
         };
         self.cmd_tx.send(command).unwrap();
     }
 
-    pub fn flappy_end(&self, conn_id: ConnId) {
+    pub fn flappy_end(&self, conn_id: ConnId, request_id: Option<String>) {
         let command = Command {
             conn_id,
+            request_id,
+            origin: Origin::Socket,
             work: Work::FlappyEnd,
         };
         self.cmd_tx.send(command).unwrap();
     }
 
-    pub fn me(&self, conn_id: ConnId) {
+    pub fn me(&self, conn_id: ConnId, request_id: Option<String>) {
         let command = Command {
             conn_id,
+            request_id,
+            origin: Origin::Socket,
             work: Work::Me,
         };
         self.cmd_tx.send(command).unwrap();
     }
 
-    pub fn me_with_rank_socials(&self, conn_id: ConnId) {
+    pub fn me_with_rank_socials(&self, conn_id: ConnId, request_id: Option<String>) {
+        let command = Command {
+            conn_id,
+            request_id,
+            origin: Origin::Socket,
+            work: Work::MeWithRankSocials,
+        };
+        self.cmd_tx.send(command).unwrap();
+    }
+
+    /// Sends the profile to a connection after it changed outside the socket
+    pub fn push_me_with_rank_socials(&self, conn_id: ConnId) {
         let command = Command {
             conn_id,
+            request_id: None,
+            origin: Origin::Server,
             work: Work::MeWithRankSocials,
         };
         self.cmd_tx.send(command).unwrap();
     }
 
-    pub fn get_activity(&self, conn_id: ConnId) {
+    pub fn get_activity(&self, conn_id: ConnId, request_id: Option<String>) {
         let command = Command {
             conn_id,
+            request_id,
+            origin: Origin::Socket,
             work: Work::GetActivity,
         };
         self.cmd_tx.send(command).unwrap();
     }
 
-    pub fn username_update(&self, conn_id: ConnId, data: String) {
+    pub fn username_update(&self, conn_id: ConnId, request_id: Option<String>, data: String) {
         let command = Command {
             conn_id,
+            request_id,
+            origin: Origin::Socket,
             work: Work::UsernameUpdate { data },
         };
         self.cmd_tx.send(command).unwrap();
     }
 
-    pub fn social_links(&self, conn_id: ConnId) {
+    pub fn social_links(&self, conn_id: ConnId, request_id: Option<String>) {
         let command = Command {
             conn_id,
+            request_id,
+            origin: Origin::Socket,
             work: Work::SocialLinks,
         };
         self.cmd_tx.send(command).unwrap();
     }
 
-    pub fn telegram(&self, conn_id: ConnId, data: TelegramUser) {
+    pub fn telegram(&self, conn_id: ConnId, request_id: Option<String>, data: TelegramUser) {
         let command = Command {
             conn_id,
+            request_id,
+            origin: Origin::Socket,
             work: Work::Telegram { data },
         };
         self.cmd_tx.send(command).unwrap();
     }
 
-    pub fn tasks(&self, conn_id: ConnId) {
+    pub fn tasks(&self, conn_id: ConnId, request_id: Option<String>) {
         let command = Command {
             conn_id,
+            request_id,
+            origin: Origin::Socket,
             work: Work::Tasks,
         };
         self.cmd_tx.send(command).unwrap();
     }
 
-    pub fn check_task(&self, conn_id: ConnId, data: TaskCheck) {
+    /// Sends the task list to a connection after it changed outside the socket
+    pub fn push_tasks(&self, conn_id: ConnId) {
         let command = Command {
             conn_id,
+            request_id: None,
+            origin: Origin::Server,
+            work: Work::Tasks,
+        };
+        self.cmd_tx.send(command).unwrap();
+    }
+
+    pub fn check_task(&self, conn_id: ConnId, request_id: Option<String>, data: TaskCheck) {
+        let command = Command {
+            conn_id,
+            request_id,
+            origin: Origin::Socket,
             work: Work::CheckTask { data },
         };
         self.cmd_tx.send(command).unwrap();
     }
 
-    pub fn check_referral_status(&self, conn_id: ConnId, referral_code: String) {
+    pub fn check_referral_status(
+        &self,
+        conn_id: ConnId,
+        request_id: Option<String>,
+        referral_code: String,
+    ) {
         let command = Command {
             conn_id,
+            request_id,
+            origin: Origin::Socket,
             work: Work::CheckReferralStatus { referral_code },
         };
         self.cmd_tx.send(command).unwrap();
     }
 
-    pub fn bind_wallet(&self, conn_id: ConnId, data: BindWallet) {
+    pub fn bind_wallet(&self, conn_id: ConnId, request_id: Option<String>, data: BindWallet) {
         let command = Command {
             conn_id,
+            request_id,
+            origin: Origin::Socket,
             work: Work::BindWallet { data },
         };
         self.cmd_tx.send(command).unwrap();
     }
+
+    pub fn get_replay(&self, conn_id: ConnId, request_id: Option<String>, session_id: String) {
+        let command = Command {
+            conn_id,
+            request_id,
+            origin: Origin::Socket,
+            work: Work::GetReplay { session_id },
+        };
+        self.cmd_tx.send(command).unwrap();
+    }
+
+    pub fn resume_session(&self, conn_id: ConnId, request_id: Option<String>, game: GameType) {
+        let command = Command {
+            conn_id,
+            request_id,
+            origin: Origin::Socket,
+            work: Work::ResumeSession { game },
+        };
+        self.cmd_tx.send(command).unwrap();
+    }
+
+    pub fn unsupported_protocol(&self, conn_id: ConnId, request_id: Option<String>) {
+        let command = Command {
+            conn_id,
+            request_id,
+            origin: Origin::Socket,
+            work: Work::UnsupportedProtocol,
+        };
+        self.cmd_tx.send(command).unwrap();
+    }
+
+    pub fn invalid_request(&self, conn_id: ConnId, reason: String) {
+        let command = Command {
+            conn_id,
+            request_id: None,
+            origin: Origin::Socket,
+            work: Work::InvalidRequest { reason },
+        };
+        self.cmd_tx.send(command).unwrap();
+    }
 }
@@ -1,8 +1,17 @@
+mod deltas;
 mod events;
+mod flusher;
 pub mod handler;
 mod interface;
+mod presence;
 mod responder;
+mod resume;
+mod shutdown;
+mod throttle;
+mod windows;
 mod work;
 
+pub use deltas::LeaderboardFeed;
+pub use flusher::FlushMetrics;
 pub use interface::*;
 pub use responder::*;
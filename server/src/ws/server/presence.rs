@@ -0,0 +1,90 @@
+use anyhow::{Context, Result};
+use db::models::User;
+use log::{error, info};
+use redis::AsyncCommands;
+use tokio::time::{Duration, sleep};
+
+use crate::INSTANCE_ID;
+use crate::ws::models::{RoutedCommand, RoutedWork};
+use crate::ws::redis_ops::{get_conn_presence, instance_channel, refresh_presence};
+use crate::ws::server::{Command, ConnId, Origin, Server};
+
+/// Must stay well below `PRESENCE_TTL` so live connections never expire
+const PRESENCE_HEARTBEAT: Duration = Duration::from_secs(10);
+
+impl Server {
+    /// Keeps the redis presence of every connection logged in to this instance alive
+    pub async fn presence_heartbeat(mut self) {
+        info!("Starting presence heartbeat for instance {}", *INSTANCE_ID);
+
+        loop {
+            sleep(PRESENCE_HEARTBEAT).await;
+
+            let entries: Vec<(ConnId, String)> = self
+                .logged_in
+                .iter()
+                .map(|entry| (*entry.key(), entry.value().user_id.clone()))
+                .collect();
+
+            if let Err(e) = refresh_presence(&mut self.redis, &INSTANCE_ID, &entries).await {
+                error!("Failed to refresh presence. Reason: {:?}", e);
+            }
+        }
+    }
+
+    /// Forwards work to the instance holding the connection. Dropped if the connection is gone.
+    pub async fn route_command(&mut self, conn_id: ConnId, work: RoutedWork) -> Result<()> {
+        let Some((instance_id, _)) = get_conn_presence(&mut self.redis, conn_id).await? else {
+            info!("Dropping {work:?} for {conn_id}, the connection is gone");
+            return Ok(());
+        };
+
+        // The socket was held here but has closed in the meantime
+        if instance_id == *INSTANCE_ID {
+            return Ok(());
+        }
+
+        let payload = serde_json::to_string(&RoutedCommand { conn_id, work })?;
+
+        let _: () = self
+            .redis
+            .publish(instance_channel(&instance_id), payload)
+            .await
+            .context("Failed to publish routed command")?;
+
+        Ok(())
+    }
+
+    /// Runs work another instance forwarded for one of our connections
+    pub fn handle_routed_command(&self, message: &str) -> Result<()> {
+        let routed: RoutedCommand =
+            serde_json::from_str(message).context("Failed to parse routed command")?;
+
+        let command = Command {
+            conn_id: routed.conn_id,
+            request_id: None,
+            origin: Origin::Server,
+            work: routed.work.into(),
+        };
+
+        tokio::spawn(self.clone().handle_command(command));
+
+        Ok(())
+    }
+
+    /// The user behind a connection on any instance
+    pub async fn conn_user(&mut self, conn_id: ConnId) -> Result<Option<User>> {
+        if let Some(user) = self.logged_in.get(&conn_id) {
+            return Ok(Some(user.clone()));
+        }
+
+        let Some((_, user_id)) = get_conn_presence(&mut self.redis, conn_id).await? else {
+            return Ok(None);
+        };
+
+        let mut conn = self.pool.get().await?;
+        let user = User::get_user(&mut conn, user_id).await?;
+
+        Ok(Some(user))
+    }
+}
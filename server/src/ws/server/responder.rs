@@ -1,45 +1,65 @@
+use anyhow::{Error, Result};
+use chrono::{DateTime, Utc};
 use dashmap::{DashMap, DashSet};
-use db::models::User;
+use db::models::{GameType, LeaderboardWindow, User};
 use diesel_async::AsyncPgConnection;
 use diesel_async::pooled_connection::bb8::Pool;
-use log::error;
+use log::{error, warn};
 use mpsc::{UnboundedReceiver, UnboundedSender};
 use redis::AsyncCommands;
 use redis::aio::ConnectionManager;
 use std::io;
 use std::sync::Arc;
+use std::sync::atomic::Ordering;
 use tokio::sync::{mpsc, oneshot};
 use tokio::time::{Duration, sleep};
 
-use crate::UserIpAgent;
-use crate::auth::CodeVerifier;
 use crate::ws::models::{
-    BindWallet, Chain, FlappyData, GameInProgress, SnakeData, TaskCheck, TelegramUser, TetrisData,
-    Two048Data, WsResponse,
+    BindWallet, Chain, ChallengeRequest, ErrorResponse, FlappyData, GameInProgress,
+    LeaderboardPageQuery, RoutedWork, SnakeData, TaskCheck, TelegramUser, TetrisPlacement,
+    Two048Move, WsResponse,
 };
-use crate::ws::server::ServerInterface;
+use crate::ws::outbox::Outbox;
+use crate::ws::rate_limit::{RateLimiter, RateLimits};
+use crate::ws::server::{FlushMetrics, LeaderboardFeed, ServerInterface};
+use crate::{SHUTTING_DOWN, UserIpAgent};
 
 pub type ConnId = u64;
 
 #[derive(Clone)]
 pub struct Server {
-    pub sessions: Arc<DashMap<ConnId, UnboundedSender<String>>>,
+    pub sessions: Arc<DashMap<ConnId, Outbox>>,
     pub logged_in: Arc<DashMap<ConnId, User>>,
     pub subscribed: Arc<DashSet<ConnId>>,
+    pub game_subscribed: Arc<DashSet<(ConnId, GameType)>>,
     pub pool: Pool<AsyncPgConnection>,
-    pub active_client: Arc<DashMap<String, u32>>,
     pub game_sessions: Arc<DashMap<ConnId, GameInProgress>>,
-    pub two048_queue: Arc<DashMap<u64, UnboundedSender<Two048Data>>>,
+    pub two048_queue: Arc<DashMap<u64, UnboundedSender<(Option<String>, GameStep<Two048Move>)>>>,
+    pub tetris_queue:
+        Arc<DashMap<u64, UnboundedSender<(Option<String>, GameStep<TetrisPlacement>)>>>,
     pub redis: ConnectionManager,
-    pub code_verifiers: Arc<DashMap<String, CodeVerifier>>,
+    pub rate_limiter: RateLimiter,
+    pub leaderboard_feeds: Arc<DashMap<Option<GameType>, LeaderboardFeed>>,
+    pub flush_metrics: Arc<FlushMetrics>,
 }
 
 #[derive(Debug)]
 pub struct Command {
     pub conn_id: ConnId,
+    /// Client supplied id echoed back on the response
+    pub request_id: Option<String>,
+    pub origin: Origin,
     pub work: Work,
 }
 
+/// Where a command came from. Only work sent over the client's socket is rate limited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin {
+    Socket,
+    /// Pushed by the server itself, e.g. after an HTTP callback or a routed command
+    Server,
+}
+
 impl Command {
     fn no_check(&self) -> bool {
         matches!(
@@ -48,57 +68,113 @@ impl Command {
                 public_key: _,
                 signature: _,
                 chain: _,
+                nonce: _,
                 ip_agent: _,
-            } | Work::Connect {
-                conn_tx: _,
-                sender: _,
-            } | Work::Disconnect
+            } | Work::SignInChallenge { data: _ }
+                | Work::Connect {
+                    conn_tx: _,
+                    sender: _,
+                    ip: _,
+                }
+                | Work::Disconnect
+                | Work::Close
                 | Work::AuthToken {
                     ip_agent: _,
                     token: _
                 }
+                | Work::AuthRefresh {
+                    ip_agent: _,
+                    refresh_token: _
+                }
+                | Work::UnsupportedProtocol
+                | Work::InvalidRequest { reason: _ }
         )
     }
 }
 
+/// A request of a game that is played through a per-connection queue. Start, Resume and End go
+/// through the same queue as the moves, so a move is always applied to the session it was sent
+/// for.
+#[derive(Debug)]
+pub enum GameStep<T> {
+    Start {
+        timestamp: DateTime<Utc>,
+    },
+    /// Reattaches the checkpointed session of the game
+    Resume,
+    Move(T),
+    End,
+}
+
 #[derive(Debug)]
 pub enum Work {
     Connect {
-        conn_tx: UnboundedSender<String>,
+        conn_tx: Outbox,
         sender: oneshot::Sender<ConnId>,
+        ip: String,
     },
     Disconnect,
+    /// Drops the socket, e.g. when an admin kicks the user
+    Close,
+    SignInChallenge {
+        data: ChallengeRequest,
+    },
     Auth {
         public_key: String,
         signature: String,
         chain: Chain,
+        nonce: String,
         ip_agent: UserIpAgent,
     },
     AuthToken {
         token: String,
         ip_agent: UserIpAgent,
     },
+    AuthRefresh {
+        refresh_token: String,
+        ip_agent: UserIpAgent,
+    },
+    LogoutEverywhere,
     Me,
     MeWithRankSocials,
     GetActivity,
+    TetrisStart {
+        timestamp: DateTime<Utc>,
+    },
     Tetris {
-        data: TetrisData,
+        data: TetrisPlacement,
     },
     TetrisEnd,
     Snake {
         data: SnakeData,
     },
     SnakeEnd,
+    Two048Start {
+        timestamp: DateTime<Utc>,
+    },
     Two048 {
-        data: Two048Data,
+        data: Two048Move,
     },
     Two048End,
     Flappy {
         data: FlappyData,
     },
     FlappyEnd,
-    LeaderboardIn,
-    LeaderboardOut,
+    LeaderboardIn {
+        game: Option<GameType>,
+    },
+    LeaderboardOut {
+        game: Option<GameType>,
+    },
+    LeaderboardPage {
+        data: LeaderboardPageQuery,
+    },
+    LeaderboardAround {
+        count: Option<i64>,
+    },
+    WindowLeaderboard {
+        window: LeaderboardWindow,
+    },
     InitialPoints,
     UsernameUpdate {
         data: String,
@@ -117,13 +193,128 @@ pub enum Work {
     BindWallet {
         data: BindWallet,
     },
+    GetReplay {
+        session_id: String,
+    },
+    ResumeSession {
+        game: GameType,
+    },
+    UnsupportedProtocol,
+    InvalidRequest {
+        reason: String,
+    },
+}
+
+impl Work {
+    /// Every name returned by [`Work::rate_limit_key`]
+    pub const RATE_LIMIT_KEYS: &[&str] = &[
+        "SignInChallenge",
+        "Auth",
+        "AuthToken",
+        "AuthRefresh",
+        "LogoutEverywhere",
+        "Me",
+        "MeWithRankSocials",
+        "GetActivity",
+        "TetrisStart",
+        "Tetris",
+        "TetrisEnd",
+        "Snake",
+        "SnakeEnd",
+        "Two048Start",
+        "Two048",
+        "Two048End",
+        "Flappy",
+        "FlappyEnd",
+        "LeaderboardIn",
+        "LeaderboardOut",
+        "LeaderboardPage",
+        "LeaderboardAround",
+        "WindowLeaderboard",
+        "InitialPoints",
+        "UsernameUpdate",
+        "SocialLinks",
+        "Telegram",
+        "Tasks",
+        "CheckTask",
+        "CheckReferralStatus",
+        "BindWallet",
+        "GetReplay",
+        "ResumeSession",
+        "UnsupportedProtocol",
+        "InvalidRequest",
+    ];
+
+    /// Name the work is rate limited under. Connection bookkeeping is never limited.
+    pub fn rate_limit_key(&self) -> Option<&'static str> {
+        let key = match self {
+            Work::Connect { .. } | Work::Disconnect | Work::Close => return None,
+            Work::SignInChallenge { .. } => "SignInChallenge",
+            Work::Auth { .. } => "Auth",
+            Work::AuthToken { .. } => "AuthToken",
+            Work::AuthRefresh { .. } => "AuthRefresh",
+            Work::LogoutEverywhere => "LogoutEverywhere",
+            Work::Me => "Me",
+            Work::MeWithRankSocials => "MeWithRankSocials",
+            Work::GetActivity => "GetActivity",
+            Work::TetrisStart { .. } => "TetrisStart",
+            Work::Tetris { .. } => "Tetris",
+            Work::TetrisEnd => "TetrisEnd",
+            Work::Snake { .. } => "Snake",
+            Work::SnakeEnd => "SnakeEnd",
+            Work::Two048Start { .. } => "Two048Start",
+            Work::Two048 { .. } => "Two048",
+            Work::Two048End => "Two048End",
+            Work::Flappy { .. } => "Flappy",
+            Work::FlappyEnd => "FlappyEnd",
+            Work::LeaderboardIn { .. } => "LeaderboardIn",
+            Work::LeaderboardOut { .. } => "LeaderboardOut",
+            Work::LeaderboardPage { .. } => "LeaderboardPage",
+            Work::LeaderboardAround { .. } => "LeaderboardAround",
+            Work::WindowLeaderboard { .. } => "WindowLeaderboard",
+            Work::InitialPoints => "InitialPoints",
+            Work::UsernameUpdate { .. } => "UsernameUpdate",
+            Work::SocialLinks => "SocialLinks",
+            Work::Telegram { .. } => "Telegram",
+            Work::Tasks => "Tasks",
+            Work::CheckTask { .. } => "CheckTask",
+            Work::CheckReferralStatus { .. } => "CheckReferralStatus",
+            Work::BindWallet { .. } => "BindWallet",
+            Work::GetReplay { .. } => "GetReplay",
+            Work::ResumeSession { .. } => "ResumeSession",
+            Work::UnsupportedProtocol => "UnsupportedProtocol",
+            Work::InvalidRequest { .. } => "InvalidRequest",
+        };
+
+        Some(key)
+    }
+
+    /// The routable form of the work, if it can run on the instance holding the connection
+    fn routed(&self) -> Option<RoutedWork> {
+        match self {
+            Work::Tasks => Some(RoutedWork::Tasks),
+            Work::MeWithRankSocials => Some(RoutedWork::MeWithRankSocials),
+            Work::Close => Some(RoutedWork::Close),
+            _ => None,
+        }
+    }
+}
+
+impl From<RoutedWork> for Work {
+    fn from(work: RoutedWork) -> Self {
+        match work {
+            RoutedWork::Tasks => Work::Tasks,
+            RoutedWork::MeWithRankSocials => Work::MeWithRankSocials,
+            RoutedWork::Close => Work::Close,
+        }
+    }
 }
 
 impl Server {
     pub fn new(
         pool: Pool<AsyncPgConnection>,
         redis: ConnectionManager,
-        code_verifiers: Arc<DashMap<String, CodeVerifier>>,
+        rate_limits: RateLimits,
     ) -> (Self, ServerInterface, UnboundedReceiver<Command>) {
         let (cmd_tx, cmd_rx) = mpsc::unbounded_channel();
         (
@@ -131,12 +322,15 @@ impl Server {
                 sessions: Arc::new(DashMap::new()),
                 logged_in: Arc::new(DashMap::new()),
                 subscribed: Arc::new(DashSet::new()),
-                active_client: Arc::new(DashMap::new()),
+                game_subscribed: Arc::new(DashSet::new()),
                 game_sessions: Arc::new(DashMap::new()),
                 two048_queue: Arc::new(DashMap::new()),
+                tetris_queue: Arc::new(DashMap::new()),
                 pool,
                 redis,
-                code_verifiers,
+                rate_limiter: RateLimiter::new(rate_limits),
+                leaderboard_feeds: Arc::new(DashMap::new()),
+                flush_metrics: Arc::new(FlushMetrics::default()),
             },
             ServerInterface { cmd_tx },
             cmd_rx,
@@ -163,10 +357,31 @@ impl Server {
 
         tokio::spawn(self_clone.clone().handle_tg_join());
         tokio::spawn(self_clone.clone().handle_discord_join());
+        tokio::spawn(self_clone.clone().handle_window_rollover());
+        tokio::spawn(self_clone.clone().presence_heartbeat());
+        tokio::spawn(self_clone.clone().commit_expired_checkpoints());
+        tokio::spawn(self_clone.clone().prune_rate_limits());
+        tokio::spawn(self_clone.clone().broadcast_leaderboard_deltas());
+        tokio::spawn(self_clone.clone().flush_dirty_points());
 
         tokio::spawn(self_clone.subscribe_for_updates());
 
         while let Some(cmd) = cmd_rx.recv().await {
+            if SHUTTING_DOWN.load(Ordering::SeqCst)
+                && !matches!(
+                    cmd.work,
+                    Work::Connect { .. } | Work::Disconnect | Work::Close
+                )
+            {
+                let response = WsResponse::server_restarting().with_request_id(cmd.request_id);
+                self.send_reply(cmd.conn_id, response);
+                continue;
+            }
+
+            if !self.admit(&cmd) {
+                continue;
+            }
+
             let self_clone = self.clone();
             tokio::spawn(self_clone.handle_command(cmd));
         }
@@ -177,19 +392,36 @@ impl Server {
     pub async fn handle_command(mut self, command: Command) {
         let conn_id = command.conn_id;
 
+        // Pushes triggered outside the socket, e.g. from HTTP callbacks, may target a
+        // connection held by another instance
+        if !self.sessions.contains_key(&conn_id)
+            && let Some(work) = command.work.routed()
+        {
+            if let Err(e) = self.route_command(conn_id, work).await {
+                error!("Error routing command for {conn_id}. Reason: {:?}", e);
+            }
+            return;
+        }
+
         if !command.no_check()
             && !self.logged_in.contains_key(&conn_id)
-            && let Some(tx) = self.sessions.get(&conn_id)
+            && self.sessions.contains_key(&conn_id)
         {
-            let _ = tx.send(WsResponse::not_logged_in().json());
+            let response = WsResponse::not_logged_in().with_request_id(command.request_id);
+            self.send_reply(conn_id, response);
             return;
         }
 
+        let request_id = command.request_id;
         let work_string = format!("{:?}", command.work);
 
         let response = match command.work {
-            Work::Connect { conn_tx, sender } => {
-                let conn_id = self.connect(conn_tx);
+            Work::Connect {
+                conn_tx,
+                sender,
+                ip,
+            } => {
+                let conn_id = self.connect(conn_tx, ip);
                 let _ = sender.send(conn_id);
                 None
             }
@@ -197,88 +429,99 @@ impl Server {
                 self.disconnect(conn_id).await;
                 None
             }
+            Work::Close => {
+                self.close_connection(conn_id);
+                None
+            }
+            Work::SignInChallenge { data } => Some(self.sign_in_challenge(data).await),
             Work::Auth {
                 public_key,
                 signature,
                 chain,
+                nonce,
                 ip_agent,
             } => Some(
-                self.start_connection(conn_id, public_key, signature, chain, ip_agent)
+                self.start_connection(conn_id, public_key, signature, chain, nonce, ip_agent)
                     .await,
             ),
             Work::AuthToken { token, ip_agent } => {
                 Some(self.start_connection_token(conn_id, token, ip_agent).await)
             }
+            Work::AuthRefresh {
+                refresh_token,
+                ip_agent,
+            } => Some(
+                self.start_connection_refresh(conn_id, refresh_token, ip_agent)
+                    .await,
+            ),
+            Work::LogoutEverywhere => {
+                let result = self.logout_everywhere(conn_id).await;
+                let logged_out = result.is_ok();
+
+                self.respond(conn_id, request_id, result, &work_string);
+
+                // Closed after the response so the client knows why
+                if logged_out {
+                    self.close_connection(conn_id);
+                }
+                return;
+            }
             Work::Me => Some(self.get_me(conn_id).await),
             Work::MeWithRankSocials => Some(self.get_me_with_rank_socials(conn_id).await),
             Work::GetActivity => Some(self.get_user_activity(conn_id).await),
+            Work::TetrisStart { timestamp } => {
+                self.queue_tetris(conn_id, request_id, GameStep::Start { timestamp });
+                return;
+            }
             Work::Tetris { data } => {
-                if let Err(e) = self.tetris(conn_id, data).await {
-                    error!("Error handling tetris move. Reason: {:?}", e);
-                }
-                None
+                self.queue_tetris(conn_id, request_id, GameStep::Move(data));
+                return;
             }
             Work::TetrisEnd => {
-                if let Err(e) = self.commit_to_db(conn_id).await {
-                    error!("Error committing tetris session to db. Reason: {:?}", e);
-                }
-                None
+                self.queue_tetris(conn_id, request_id, GameStep::End);
+                return;
             }
-            Work::Snake { data } => {
-                if let Err(e) = self.snake(conn_id, data).await {
-                    error!("Error handling snake move. Reason: {:?}", e);
-                }
-                None
-            }
-            Work::SnakeEnd => {
-                if let Err(e) = self.commit_to_db(conn_id).await {
-                    error!("Error committing snake session to db. Reason: {:?}", e);
-                }
-                None
+            Work::Snake { data } => self
+                .snake(conn_id, request_id.clone(), data)
+                .await
+                .err()
+                .map(Err),
+            Work::SnakeEnd => self.commit_to_db(conn_id).await.err().map(Err),
+            Work::Two048Start { timestamp } => {
+                self.queue_two048(conn_id, request_id, GameStep::Start { timestamp });
+                return;
             }
             Work::Two048 { data } => {
-                if let Some(sender) = self.two048_queue.get(&conn_id) {
-                    sender.send(data).unwrap();
-                } else {
-                    let (sender, receiver) = mpsc::unbounded_channel();
-                    self.two048_queue.insert(conn_id, sender.clone());
-                    let self_clone = self.clone();
-                    tokio::spawn(self_clone.two048_queue(conn_id, receiver));
-                    sender.send(data).unwrap();
-                }
-                None
+                self.queue_two048(conn_id, request_id, GameStep::Move(data));
+                return;
             }
             Work::Two048End => {
-                if let Err(e) = self.commit_to_db(conn_id).await {
-                    error!("Error committing 2048 session to db. Reason: {:?}", e);
-                }
-                self.two048_queue.remove(&conn_id);
-
-                None
+                self.queue_two048(conn_id, request_id, GameStep::End);
+                return;
             }
-            Work::Flappy { data } => {
-                if let Err(e) = self.flappy(conn_id, data).await {
-                    error!("Error handling flappy move. Reason: {:?}", e);
+            Work::Flappy { data } => self
+                .flappy(conn_id, request_id.clone(), data)
+                .await
+                .err()
+                .map(Err),
+            Work::FlappyEnd => self.commit_to_db(conn_id).await.err().map(Err),
+            Work::LeaderboardIn { game } => Some(self.leaderboard_in(conn_id, game).await),
+            Work::LeaderboardOut { game } => {
+                if let Some(game) = game {
+                    self.game_subscribed.remove(&(conn_id, game));
+                } else {
+                    self.subscribed.remove(&conn_id);
                 }
                 None
             }
-            Work::FlappyEnd => {
-                if let Err(e) = self.commit_to_db(conn_id).await {
-                    error!("Error committing Flappy session to db. Reason: {:?}", e);
-                }
-                None
-            }
-            Work::LeaderboardIn => Some(self.leaderboard_in(conn_id).await),
-            Work::LeaderboardOut => {
-                self.subscribed.remove(&conn_id);
-                None
+            Work::LeaderboardPage { data } => Some(self.leaderboard_page(data).await),
+            Work::LeaderboardAround { count } => {
+                Some(self.leaderboard_around(conn_id, count).await)
             }
+            Work::WindowLeaderboard { window } => Some(self.window_leaderboard(window).await),
             Work::InitialPoints => Some(self.initial_points(conn_id).await),
             Work::UsernameUpdate { data } => {
-                if let Err(e) = self.update_username(conn_id, data).await {
-                    error!("Error updating username. Reason: {:?}", e);
-                }
-                None
+                self.update_username(conn_id, data).await.err().map(Err)
             }
             Work::SocialLinks => Some(self.social_links(conn_id).await),
             Work::Telegram { data } => Some(self.telegram(conn_id, data).await),
@@ -299,32 +542,87 @@ impl Server {
             Work::CheckReferralStatus { referral_code } => {
                 let result = self.check_referral_status(conn_id, referral_code).await;
 
-                match result {
-                    Ok(Some(response)) => Some(Ok(response)),
-                    Ok(None) => None,
-                    Err(e) => {
-                        error!("Error checking referral status. Reason: {:?}", e);
-                        None
-                    }
-                }
+                result.transpose()
             }
             Work::BindWallet { data } => Some(self.bind_wallet(conn_id, data).await),
+            Work::GetReplay { session_id } => Some(self.get_replay(session_id).await),
+            Work::ResumeSession { game } => match game {
+                GameType::Tetris => {
+                    self.queue_tetris(conn_id, request_id, GameStep::Resume);
+                    return;
+                }
+                GameType::Two048 => {
+                    self.queue_two048(conn_id, request_id, GameStep::Resume);
+                    return;
+                }
+                GameType::Snake | GameType::Flappy => {
+                    Some(self.resume_session(conn_id, game).await)
+                }
+            },
+            Work::UnsupportedProtocol => Some(Ok(WsResponse::unsupported_protocol())),
+            Work::InvalidRequest { reason } => Some(Ok(WsResponse::invalid_request(reason))),
         };
 
         if let Some(response) = response {
-            let to_send = match response {
-                Ok(response) => response.json(),
-                Err(err) => {
-                    error!("Error while handling command {}: {err}", work_string);
-                    WsResponse::internal_error().json()
-                }
-            };
+            self.respond(conn_id, request_id, response, &work_string);
+        }
+    }
 
-            if let Some(tx) = self.sessions.get(&conn_id)
-                && let Err(e) = tx.send(to_send)
-            {
-                error!("Error sending response to client. Reason: {:?}", e);
+    /// Sends the outcome of a request to the client, tagged with the request id.
+    ///
+    /// Errors carrying an `ErrorResponse` are passed on as is, anything else becomes an
+    /// `InternalError`.
+    pub fn respond(
+        &self,
+        conn_id: ConnId,
+        request_id: Option<String>,
+        response: Result<WsResponse>,
+        work: &str,
+    ) {
+        let response = match response {
+            Ok(response) => response,
+            Err(err) => {
+                error!("Error while handling command {work}: {err:#}");
+
+                err.downcast_ref::<ErrorResponse>()
+                    .cloned()
+                    .map_or_else(WsResponse::internal_error, WsResponse::error)
             }
+        };
+
+        self.send_reply(conn_id, response.with_request_id(request_id));
+    }
+
+    /// Queues a pushed response for the client and closes connections that stopped reading
+    pub fn send_response(&self, conn_id: ConnId, response: WsResponse) {
+        let slow = self
+            .sessions
+            .get(&conn_id)
+            .is_some_and(|outbox| outbox.send(&response).is_err());
+
+        if slow {
+            warn!("Closing {conn_id}, its outbound queue stayed full");
+            self.close_connection(conn_id);
         }
     }
+
+    /// Queues the reply to a request. A client that can't take it is closed instead of losing it.
+    pub fn send_reply(&self, conn_id: ConnId, response: WsResponse) {
+        let slow = self
+            .sessions
+            .get(&conn_id)
+            .is_some_and(|outbox| outbox.send_reply(&response).is_err());
+
+        if slow {
+            warn!("Closing {conn_id}, its outbound queue is full and a reply is waiting");
+            self.close_connection(conn_id);
+        }
+    }
+}
+
+/// Marks a rejected client input so the client is told why instead of getting an
+/// `InternalError`
+pub fn validation_failed(err: Error) -> Error {
+    let reason = err.root_cause().to_string();
+    err.context(ErrorResponse::ValidationFailed { data: reason })
 }
@@ -0,0 +1,139 @@
+use anyhow::{Context, Result, anyhow};
+use db::models::{GameType, User};
+use log::{error, info};
+use tokio::time::{Duration, sleep};
+
+use crate::ws::models::{FlappyData, GameInProgress, SnakeData, WsResponse};
+use crate::ws::redis_ops::{
+    checkpoint_session, expired_session_checkpoints, resumable_games, session_checkpoint_key,
+    take_session_checkpoint,
+};
+use crate::ws::server::{ConnId, Server};
+
+/// How often checkpoints past their grace window are looked for
+const CHECKPOINT_SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+impl Server {
+    /// Moves the unfinished session of a closing connection to redis so the user can reattach
+    /// to it. Committed right away if it cannot be checkpointed.
+    pub async fn checkpoint_game_session(&mut self, conn_id: ConnId) -> Result<()> {
+        let Some(user) = self.logged_in.get(&conn_id).map(|user| user.clone()) else {
+            return Ok(());
+        };
+
+        let Some((_, session)) = self.game_sessions.remove(&conn_id) else {
+            return Ok(());
+        };
+
+        // Another client of the user may have left the same game behind already
+        let key = session_checkpoint_key(&user.user_id, session.get_game());
+        if let Some(previous) = take_session_checkpoint(&mut self.redis, &key).await? {
+            self.commit_session(&user, previous).await?;
+        }
+
+        if let Err(e) = checkpoint_session(&mut self.redis, &session).await {
+            error!(
+                "Failed to checkpoint session {}, committing it instead. Reason: {e:?}",
+                session.get_session_id()
+            );
+            return self.commit_session(&user, session).await;
+        }
+
+        Ok(())
+    }
+
+    /// Commits checkpoints nobody reattached to within the grace window
+    pub async fn commit_expired_checkpoints(mut self) {
+        info!("Starting session checkpoint sweeper");
+
+        loop {
+            sleep(CHECKPOINT_SWEEP_INTERVAL).await;
+
+            let keys = match expired_session_checkpoints(&mut self.redis).await {
+                Ok(keys) => keys,
+                Err(e) => {
+                    error!("Failed to get expired session checkpoints. Reason: {e:?}");
+                    continue;
+                }
+            };
+
+            for key in keys {
+                if let Err(e) = self.commit_checkpoint(&key).await {
+                    error!("Failed to commit session checkpoint {key}. Reason: {e:?}");
+                }
+            }
+        }
+    }
+
+    async fn commit_checkpoint(&mut self, key: &str) -> Result<()> {
+        // Taken by another instance or a resuming user in the meantime
+        let Some(session) = take_session_checkpoint(&mut self.redis, key).await? else {
+            return Ok(());
+        };
+
+        let mut conn = self.pool.get().await?;
+        let user = User::get_user(&mut conn, session.get_user_id().to_string())
+            .await
+            .context("Failed to get user of the checkpoint")?;
+
+        drop(conn);
+
+        self.commit_session(&user, session).await
+    }
+
+    /// Reattaches a checkpointed session to the connection and returns its last state
+    pub async fn resume_session(&mut self, conn_id: ConnId, game: GameType) -> Result<WsResponse> {
+        let user_id = self
+            .logged_in
+            .get(&conn_id)
+            .ok_or(anyhow!("{conn_id} not logged in"))?
+            .user_id
+            .clone();
+
+        if !resumable_games(&mut self.redis, &user_id)
+            .await?
+            .contains(&game)
+        {
+            return Ok(WsResponse::session_not_resumable(game));
+        }
+
+        let key = session_checkpoint_key(&user_id, game);
+        let Some(session) = take_session_checkpoint(&mut self.redis, &key).await? else {
+            return Ok(WsResponse::session_not_resumable(game));
+        };
+
+        // Whatever was played on this connection before resuming is finished as usual
+        self.commit_to_db(conn_id).await?;
+
+        let response = last_state(&session)
+            .ok_or(anyhow!("No state found for the resumed {game:?} session"))?;
+
+        info!(
+            "User {user_id} resumed {game:?} session {}",
+            session.get_session_id()
+        );
+
+        self.game_sessions.insert(conn_id, session);
+
+        Ok(response)
+    }
+}
+
+fn last_state(session: &GameInProgress) -> Option<WsResponse> {
+    let response = match session.get_game() {
+        GameType::Tetris => WsResponse::new_tetris(session.get_last_tetris()?),
+        GameType::Two048 => WsResponse::new_two048(session.get_last_two048()?),
+        GameType::Snake => WsResponse::new_snake(
+            session
+                .get_last_snake()
+                .map_or_else(SnakeData::new, |e| SnakeData::from(&e)),
+        ),
+        GameType::Flappy => WsResponse::new_flappy(
+            session
+                .get_last_flappy()
+                .map_or_else(FlappyData::new, |e| FlappyData::from(&e)),
+        ),
+    };
+
+    Some(response)
+}
@@ -0,0 +1,48 @@
+use log::{error, info};
+use std::sync::atomic::Ordering;
+
+use crate::SHUTTING_DOWN;
+use crate::ws::models::WsResponse;
+use crate::ws::server::{ConnId, Server};
+
+impl Server {
+    /// Saves everything that only lives in memory or redis before the process exits.
+    ///
+    /// Clients are told the server is restarting, running games are committed, dirty points are
+    /// written to Postgres and finally every socket is closed.
+    pub async fn shutdown(mut self) {
+        SHUTTING_DOWN.store(true, Ordering::SeqCst);
+        info!("Shutting down, {} connections open", self.sessions.len());
+
+        let conn_ids: Vec<ConnId> = self.sessions.iter().map(|entry| *entry.key()).collect();
+
+        for conn_id in &conn_ids {
+            self.send_response(*conn_id, WsResponse::server_restarting());
+        }
+
+        let in_progress: Vec<ConnId> = self
+            .game_sessions
+            .iter()
+            .map(|entry| *entry.key())
+            .collect();
+
+        for conn_id in &in_progress {
+            if let Err(e) = self.commit_to_db(*conn_id).await {
+                error!("Error committing session of {conn_id} on shutdown. Reason: {e:?}");
+            }
+        }
+
+        info!("Committed {} game sessions", in_progress.len());
+
+        // Only flushed, during a rolling deploy other replicas still serve these users
+        if let Err(e) = self.flush_dirty_round().await {
+            error!("Error flushing dirty users on shutdown. Reason: {e:?}");
+        }
+
+        info!("Flushed dirty users");
+
+        for conn_id in conn_ids {
+            self.close_connection(conn_id);
+        }
+    }
+}
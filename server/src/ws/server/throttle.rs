@@ -0,0 +1,59 @@
+use log::warn;
+use tokio::time::{Duration, sleep};
+
+use crate::ws::models::WsResponse;
+use crate::ws::rate_limit::Verdict;
+use crate::ws::server::{Command, ConnId, Origin, Server};
+
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+impl Server {
+    /// Whether a command may run. Rejected commands are answered with `RateLimited` and
+    /// connections that keep getting rejected are closed. Server pushes are always admitted.
+    pub fn admit(&self, command: &Command) -> bool {
+        if command.origin == Origin::Server {
+            return true;
+        }
+
+        let Some(work) = command.work.rate_limit_key() else {
+            return true;
+        };
+
+        let conn_id = command.conn_id;
+        let user_id = self
+            .logged_in
+            .get(&conn_id)
+            .map(|user| user.user_id.clone());
+
+        match self.rate_limiter.check(conn_id, user_id.as_deref(), work) {
+            Verdict::Allowed => true,
+            Verdict::Limited(wait) => {
+                let response = WsResponse::rate_limited(wait.as_secs_f64().ceil() as u64)
+                    .with_request_id(command.request_id.clone());
+                self.send_reply(conn_id, response);
+                false
+            }
+            Verdict::Abusive => {
+                warn!(
+                    "Closing {conn_id} of user {}, it keeps exceeding rate limits",
+                    user_id.as_deref().unwrap_or("Not logged in")
+                );
+                self.close_connection(conn_id);
+                false
+            }
+        }
+    }
+
+    /// Makes the socket of `conn_id` close. Cleanup runs through the usual disconnect once the
+    /// socket is gone.
+    pub fn close_connection(&self, conn_id: ConnId) {
+        self.sessions.remove(&conn_id);
+    }
+
+    pub async fn prune_rate_limits(self) {
+        loop {
+            sleep(PRUNE_INTERVAL).await;
+            self.rate_limiter.prune();
+        }
+    }
+}
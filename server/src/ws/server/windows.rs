@@ -0,0 +1,281 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Datelike, Days, Duration, NaiveTime, Utc};
+use db::models::{
+    LeaderboardArchive, LeaderboardWindow, NewLeaderboardArchive, Season, get_window_standings,
+};
+use log::{error, info};
+use redis::AsyncCommands;
+use std::collections::HashSet;
+
+use crate::ws::models::WsResponse;
+use crate::ws::redis_ops::{
+    MAX_LEADERBOARD_SIZE, get_window_leaderboard_entries, set_window_leaderboard,
+    window_leaderboard_key,
+};
+use crate::ws::server::Server;
+use crate::ws::sleep_remaining_time;
+
+/// How many users of a closed window are kept in Postgres
+const MAX_ARCHIVE_SIZE: i64 = 500;
+
+/// Window keys outlive the window a bit so the closing standings can still be read
+const WINDOW_KEY_GRACE: Duration = Duration::hours(1);
+
+/// One concrete period of a leaderboard window, e.g. the daily window of 2026-10-18
+#[derive(Debug, Clone)]
+pub struct WindowPeriod {
+    pub window: LeaderboardWindow,
+    pub period: String,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+}
+
+impl WindowPeriod {
+    /// The UTC day containing `at`
+    pub fn daily(at: DateTime<Utc>) -> Self {
+        let starts_at = at.date_naive().and_time(NaiveTime::MIN).and_utc();
+
+        Self {
+            window: LeaderboardWindow::Daily,
+            period: starts_at.format("%Y-%m-%d").to_string(),
+            starts_at,
+            ends_at: starts_at + Duration::days(1),
+        }
+    }
+
+    /// The ISO week containing `at`, starting on Monday 00:00 UTC
+    pub fn weekly(at: DateTime<Utc>) -> Self {
+        let date = at.date_naive();
+        let monday = date - Days::new(u64::from(date.weekday().num_days_from_monday()));
+        let starts_at = monday.and_time(NaiveTime::MIN).and_utc();
+        let week = date.iso_week();
+
+        Self {
+            window: LeaderboardWindow::Weekly,
+            period: format!("{}-W{:02}", week.year(), week.week()),
+            starts_at,
+            ends_at: starts_at + Duration::weeks(1),
+        }
+    }
+
+    pub fn season(season: &Season) -> Self {
+        Self {
+            window: LeaderboardWindow::Season,
+            period: season.id.clone(),
+            starts_at: season.starts_at,
+            ends_at: season.ends_at,
+        }
+    }
+
+    pub fn key(&self) -> String {
+        window_leaderboard_key(self.window, &self.period)
+    }
+}
+
+impl Server {
+    /// Keeps the current daily, weekly and season leaderboards fresh and archives the
+    /// ones that have closed. Runs at the start of every minute.
+    pub async fn handle_window_rollover(mut self) {
+        info!("Starting leaderboard window rollover task");
+
+        // Periods archived by this task, so empty windows are not recomputed every minute
+        let mut archived = HashSet::new();
+
+        loop {
+            if let Err(e) = self.refresh_windows().await {
+                error!("Failed to refresh leaderboard windows. Reason: {e:?}");
+            }
+
+            if let Err(e) = self.archive_closed_windows(&mut archived).await {
+                error!("Failed to archive leaderboard windows. Reason: {e:?}");
+            }
+
+            sleep_remaining_time().await;
+        }
+    }
+
+    /// The currently running period of a window. `None` if no season is active.
+    async fn current_period(
+        &mut self,
+        window: LeaderboardWindow,
+        now: DateTime<Utc>,
+    ) -> Result<Option<WindowPeriod>> {
+        let period = match window {
+            LeaderboardWindow::Daily => Some(WindowPeriod::daily(now)),
+            LeaderboardWindow::Weekly => Some(WindowPeriod::weekly(now)),
+            LeaderboardWindow::Season => {
+                let mut conn = self.pool.get().await?;
+                Season::get_active(now, &mut conn)
+                    .await?
+                    .as_ref()
+                    .map(WindowPeriod::season)
+            }
+        };
+
+        Ok(period)
+    }
+
+    async fn refresh_windows(&mut self) -> Result<()> {
+        let now = Utc::now();
+
+        for window in LeaderboardWindow::ALL {
+            if let Some(period) = self.current_period(window, now).await? {
+                self.refresh_window(&period).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Rebuilds the redis standings of a period from the DB
+    async fn refresh_window(&mut self, period: &WindowPeriod) -> Result<Vec<(String, i32)>> {
+        let mut conn = self.pool.get().await?;
+
+        let standings = get_window_standings(
+            &mut conn,
+            period.starts_at,
+            period.ends_at,
+            MAX_LEADERBOARD_SIZE as i64,
+        )
+        .await
+        .with_context(|| format!("Failed to get standings of {}", period.key()))?;
+
+        let expire_at = (period.ends_at + WINDOW_KEY_GRACE).timestamp();
+        set_window_leaderboard(&mut self.redis, &period.key(), &standings, expire_at).await?;
+
+        Ok(standings)
+    }
+
+    async fn archive_closed_windows(&mut self, archived: &mut HashSet<String>) -> Result<()> {
+        let now = Utc::now();
+
+        for window in [LeaderboardWindow::Daily, LeaderboardWindow::Weekly] {
+            for period in self.unarchived_periods(window, now).await? {
+                if archived.contains(&period.key()) {
+                    continue;
+                }
+
+                self.archive_window(&period).await?;
+                archived.insert(period.key());
+            }
+        }
+
+        let mut conn = self.pool.get().await?;
+        let ended_seasons = Season::get_ended_unarchived(now, &mut conn).await?;
+
+        for season in ended_seasons {
+            let period = WindowPeriod::season(&season);
+            self.archive_window(&period).await?;
+
+            season
+                .mark_archived(&mut conn)
+                .await
+                .with_context(|| format!("Failed to mark season {} archived", season.id))?;
+
+            let _: () = self.redis.del(period.key()).await?;
+            info!("Season {} ({}) archived", season.name, season.id);
+        }
+
+        Ok(())
+    }
+
+    /// Closed daily or weekly periods newer than the last archived one, oldest first, so
+    /// periods missed while no instance was running are archived too. Only the period that
+    /// closed last is returned when nothing was archived yet.
+    async fn unarchived_periods(
+        &mut self,
+        window: LeaderboardWindow,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<WindowPeriod>> {
+        let period_at = match window {
+            LeaderboardWindow::Daily => WindowPeriod::daily,
+            LeaderboardWindow::Weekly => WindowPeriod::weekly,
+            LeaderboardWindow::Season => return Ok(Vec::new()),
+        };
+
+        let mut conn = self.pool.get().await?;
+        let latest = LeaderboardArchive::latest_period(window, &mut conn).await?;
+
+        let mut period = period_at(period_at(now).starts_at - Duration::seconds(1));
+        let mut periods = Vec::new();
+
+        match latest {
+            None => periods.push(period),
+            Some(latest) => {
+                while period.period > latest {
+                    let previous = period_at(period.starts_at - Duration::seconds(1));
+                    periods.push(period);
+                    period = previous;
+                }
+            }
+        }
+
+        periods.reverse();
+
+        Ok(periods)
+    }
+
+    /// Stores the final standings of a closed period in the DB
+    async fn archive_window(&mut self, period: &WindowPeriod) -> Result<()> {
+        let mut conn = self.pool.get().await?;
+
+        let standings = get_window_standings(
+            &mut conn,
+            period.starts_at,
+            period.ends_at,
+            MAX_ARCHIVE_SIZE,
+        )
+        .await
+        .with_context(|| format!("Failed to get final standings of {}", period.key()))?;
+
+        let rows: Vec<NewLeaderboardArchive> = standings
+            .into_iter()
+            .enumerate()
+            .map(|(index, (user_id, score))| {
+                NewLeaderboardArchive::new(
+                    period.window,
+                    period.period.clone(),
+                    user_id,
+                    index as i32 + 1,
+                    score,
+                )
+            })
+            .collect();
+
+        if !rows.is_empty() {
+            NewLeaderboardArchive::insert_batch(&rows, &mut conn)
+                .await
+                .with_context(|| format!("Failed to archive {}", period.key()))?;
+        }
+
+        info!("Archived {} with {} users", period.key(), rows.len());
+
+        Ok(())
+    }
+
+    pub async fn window_leaderboard(&mut self, window: LeaderboardWindow) -> Result<WsResponse> {
+        let Some(period) = self.current_period(window, Utc::now()).await? else {
+            return Ok(WsResponse::window_leaderboard(
+                window,
+                None,
+                None,
+                Vec::new(),
+            ));
+        };
+
+        let entries = match get_window_leaderboard_entries(&mut self.redis, &period.key()).await? {
+            Some(entries) => entries,
+            // Not built yet or the window has just rolled over
+            None => self.refresh_window(&period).await?,
+        };
+
+        let leaderboard = self.entries_with_users(entries).await?;
+
+        Ok(WsResponse::window_leaderboard(
+            window,
+            Some(period.period),
+            Some(period.ends_at),
+            leaderboard,
+        ))
+    }
+}
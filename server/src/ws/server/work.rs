@@ -2,15 +2,15 @@ use anyhow::{Context as _, Error, Result, anyhow};
 use bots::discord::{check_user_in_reactions, user_in_discord};
 use bots::telegram::check_user_in_chat;
 use db::models::{
-    GameSession, MAX_SOCIALS, Platform, Referral, ReferralReward, Task, TaskCompletion, TaskType,
-    User, UserSocial, get_user_rank,
+    GameSession, GameType, MAX_SOCIALS, NewPointTransaction, Platform, PointSource, Referral,
+    ReferralReward, Task, TaskCompletion, TaskType, User, UserSocial, UserStatus, get_user_rank,
 };
 use diesel_async::AsyncConnection as _;
 use log::{error, info};
 use rand::{RngExt as _, rng};
 use redis::AsyncCommands;
+use std::iter::once;
 use std::time::Duration;
-use tokio::sync::mpsc::UnboundedSender;
 use tokio::time::sleep;
 use ulid::Ulid;
 
@@ -18,69 +18,80 @@ use crate::TELEGRAM_REDIRECT;
 use crate::auth::{generate_discord_oauth2_url, generate_twitter_oauth2_url};
 use crate::ws::hash_verifier::verify_hash;
 use crate::ws::models::{
-    BindWallet, Chain, MiniTask, SocialLinks, TaskCheck, TelegramUser, UserTask, UserWithRank,
-    WsResponse,
+    BindWallet, Chain, ErrorResponse, GameInProgress, LeaderboardCursor, LeaderboardEntry,
+    LeaderboardPageQuery, SocialLinks, TaskCheck, TelegramUser, UserTask, UserWithRank, WsResponse,
+    load_replay, mini_task,
 };
+use crate::ws::outbox::Outbox;
 use crate::ws::redis_ops::{
-    DISCONNECTED_SUB, USER_KEY, USER_TASK_KEY, convert_to_user_with_rank_socials, get_all_tasks,
-    get_full_user, get_task_details, get_user_completed_tasks, get_user_discord_id,
+    MAX_LEADERBOARD_SIZE, USER_KEY, USER_TASK_KEY, convert_to_user_with_rank_socials,
+    get_all_tasks, get_full_user, get_task_details, get_user_completed_tasks, get_user_discord_id,
     get_user_points, get_user_socials_status, get_user_telegram_id, mark_task_completed,
-    update_task_details, update_user_evm_wallet, update_user_referral_code, update_user_sol_wallet,
-    update_user_telegram, update_user_username,
+    remove_presence, set_code_verifier, update_task_details, update_user_evm_wallet,
+    update_user_referral_code, update_user_sol_wallet, update_user_telegram, update_user_username,
+    user_connection_count,
 };
 use crate::ws::server::{ConnId, Server};
+use crate::ws::sign_in::verify_sign_in;
 use crate::ws::validator::consts::{
-    GAME_BONUS_PERCENTAGE, MAX_USERNAME_LENGTH, MINIMUM_POINTS_FOR_REFERRAL, REFERRAL_BONUS,
-};
-use crate::ws::{
-    extract_ids_from_message_url, generate_referral_code, verify_signature_evm,
-    verify_signature_solana,
+    DEFAULT_NEIGHBOURS, GAME_BONUS_PERCENTAGE, MAX_LEADERBOARD_PAGE_SIZE, MAX_NEIGHBOURS,
+    MAX_USERNAME_LENGTH, MINIMUM_POINTS_FOR_REFERRAL, REFERRAL_BONUS,
 };
+use crate::ws::{extract_ids_from_message_url, generate_referral_code};
 
 impl Server {
-    pub fn connect(&mut self, tx: UnboundedSender<String>) -> ConnId {
+    pub fn connect(&mut self, tx: Outbox, ip: String) -> ConnId {
         let id: u64 = rng().random();
 
         self.sessions.insert(id, tx);
+        self.rate_limiter.track_connection(id, ip);
         id
     }
 
     pub async fn disconnect(&mut self, conn_id: ConnId) {
-        if let Err(e) = self.commit_to_db(conn_id).await {
-            error!("Error a committing session to db. Reason: {:?}", e);
+        if let Err(e) = self.checkpoint_game_session(conn_id).await {
+            error!("Error checkpointing session of {conn_id}. Reason: {:?}", e);
         }
 
         if let Some((_, user)) = self.logged_in.remove(&conn_id) {
-            if let Some(client_num) = self.active_client.get(&user.user_id).map(|v| *v) {
-                if client_num == 1 {
-                    let _: () = self
-                        .redis
-                        .publish(DISCONNECTED_SUB, user.user_id.clone())
-                        .await
-                        .unwrap();
-                    self.active_client.remove(&user.user_id);
+            // Counts clients on every instance, only the last one to leave flushes the user
+            match remove_presence(&mut self.redis, conn_id, &user.user_id).await {
+                Ok(0) => {
+                    // Only the instance that saw the last connection leave cleans up
+                    if let Err(e) = self.cleanup_disconnected(&user.user_id).await {
+                        error!(
+                            "Failed to cleanup disconnected user {}. Reason: {:?}",
+                            user.user_id, e
+                        );
+                    }
                     info!(
                         "Session disconnected. User id: {} wallet: {} username: {:?}",
                         user.user_id,
                         user.sol_wallet.as_deref().unwrap_or("No Sol Wallet"),
                         user.username
                     );
-                } else {
-                    self.active_client
-                        .insert(user.user_id.clone(), client_num - 1);
-                    info!("Reducing client number for {}", user.user_id);
                 }
-            } else {
-                error!(
-                    "User {} not found in active clients. Did the user disconnected too quickly?",
-                    user.user_id
-                );
+                Ok(client_num) => {
+                    info!(
+                        "Reducing client number for {}. {client_num} left",
+                        user.user_id
+                    );
+                }
+                Err(e) => {
+                    error!(
+                        "Failed to remove presence of {conn_id} for user {}. Reason: {:?}",
+                        user.user_id, e
+                    );
+                }
             }
         }
 
         self.two048_queue.remove(&conn_id);
+        self.tetris_queue.remove(&conn_id);
         self.sessions.remove(&conn_id);
+        self.rate_limiter.forget_connection(conn_id);
         self.subscribed.remove(&conn_id);
+        self.game_subscribed.retain(|(id, _)| *id != conn_id);
     }
 
     pub async fn get_me(&mut self, conn_id: ConnId) -> Result<WsResponse> {
@@ -95,7 +106,7 @@ impl Server {
 
         let full_user = get_full_user(&mut self.redis, &user_key, &user.user_id).await?;
 
-        Ok(WsResponse::me(full_user))
+        Ok(WsResponse::me(full_user.into()))
     }
 
     pub async fn get_me_with_rank_socials(&mut self, conn_id: ConnId) -> Result<WsResponse> {
@@ -111,13 +122,12 @@ impl Server {
         // If user image gets update, the internal struct won't reflect that change. Redis will
         // have the updated image, so we need to fetch it again.
         let full_user = get_full_user(&mut self.redis, &user_key, &user.user_id).await?;
-        let user_rank = get_user_rank(&mut conn, &user.user_id)
+        let user_rank = get_user_rank(&mut conn, &user.user_id, full_user.points)
             .await
-            .context(anyhow!("Could not get user rank"))?
-            .ok_or(anyhow!("The user rank gotten was a None value"))?;
+            .context(anyhow!("Could not get user rank"))?;
 
         let ranked_user = UserWithRank {
-            user: full_user,
+            user: full_user.into(),
             rank: user_rank,
         };
 
@@ -129,10 +139,97 @@ impl Server {
         Ok(WsResponse::me_with_rank_socials(user_with_rank_socials))
     }
 
-    pub async fn leaderboard_in(&mut self, conn_id: ConnId) -> Result<WsResponse> {
-        self.subscribed.insert(conn_id);
+    pub async fn leaderboard_in(
+        &mut self,
+        conn_id: ConnId,
+        game: Option<GameType>,
+    ) -> Result<WsResponse> {
+        // Subscribed first so no delta after the snapshot is missed
+        match game {
+            Some(game) => self.game_subscribed.insert((conn_id, game)),
+            None => self.subscribed.insert(conn_id),
+        };
+
+        let (version, leaderboard) = self.leaderboard_snapshot(game).await?;
+
+        Ok(WsResponse::leaderboard(game, version, leaderboard))
+    }
+
+    pub async fn leaderboard_page(&mut self, data: LeaderboardPageQuery) -> Result<WsResponse> {
+        let LeaderboardPageQuery { cursor, limit } = data;
+        let limit = limit
+            .unwrap_or(MAX_LEADERBOARD_SIZE as i64)
+            .clamp(1, MAX_LEADERBOARD_PAGE_SIZE);
+
+        let mut conn = self.pool.get().await?;
+
+        let after = cursor
+            .as_ref()
+            .map(|cursor| (cursor.points, cursor.user_id.as_str()));
+        let users = User::get_leaderboard_page(&mut conn, after, limit).await?;
+
+        let start_rank = match users.first() {
+            Some(first) => get_user_rank(&mut conn, &first.user_id, first.points).await?,
+            None => 0,
+        };
+
+        // A short page means there is nothing left to fetch
+        let next_cursor = users
+            .last()
+            .filter(|_| users.len() as i64 == limit)
+            .map(|last| LeaderboardCursor {
+                points: last.points,
+                user_id: last.user_id.clone(),
+            });
+
+        let data = users
+            .into_iter()
+            .map(|user| LeaderboardEntry::new(user.points, user.into()))
+            .collect();
+
+        Ok(WsResponse::leaderboard_page(start_rank, next_cursor, data))
+    }
+
+    /// The caller with up to `count` users ranked right above and below them
+    pub async fn leaderboard_around(
+        &mut self,
+        conn_id: ConnId,
+        count: Option<i64>,
+    ) -> Result<WsResponse> {
+        let user_id = self
+            .logged_in
+            .get(&conn_id)
+            .ok_or(anyhow!("{conn_id} not logged in"))?
+            .user_id
+            .clone();
+
+        let count = count.unwrap_or(DEFAULT_NEIGHBOURS).clamp(1, MAX_NEIGHBOURS);
+
+        // Redis has the latest points of the caller, the DB may lag behind until it is flushed
+        let user_key = format!("{USER_KEY}:{user_id}");
+        let full_user = get_full_user(&mut self.redis, &user_key, &user_id).await?;
+        let points = full_user.points;
+
+        let mut conn = self.pool.get().await?;
 
-        Ok(WsResponse::leaderboard(self.create_leaderboard().await?))
+        let rank = get_user_rank(&mut conn, &user_id, points)
+            .await
+            .context("Could not get user rank")?;
+
+        let above = User::get_ranked_above(&mut conn, &user_id, points, count).await?;
+        let below = User::get_ranked_below(&mut conn, &user_id, points, count).await?;
+
+        let start_rank = rank - above.len() as i64;
+
+        let data = above
+            .into_iter()
+            .rev()
+            .chain(once(full_user))
+            .chain(below)
+            .map(|user| LeaderboardEntry::new(user.points, user.into()))
+            .collect();
+
+        Ok(WsResponse::leaderboard_around(rank, start_rank, data))
     }
 
     pub async fn initial_points(&mut self, conn_id: ConnId) -> Result<WsResponse> {
@@ -151,55 +248,104 @@ impl Server {
     }
 
     pub async fn commit_to_db(&mut self, conn_id: ConnId) -> Result<()> {
-        let mut conn = self.pool.get().await?;
-        let (bonus_to, amount) = conn
-            .transaction::<(Option<User>, i32), Error, _>(async |conn| {
-                let Some(user) = self.logged_in.get(&conn_id) else {
-                    return Ok((None, 0));
-                };
-
-                if let Some((_, session)) = self.game_sessions.remove(&conn_id) {
-                    let session = session
-                        .commit_to_db(conn)
-                        .await
-                        .context("Failed to commit game session")?;
-
-                    if user.referral_code.is_none() {
-                        return Ok((None, 0));
-                    }
-
-                    let belongs_to = Referral::get_referrer_by_referred_id(conn, &user.user_id)
-                        .await
-                        .context("Could not get referrer")?;
+        let Some(user) = self.logged_in.get(&conn_id).map(|user| user.clone()) else {
+            return Ok(());
+        };
 
-                    let points_to_award = (session.final_score * GAME_BONUS_PERCENTAGE) / 100;
+        let Some((_, session)) = self.game_sessions.remove(&conn_id) else {
+            return Ok(());
+        };
 
-                    if points_to_award < 1 {
-                        return Ok((None, 0));
-                    }
+        self.commit_session(&user, session).await
+    }
 
-                    User::increase_points(conn, &belongs_to.user_id, points_to_award).await?;
+    /// Saves a finished session with its ledger row and awards the referral bonus of its user
+    pub async fn commit_session(&mut self, user: &User, session: GameInProgress) -> Result<()> {
+        let user_key = format!("{USER_KEY}:{}", user.user_id);
+        let balance = get_user_points(&mut self.redis, &user_key)
+            .await?
+            .unwrap_or(user.points);
 
-                    ReferralReward::new(
-                        &belongs_to.user_id,
-                        &user.user_id,
-                        &session.id,
-                        points_to_award,
+        let mut conn = self.pool.get().await?;
+        let (committed, bonus_to, amount) = conn
+            .transaction::<(GameSession, Option<User>, i32), Error, _>(async |conn| {
+                let session = session
+                    .commit_to_db(conn)
+                    .await
+                    .context("Failed to commit game session")?;
+
+                // The points were added move by move in redis, the ledger gets them once here
+                if session.final_score != 0 {
+                    NewPointTransaction::new(
+                        session.user_id.clone(),
+                        PointSource::GameSession,
+                        Some(session.id.clone()),
+                        session.final_score,
+                        balance,
                     )
                     .insert(conn)
                     .await
-                    .context("Could not insert referral reward")?;
+                    .context("Could not record game session points")?;
+                }
 
-                    return Ok((Some(belongs_to), points_to_award));
+                // Shadow banned players neither earn nor give referral bonuses
+                if user.referral_code.is_none() || user.status == UserStatus::ShadowBanned {
+                    return Ok((session, None, 0));
                 }
-                Ok((None, 0))
+
+                let belongs_to = Referral::get_referrer_by_referred_id(conn, &user.user_id)
+                    .await
+                    .context("Could not get referrer")?;
+
+                if belongs_to.status != UserStatus::Active {
+                    return Ok((session, None, 0));
+                }
+
+                let points_to_award = (session.final_score * GAME_BONUS_PERCENTAGE) / 100;
+
+                if points_to_award < 1 {
+                    return Ok((session, None, 0));
+                }
+
+                User::increase_points_with_ledger(
+                    conn,
+                    &belongs_to.user_id,
+                    points_to_award,
+                    PointSource::Referral,
+                    Some(session.id.clone()),
+                )
+                .await
+                .context("Could not award referral bonus")?;
+
+                ReferralReward::new(
+                    &belongs_to.user_id,
+                    &user.user_id,
+                    &session.id,
+                    points_to_award,
+                )
+                .insert(conn)
+                .await
+                .context("Could not insert referral reward")?;
+
+                Ok((session, Some(belongs_to), points_to_award))
             })
             .await?;
 
         drop(conn);
 
+        // Banned and shadow banned users never get ranked
+        if user.status == UserStatus::Active
+            && let Err(e) = self.update_game_leaderboard(&committed).await
+        {
+            error!(
+                "Failed to update {:?} leaderboard for session {}. Reason: {:?}",
+                committed.game, committed.id, e
+            );
+        }
+
         if let Some(user) = bonus_to {
-            self.increase_point(amount, &user, true).await?;
+            self.increase_point(amount, &user, PointSource::Referral, None)
+                .await?;
         }
 
         Ok(())
@@ -222,11 +368,25 @@ impl Server {
         Ok(WsResponse::game_sessions(sessions))
     }
 
+    pub async fn get_replay(&mut self, session_id: String) -> Result<WsResponse> {
+        let mut conn = self.pool.get().await?;
+
+        let replay = load_replay(&session_id, &mut conn).await?;
+
+        Ok(replay.map_or_else(
+            || WsResponse::replay_not_found(session_id),
+            WsResponse::replay,
+        ))
+    }
+
     pub async fn update_username(&mut self, conn_id: ConnId, data: String) -> Result<()> {
         if data.len() > MAX_USERNAME_LENGTH {
-            return Err(anyhow!(
-                "The username {data} exceeds maximum length of {MAX_USERNAME_LENGTH} characters"
-            ));
+            return Err(ErrorResponse::ValidationFailed {
+                data: format!(
+                    "Username exceeds maximum length of {MAX_USERNAME_LENGTH} characters"
+                ),
+            }
+            .into());
         }
 
         let mut user = self
@@ -266,8 +426,7 @@ impl Server {
             let new_state = Ulid::new().to_string();
             let twitter_link = generate_twitter_oauth2_url(&new_state, conn_id);
 
-            self.code_verifiers
-                .insert(new_state.clone(), twitter_link.code_verifier);
+            set_code_verifier(&mut self.redis, &new_state, &twitter_link.code_verifier).await?;
 
             social_links.twitter = Some(twitter_link.url);
         }
@@ -276,8 +435,7 @@ impl Server {
             let new_state = Ulid::new().to_string();
             let discord_link = generate_discord_oauth2_url(&new_state, conn_id);
 
-            self.code_verifiers
-                .insert(new_state.clone(), discord_link.code_verifier);
+            set_code_verifier(&mut self.redis, &new_state, &discord_link.code_verifier).await?;
 
             social_links.discord = Some(discord_link.url);
         }
@@ -341,6 +499,18 @@ impl Server {
         self.get_me_with_rank_socials(conn_id).await
     }
 
+    /// Sends the current task list to every logged in connection of this instance
+    pub async fn push_tasks(mut self) {
+        let conn_ids: Vec<ConnId> = self.logged_in.iter().map(|entry| *entry.key()).collect();
+
+        for conn_id in conn_ids {
+            match self.tasks(conn_id).await {
+                Ok(response) => self.send_response(conn_id, response),
+                Err(e) => error!("Failed to push tasks to {conn_id}. Reason: {e:?}"),
+            }
+        }
+    }
+
     pub async fn tasks(&mut self, conn_id: ConnId) -> Result<WsResponse> {
         let user = self
             .logged_in
@@ -361,7 +531,12 @@ impl Server {
         for (task_id, task_json) in all_tasks {
             let task = Task::from_json(&task_json);
 
-            let mini_task = MiniTask::from_task(task, conn_id, &self.code_verifiers);
+            let (mini_task, verifier) = mini_task(task, conn_id);
+
+            if let Some((state, code_verifier)) = verifier {
+                set_code_verifier(&mut self.redis, &state, &code_verifier).await?;
+            }
+
             let completed = user_completed_tasks.contains(&task_id);
 
             let user_task = UserTask {
@@ -417,7 +592,13 @@ impl Server {
 
                 drop(conn);
 
-                self.increase_point(task.reward_point, &user, false).await?;
+                self.increase_point(
+                    task.reward_point,
+                    &user,
+                    PointSource::Task,
+                    Some(task.id.as_str()),
+                )
+                .await?;
 
                 mark_task_completed(&mut self.redis, &user_task_key, &task.id).await?;
             }
@@ -473,7 +654,13 @@ impl Server {
 
                     drop(conn);
 
-                    self.increase_point(task.reward_point, &user, false).await?;
+                    self.increase_point(
+                        task.reward_point,
+                        &user,
+                        PointSource::Task,
+                        Some(task.id.as_str()),
+                    )
+                    .await?;
                     mark_task_completed(&mut self.redis, &user_task_key, &task.id).await?;
 
                     Ok(WsResponse::task_completed(task.id))
@@ -522,7 +709,13 @@ impl Server {
 
                     drop(conn);
 
-                    self.increase_point(task.reward_point, &user, false).await?;
+                    self.increase_point(
+                        task.reward_point,
+                        &user,
+                        PointSource::Task,
+                        Some(task.id.as_str()),
+                    )
+                    .await?;
                     mark_task_completed(&mut self.redis, &user_task_key, &task.id).await?;
 
                     Ok(WsResponse::task_completed(task.id))
@@ -565,7 +758,13 @@ impl Server {
 
                     drop(conn);
 
-                    self.increase_point(task.reward_point, &user, false).await?;
+                    self.increase_point(
+                        task.reward_point,
+                        &user,
+                        PointSource::Task,
+                        Some(task.id.as_str()),
+                    )
+                    .await?;
                     mark_task_completed(&mut self.redis, &user_task_key, &task.id).await?;
 
                     Ok(WsResponse::task_completed(task.id))
@@ -589,8 +788,8 @@ impl Server {
 
         let mut conn = self.pool.get().await?;
 
-        let (bonus_to, bad_referral) = conn
-            .transaction::<(Option<User>, bool), Error, _>(async |conn| {
+        let (referred, bonus_to, bad_referral) = conn
+            .transaction::<(bool, Option<User>, bool), Error, _>(async |conn| {
                 let mut user = self
                     .logged_in
                     .get_mut(&conn_id)
@@ -602,7 +801,7 @@ impl Server {
 
                 if let Some(user_points) = user_points {
                     if user_points < MINIMUM_POINTS_FOR_REFERRAL {
-                        return Ok((None, false));
+                        return Ok((false, None, false));
                     }
                 } else {
                     error!(
@@ -611,29 +810,28 @@ impl Server {
                     );
 
                     if user.points < MINIMUM_POINTS_FOR_REFERRAL {
-                        return Ok((None, false));
+                        return Ok((false, None, false));
                     }
                 }
 
                 if user.referral_code.is_some() {
-                    return Ok((None, false));
+                    return Ok((false, None, false));
                 }
 
                 let social_media_count = UserSocial::get_social_count(conn, &user.user_id).await?;
 
                 if social_media_count != MAX_SOCIALS {
-                    return Ok((None, false));
+                    return Ok((false, None, false));
                 }
 
                 let Some(belongs_to) = User::get_by_referral_code(conn, &referral_code).await?
                 else {
-                    return Ok((None, true));
+                    return Ok((false, None, true));
                 };
 
                 let new_referral_code = generate_referral_code();
 
                 User::set_referral_code(conn, &user.user_id, &new_referral_code).await?;
-                User::increase_points(conn, &belongs_to.user_id, REFERRAL_BONUS).await?;
 
                 Referral::new(belongs_to.user_id.clone(), user.user_id.clone())
                     .insert(conn)
@@ -643,7 +841,23 @@ impl Server {
 
                 user.referral_code = Some(new_referral_code);
 
-                Ok((Some(belongs_to), false))
+                // Shadow banned players neither earn nor give referral bonuses
+                if user.status == UserStatus::ShadowBanned
+                    || belongs_to.status != UserStatus::Active
+                {
+                    return Ok((true, None, false));
+                }
+
+                User::increase_points_with_ledger(
+                    conn,
+                    &belongs_to.user_id,
+                    REFERRAL_BONUS,
+                    PointSource::Referral,
+                    Some(user.user_id.clone()),
+                )
+                .await?;
+
+                Ok((true, Some(belongs_to), false))
             })
             .await?;
 
@@ -654,7 +868,11 @@ impl Server {
         }
 
         if let Some(user) = bonus_to {
-            self.increase_point(REFERRAL_BONUS, &user, true).await?;
+            self.increase_point(REFERRAL_BONUS, &user, PointSource::Referral, None)
+                .await?;
+        }
+
+        if referred {
             let response = self.get_me_with_rank_socials(conn_id).await?;
 
             return Ok(Some(response));
@@ -668,12 +886,15 @@ impl Server {
         conn_id: ConnId,
         bind_data: BindWallet,
     ) -> Result<WsResponse> {
-        let signature_ok = match bind_data.chain {
-            Chain::Solana => {
-                verify_signature_solana(&bind_data.address, &bind_data.signature).is_ok()
-            }
-            Chain::Evm => verify_signature_evm(&bind_data.address, &bind_data.signature).is_ok(),
-        };
+        let signature_ok = verify_sign_in(
+            &mut self.redis,
+            bind_data.chain,
+            &bind_data.address,
+            &bind_data.nonce,
+            &bind_data.signature,
+        )
+        .await
+        .is_ok();
 
         if !signature_ok {
             return Ok(WsResponse::bind_failed(String::from(
@@ -724,7 +945,8 @@ impl Server {
         self.get_me_with_rank_socials(conn_id).await
     }
 
-    pub async fn is_user_logged_in(&self, user_id: &str) -> bool {
-        self.active_client.contains_key(user_id)
+    /// Whether the user has a live connection on any instance
+    pub async fn is_user_logged_in(&mut self, user_id: &str) -> Result<bool> {
+        Ok(user_connection_count(&mut self.redis, user_id).await? > 0)
     }
 }
@@ -0,0 +1,158 @@
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use rand::RngExt as _;
+use rand::distr::Alphanumeric;
+use redis::aio::ConnectionManager;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::SIGN_IN_URI;
+use crate::ws::models::{Chain, SignInChallenge};
+use crate::ws::redis_ops::take_sign_in_message;
+use crate::ws::{parse_evm_address, verify_signature_evm, verify_signature_solana};
+
+/// How long a challenge can be signed and used
+pub const CHALLENGE_TTL: Duration = Duration::minutes(5);
+
+const NONCE_LENGTH: usize = 16;
+
+const STATEMENT: &str = "Sign in to the app. This request will not trigger a transaction.";
+
+/// The fields of a sign in message. The message is rendered from them both when it is handed
+/// out and when its signature is checked, so only messages this server issued can verify.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SignInMessage {
+    pub domain: String,
+    pub address: String,
+    pub uri: String,
+    pub chain: Chain,
+    pub chain_id: String,
+    pub nonce: String,
+    pub issued_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl SignInMessage {
+    pub fn new(chain: Chain, address: String, chain_id: Option<u64>) -> Result<Self> {
+        let uri = SIGN_IN_URI.get().unwrap().clone();
+        let url = Url::parse(&uri).context("SIGN_IN_URI is not a valid url")?;
+        let host = url.host_str().ok_or(anyhow!("SIGN_IN_URI has no host"))?;
+
+        let domain = match url.port() {
+            Some(port) => format!("{host}:{port}"),
+            None => host.to_string(),
+        };
+
+        let chain_id = match chain {
+            Chain::Evm => chain_id.unwrap_or(1).to_string(),
+            Chain::Solana => String::from("mainnet"),
+        };
+
+        let nonce = rand::rng()
+            .sample_iter(&Alphanumeric)
+            .take(NONCE_LENGTH)
+            .map(char::from)
+            .collect();
+
+        let issued_at = Utc::now();
+
+        Ok(Self {
+            domain,
+            address,
+            uri,
+            chain,
+            chain_id,
+            nonce,
+            issued_at,
+            expires_at: issued_at + CHALLENGE_TTL,
+        })
+    }
+
+    /// The text the wallet signs, in the EIP-4361 layout. Sign In With Solana uses the same
+    /// layout with another account name.
+    pub fn render(&self) -> String {
+        let account = match self.chain {
+            Chain::Evm => "Ethereum",
+            Chain::Solana => "Solana",
+        };
+
+        format!(
+            "{domain} wants you to sign in with your {account} account:\n\
+             {address}\n\
+             \n\
+             {STATEMENT}\n\
+             \n\
+             URI: {uri}\n\
+             Version: 1\n\
+             Chain ID: {chain_id}\n\
+             Nonce: {nonce}\n\
+             Issued At: {issued_at}\n\
+             Expiration Time: {expires_at}",
+            domain = self.domain,
+            address = self.address,
+            uri = self.uri,
+            chain_id = self.chain_id,
+            nonce = self.nonce,
+            issued_at = self.issued_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+            expires_at = self.expires_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+        )
+    }
+
+    /// Checks that the message was issued for `chain` and `address` and is still valid
+    pub fn check(&self, chain: Chain, address: &str) -> Result<()> {
+        if self.chain != chain {
+            return Err(anyhow!(
+                "Challenge was issued for {:?}, got {chain:?}",
+                self.chain
+            ));
+        }
+
+        // EVM messages carry the checksummed address, clients may send it in any case
+        let address_matches = match chain {
+            Chain::Solana => self.address == address,
+            Chain::Evm => parse_evm_address(address)
+                .is_ok_and(|address| address.to_checksum(None) == self.address),
+        };
+
+        if !address_matches {
+            return Err(anyhow!(
+                "Challenge was issued for {}, got {address}",
+                self.address
+            ));
+        }
+
+        if self.expires_at <= Utc::now() {
+            return Err(anyhow!("Challenge expired at {}", self.expires_at));
+        }
+
+        Ok(())
+    }
+
+    pub fn challenge(&self) -> SignInChallenge {
+        SignInChallenge {
+            nonce: self.nonce.clone(),
+            message: self.render(),
+            expires_at: self.expires_at,
+        }
+    }
+}
+
+/// Uses up the challenge issued with `nonce` and checks that `address` signed it
+pub async fn verify_sign_in(
+    redis: &mut ConnectionManager,
+    chain: Chain,
+    address: &str,
+    nonce: &str,
+    signature: &str,
+) -> Result<()> {
+    let message = take_sign_in_message(redis, nonce)
+        .await?
+        .ok_or(anyhow!("Nonce {nonce} is unknown, expired or already used"))?;
+
+    message.check(chain, address)?;
+
+    match chain {
+        Chain::Solana => verify_signature_solana(&message, signature),
+        Chain::Evm => verify_signature_evm(&message, signature),
+    }
+}
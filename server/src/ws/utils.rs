@@ -2,7 +2,9 @@ use alloy_core::primitives::{Address, Signature as SignatureAlloy};
 use anyhow::{Result, anyhow};
 use chrono::{Timelike, Utc};
 use db::get_redis_pubsub;
+use db::models::GameType;
 use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use log::error;
 use rand::RngExt as _;
 use rand::distr::Alphanumeric;
 use redis::PushInfo;
@@ -11,18 +13,41 @@ use reqwest::Client;
 use std::{str, time::Duration};
 use tokio::{sync::mpsc::UnboundedSender, time::sleep};
 
-use crate::IMAGEKIT_PRIVATE;
-use crate::ws::redis_ops::{DISCONNECTED_SUB, LEADERBOARD_SUB};
-
-pub fn verify_signature_solana(public_key: &str, signature: &str) -> Result<()> {
-    let message = craft_sign_message(public_key);
+use crate::ws::redis_ops::{
+    LEADERBOARD_SUB, TASKS_SUB, game_leaderboard_channel, instance_channel,
+};
+use crate::ws::sign_in::SignInMessage;
+use crate::{ALERT_WEBHOOK, IMAGEKIT_PRIVATE, INSTANCE_ID};
 
+pub fn parse_solana_key(public_key: &str) -> Result<VerifyingKey> {
     let pubkey_bytes_vec = bs58::decode(public_key).into_vec()?;
 
     let pubkey_bytes = pubkey_bytes_vec
         .try_into()
         .map_err(|_| anyhow!("Failed to convert public key bytes"))?;
 
+    Ok(VerifyingKey::from_bytes(&pubkey_bytes)?)
+}
+
+pub fn parse_evm_address(public_key: &str) -> Result<Address> {
+    if let Ok(address) = Address::parse_checksummed(public_key, None) {
+        return Ok(address);
+    }
+
+    let addr_bytes = hex::decode(public_key.trim_start_matches("0x"))?;
+
+    if addr_bytes.len() != 20 {
+        return Err(anyhow!("address must be 20 bytes"));
+    }
+    let mut arr = [0u8; 20];
+    arr.copy_from_slice(&addr_bytes);
+    Ok(Address::new(arr))
+}
+
+/// Checks a signature over the sign in message issued to a Solana wallet
+pub fn verify_signature_solana(message: &SignInMessage, signature: &str) -> Result<()> {
+    let verifying_key = parse_solana_key(&message.address)?;
+
     let signature_bytes_vec = bs58::decode(signature)
         .into_vec()
         .map_err(|_| anyhow!("Failed to decode signature from base58"))?;
@@ -33,35 +58,19 @@ pub fn verify_signature_solana(public_key: &str, signature: &str) -> Result<()>
 
     let signature = Signature::from_bytes(&signature_bytes);
 
-    let verifying_key = VerifyingKey::from_bytes(&pubkey_bytes)?;
-
-    verifying_key.verify(message.as_bytes(), &signature)?;
+    verifying_key.verify(message.render().as_bytes(), &signature)?;
 
     Ok(())
 }
 
-pub fn verify_signature_evm(public_key: &str, signature: &str) -> Result<()> {
-    let message = craft_sign_message(public_key);
-
-    let signer = Address::parse_checksummed(public_key, None);
-
-    let address = if let Ok(a) = signer {
-        a
-    } else {
-        let addr_bytes = hex::decode(public_key.trim_start_matches("0x"))?;
-
-        if addr_bytes.len() != 20 {
-            return Err(anyhow!("address must be 20 bytes"));
-        }
-        let mut arr = [0u8; 20];
-        arr.copy_from_slice(&addr_bytes);
-        Address::new(arr)
-    };
+/// Checks a signature over the sign in message issued to an EVM wallet
+pub fn verify_signature_evm(message: &SignInMessage, signature: &str) -> Result<()> {
+    let address = parse_evm_address(&message.address)?;
 
     let sig_bytes = hex::decode(signature.trim_start_matches("0x"))?;
     let signature = SignatureAlloy::try_from(sig_bytes.as_slice())?;
 
-    let recovered = signature.recover_address_from_msg(message.as_bytes())?;
+    let recovered = signature.recover_address_from_msg(message.render().as_bytes())?;
 
     if address != recovered {
         return Err(anyhow!("Invalid signature"));
@@ -76,8 +85,12 @@ pub async fn get_pubsub_conn(
 ) -> ConnectionManager {
     let mut pubsub = get_redis_pubsub(redis_url, sender).await;
 
+    let mut channels = vec![LEADERBOARD_SUB.to_string(), TASKS_SUB.to_string()];
+    channels.extend(GameType::ALL.map(game_leaderboard_channel));
+    channels.push(instance_channel(&INSTANCE_ID));
+
     pubsub
-        .subscribe(&[DISCONNECTED_SUB, LEADERBOARD_SUB])
+        .subscribe(&channels)
         .await
         .expect("Failed to subscribe to Redis pubsub channels");
     pubsub
@@ -110,6 +123,27 @@ pub async fn delete_old_photo(old_id: Option<String>) -> Result<()> {
     }
 }
 
+/// Logs a condition that needs a human and posts it to the alert webhook if one is set
+pub async fn send_alert(message: &str) -> Result<()> {
+    error!("ALERT: {message}");
+
+    let Some(webhook) = ALERT_WEBHOOK.get() else {
+        return Ok(());
+    };
+
+    let res = Client::new()
+        .post(webhook)
+        .json(&serde_json::json!({ "content": message }))
+        .send()
+        .await?;
+
+    if res.status().is_success() {
+        Ok(())
+    } else {
+        Err(anyhow!("Failed to post alert: {}", res.status()))
+    }
+}
+
 pub async fn sleep_remaining_time() {
     let now = Utc::now();
     let seconds_remaining = u64::from(60 - now.second());
@@ -141,7 +175,3 @@ pub fn generate_referral_code() -> String {
         .collect::<String>()
         .to_uppercase()
 }
-
-pub fn craft_sign_message(key: &str) -> String {
-    format!("Welcome to the app. Sign this message to continue. Signing with: {key}")
-}
@@ -6,7 +6,15 @@ pub const REFERRAL_BONUS: i32 = 1000;
 pub const MINIMUM_POINTS_FOR_REFERRAL: i32 = 1000;
 pub const GAME_BONUS_PERCENTAGE: i32 = 5;
 
-pub const BOARD_HEIGHT: i32 = 20;
+pub const MAX_LEADERBOARD_PAGE_SIZE: i64 = 100;
+/// Users returned on each side of the caller by default for an around me leaderboard
+pub const DEFAULT_NEIGHBOURS: i64 = 5;
+pub const MAX_NEIGHBOURS: i64 = 25;
+
+pub const BOARD_HEIGHT: usize = 20;
+pub const BOARD_WIDTH: usize = 10;
+/// Number of upcoming tetris pieces shown to the client after the current one
+pub const PREVIEW_SIZE: usize = 5;
 pub const LEVEL_UP: i32 = 10;
 pub const POINTS_PER_LINE: [i32; 5] = [0, 40, 100, 300, 1200];
 pub const MIN_TIME: i64 = 150;
@@ -17,8 +25,11 @@ pub const MULTIPLIER: f32 = 0.12;
 pub const GRID_SIZE: usize = 4;
 pub const ALLOWED_FUTURE_MS: i64 = 5000;
 
-pub const DEFAULT_BOARD: [[i32; 4]; 4] = [[2, 0, 0, 0], [0, 2, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0]];
-pub const VALID_NEW_VALUE: [i32; 2] = [2, 4];
+pub const INITIAL_TILES: usize = 2;
+pub const NEW_TILE_VALUE: i32 = 2;
+pub const RARE_NEW_TILE_VALUE: i32 = 4;
+/// 1 in `RARE_TILE_ODDS` spawned tiles is a `RARE_NEW_TILE_VALUE`
+pub const RARE_TILE_ODDS: usize = 10;
 pub const VALID_TILES: [i32; 13] = [2, 4, 8, 16, 32, 64, 128, 256, 512, 1024, 2048, 4096, 8192];
 
 pub static TILE_SCORE_MAP: LazyLock<HashMap<i32, i32>> = LazyLock::new(|| {
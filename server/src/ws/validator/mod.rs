@@ -1,5 +1,7 @@
 pub mod consts;
 pub mod flappy;
+pub mod rng;
 pub mod snake;
 pub mod tetris;
+pub mod tetris_engine;
 pub mod two048;
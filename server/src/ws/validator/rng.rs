@@ -0,0 +1,54 @@
+use serde::{Deserialize, Serialize};
+
+/// Small deterministic PRNG (SplitMix64) used for server-side game randomness.
+///
+/// The algorithm is fixed here rather than taken from `rand` so that a stored session seed
+/// always reproduces the exact same sequence, regardless of dependency upgrades.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SeededRng {
+    state: u64,
+}
+
+impl SeededRng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a value in `0..bound`. `bound` must not be 0.
+    pub fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_reference_splitmix64() {
+        let mut rng = SeededRng::new(0);
+
+        assert_eq!(rng.next_u64(), 0xE220_A839_7B1D_CDAF);
+        assert_eq!(rng.next_u64(), 0x6E78_9E6A_A1B9_65F4);
+        assert_eq!(rng.next_u64(), 0x06C4_5D18_8009_454F);
+    }
+
+    #[test]
+    fn same_seed_same_sequence() {
+        let mut first = SeededRng::new(42);
+        let mut second = SeededRng::new(42);
+
+        for _ in 0..100 {
+            assert_eq!(first.below(7), second.below(7));
+        }
+    }
+}
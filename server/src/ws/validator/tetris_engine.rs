@@ -0,0 +1,447 @@
+use std::collections::VecDeque;
+
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Utc};
+use db::models::{DropKind, TetrisPiece, TetrisSnapshot};
+use serde::{Deserialize, Serialize};
+
+use crate::ws::models::{TetrisBoard, TetrisData, TetrisPlacement};
+use crate::ws::validator::consts::{
+    BOARD_HEIGHT, BOARD_WIDTH, LEVEL_UP, POINTS_PER_LINE, PREVIEW_SIZE,
+};
+use crate::ws::validator::rng::SeededRng;
+
+const BAG: [TetrisPiece; 7] = [
+    TetrisPiece::I,
+    TetrisPiece::O,
+    TetrisPiece::T,
+    TetrisPiece::S,
+    TetrisPiece::Z,
+    TetrisPiece::J,
+    TetrisPiece::L,
+];
+
+/// Column shifts tried in order when a turn does not fit where the piece is
+const KICKS: [i32; 5] = [0, -1, 1, -2, 2];
+
+/// Cells of a piece after it has been dropped, as (row, col)
+pub struct Landing {
+    pub cells: Vec<(usize, usize)>,
+    pub distance: usize,
+}
+
+pub struct PlacementResult {
+    pub lines_cleared: i32,
+    pub line_points: i32,
+    pub drop_points: i32,
+}
+
+/// Server side tetris board with a seeded 7-bag piece queue.
+///
+/// A placement is the piece turned and shifted at the top of the board from where it spawns,
+/// then dropped straight down. The first `PREVIEW_SIZE + 1` pieces of the queue are always generated.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TetrisEngine {
+    board: TetrisBoard,
+    queue: VecDeque<TetrisPiece>,
+}
+
+impl TetrisEngine {
+    pub fn new(rng: &mut SeededRng) -> Self {
+        let mut engine = Self {
+            board: vec![vec![None; BOARD_WIDTH]; BOARD_HEIGHT],
+            queue: VecDeque::new(),
+        };
+
+        engine.refill_queue(rng);
+        engine
+    }
+
+    pub fn board(&self) -> &TetrisBoard {
+        &self.board
+    }
+
+    pub fn current_piece(&self) -> TetrisPiece {
+        self.queue[0]
+    }
+
+    pub fn next_pieces(&self) -> Vec<TetrisPiece> {
+        self.queue
+            .iter()
+            .skip(1)
+            .take(PREVIEW_SIZE)
+            .copied()
+            .collect()
+    }
+
+    /// The state sent before any piece has been placed
+    pub fn initial_state(&self, timestamp: DateTime<Utc>) -> TetrisData {
+        TetrisData {
+            timestamp,
+            prev_timestamp: timestamp,
+            points: 0,
+            lines: 0,
+            level: 1,
+            prev_points: 0,
+            prev_lines: 0,
+            prev_level: 1,
+            board: self.board.clone(),
+            current_piece: self.current_piece(),
+            next_pieces: self.next_pieces(),
+        }
+    }
+
+    /// The state of the last placement, with this engine being the board after it
+    pub fn state_from_snapshot(&self, snapshot: &TetrisSnapshot) -> TetrisData {
+        TetrisData {
+            timestamp: snapshot.timestamp,
+            prev_timestamp: snapshot.prev_timestamp,
+            points: snapshot.points,
+            prev_points: snapshot.prev_points,
+            lines: snapshot.lines,
+            prev_lines: snapshot.prev_lines,
+            level: snapshot.level,
+            prev_level: snapshot.prev_level,
+            board: self.board.clone(),
+            current_piece: self.current_piece(),
+            next_pieces: self.next_pieces(),
+        }
+    }
+
+    /// Builds the state after this engine has applied `placement` on top of `last_move`
+    pub fn state_after(
+        &self,
+        placement: &TetrisPlacement,
+        last_move: &TetrisData,
+        result: &PlacementResult,
+    ) -> TetrisData {
+        let lines = last_move.lines + result.lines_cleared;
+
+        TetrisData {
+            timestamp: placement.timestamp,
+            prev_timestamp: placement.prev_timestamp,
+            points: last_move.points + result.line_points + result.drop_points,
+            prev_points: last_move.points,
+            lines,
+            prev_lines: last_move.lines,
+            level: (lines / LEVEL_UP) + 1,
+            prev_level: last_move.level,
+            board: self.board.clone(),
+            current_piece: self.current_piece(),
+            next_pieces: self.next_pieces(),
+        }
+    }
+
+    /// Finds where a piece would land without touching the board.
+    ///
+    /// The piece spawns at the top in the middle of the board, is turned to `rotation` with wall
+    /// kicks, shifted one column at a time until its leftmost cell is at `column`, then dropped.
+    /// Every step has to fit, so a placement behind a wall of blocks is refused.
+    pub fn landing(&self, piece: TetrisPiece, rotation: i32, column: i32) -> Result<Landing> {
+        // Three clockwise turns are one counterclockwise turn
+        let turns: &[bool] = match rotation {
+            0 => &[],
+            1 => &[true],
+            2 => &[true, true],
+            3 => &[false],
+            _ => return Err(anyhow!("Rotation {rotation} is not valid")),
+        };
+
+        let (size, mut shape) = spawn_shape(piece);
+        let mut x = ((BOARD_WIDTH - size) / 2) as i32;
+
+        if !self.fits(&shape, x, 0) {
+            return Err(anyhow!("Board topped out. {piece:?} cannot spawn"));
+        }
+
+        for &clockwise in turns {
+            let turned = rotate(&shape, size, clockwise);
+            let kick = KICKS
+                .iter()
+                .find(|&&kick| self.fits(&turned, x + kick, 0))
+                .ok_or(anyhow!("{piece:?} cannot rotate to {rotation}"))?;
+
+            x += kick;
+            shape = turned;
+        }
+
+        let left = shape.iter().map(|&(cell_x, _)| cell_x).min().unwrap_or(0);
+        let target = column - left;
+
+        while x != target {
+            let step = (target - x).signum();
+            if !self.fits(&shape, x + step, 0) {
+                return Err(anyhow!(
+                    "{piece:?} with rotation {rotation} cannot reach column {column}"
+                ));
+            }
+            x += step;
+        }
+
+        let mut distance = 0;
+        while self.fits(&shape, x, distance + 1) {
+            distance += 1;
+        }
+
+        let cells = shape
+            .iter()
+            .map(|&(cell_x, cell_y)| ((cell_y + distance) as usize, (cell_x + x) as usize))
+            .collect();
+
+        Ok(Landing {
+            cells,
+            distance: distance as usize,
+        })
+    }
+
+    /// Whether the cells of a piece moved by `x` and `y` are on the board and free
+    fn fits(&self, shape: &[(i32, i32)], x: i32, y: i32) -> bool {
+        shape.iter().all(|&(cell_x, cell_y)| {
+            let (col, row) = (cell_x + x, cell_y + y);
+
+            (0..BOARD_WIDTH as i32).contains(&col)
+                && (0..BOARD_HEIGHT as i32).contains(&row)
+                && self.board[row as usize][col as usize].is_none()
+        })
+    }
+
+    /// Locks the current piece, clears full lines and advances the queue.
+    ///
+    /// `level` is the level the piece was placed at, before any line from this placement counts.
+    /// Every row the piece falls scores one point on a soft drop and two on a hard drop.
+    pub fn place(
+        &mut self,
+        piece: TetrisPiece,
+        rotation: i32,
+        column: i32,
+        drop_kind: DropKind,
+        level: i32,
+        rng: &mut SeededRng,
+    ) -> Result<PlacementResult> {
+        let current = self.current_piece();
+        if piece != current {
+            return Err(anyhow!("Expected piece {current:?}, got {piece:?}"));
+        }
+
+        let landing = self.landing(piece, rotation, column)?;
+
+        for (row, col) in landing.cells {
+            self.board[row][col] = Some(piece);
+        }
+
+        self.board.retain(|row| row.iter().any(Option::is_none));
+        let lines_cleared = BOARD_HEIGHT - self.board.len();
+
+        for _ in 0..lines_cleared {
+            self.board.insert(0, vec![None; BOARD_WIDTH]);
+        }
+
+        self.queue.pop_front();
+        self.refill_queue(rng);
+
+        Ok(PlacementResult {
+            lines_cleared: lines_cleared as i32,
+            line_points: POINTS_PER_LINE[lines_cleared] * level,
+            drop_points: landing.distance as i32 * drop_multiplier(drop_kind),
+        })
+    }
+
+    fn refill_queue(&mut self, rng: &mut SeededRng) {
+        while self.queue.len() <= PREVIEW_SIZE {
+            let mut bag = BAG;
+
+            // Fisher-Yates so every bag order is equally likely
+            for i in (1..bag.len()).rev() {
+                let j = rng.below(i + 1);
+                bag.swap(i, j);
+            }
+
+            self.queue.extend(bag);
+        }
+    }
+}
+
+/// The (x, y) cells of a piece in its spawn orientation and the size of the box it turns in
+fn spawn_shape(piece: TetrisPiece) -> (usize, Vec<(i32, i32)>) {
+    match piece {
+        TetrisPiece::I => (4, vec![(0, 1), (1, 1), (2, 1), (3, 1)]),
+        TetrisPiece::O => (2, vec![(0, 0), (1, 0), (0, 1), (1, 1)]),
+        TetrisPiece::T => (3, vec![(1, 0), (0, 1), (1, 1), (2, 1)]),
+        TetrisPiece::S => (3, vec![(1, 0), (2, 0), (0, 1), (1, 1)]),
+        TetrisPiece::Z => (3, vec![(0, 0), (1, 0), (1, 1), (2, 1)]),
+        TetrisPiece::J => (3, vec![(0, 0), (0, 1), (1, 1), (2, 1)]),
+        TetrisPiece::L => (3, vec![(2, 0), (0, 1), (1, 1), (2, 1)]),
+    }
+}
+
+/// Turns the cells a quarter inside their box
+fn rotate(shape: &[(i32, i32)], size: usize, clockwise: bool) -> Vec<(i32, i32)> {
+    let last = size as i32 - 1;
+
+    shape
+        .iter()
+        .map(|&(x, y)| {
+            if clockwise {
+                (last - y, x)
+            } else {
+                (y, last - x)
+            }
+        })
+        .collect()
+}
+
+/// Points per row a piece falls
+const fn drop_multiplier(drop_kind: DropKind) -> i32 {
+    match drop_kind {
+        DropKind::Soft => 1,
+        DropKind::Hard => 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use TetrisPiece::*;
+
+    fn empty_board() -> TetrisBoard {
+        vec![vec![None; BOARD_WIDTH]; BOARD_HEIGHT]
+    }
+
+    fn engine_with(board: TetrisBoard, pieces: &[TetrisPiece]) -> TetrisEngine {
+        TetrisEngine {
+            board,
+            queue: pieces.iter().copied().collect(),
+        }
+    }
+
+    #[test]
+    fn seed_pins_piece_sequence() {
+        let mut rng = SeededRng::new(42);
+        let mut engine = TetrisEngine::new(&mut rng);
+
+        assert_eq!(engine.current_piece(), T);
+        assert_eq!(engine.next_pieces(), vec![Z, L, I, S, O]);
+
+        let mut pieces = vec![];
+        for _ in 0..14 {
+            pieces.push(engine.current_piece());
+            engine.queue.pop_front();
+            engine.refill_queue(&mut rng);
+        }
+
+        assert_eq!(pieces, vec![T, Z, L, I, S, O, J, O, Z, S, J, I, L, T]);
+    }
+
+    #[test]
+    fn every_bag_holds_each_piece_once() {
+        let mut rng = SeededRng::new(7);
+        let mut engine = TetrisEngine::new(&mut rng);
+
+        for _ in 0..10 {
+            let mut bag: Vec<TetrisPiece> = engine.queue.drain(..7).collect();
+            bag.sort_by_key(|piece| BAG.iter().position(|p| p == piece));
+            assert_eq!(bag, BAG);
+
+            engine.refill_queue(&mut rng);
+        }
+    }
+
+    #[test]
+    fn rotation_turns_inside_the_box() {
+        let (size, shape) = spawn_shape(T);
+
+        let once = rotate(&shape, size, true);
+        assert_eq!(once, vec![(2, 1), (1, 0), (1, 1), (1, 2)]);
+
+        let back = rotate(&once, size, false);
+        assert_eq!(back, shape);
+
+        let full_turn = (0..4).fold(shape.clone(), |cells, _| rotate(&cells, size, true));
+        assert_eq!(full_turn, shape);
+    }
+
+    #[test]
+    fn line_clear_scores_lines_and_drop_distance() {
+        let mut board = empty_board();
+        for col in 4..BOARD_WIDTH {
+            board[BOARD_HEIGHT - 1][col] = Some(O);
+        }
+
+        let mut engine = engine_with(board, &[I, O, T, S, Z, J, L]);
+        let mut rng = SeededRng::new(1);
+
+        let result = engine.place(I, 0, 0, DropKind::Soft, 2, &mut rng).unwrap();
+
+        assert_eq!(result.lines_cleared, 1);
+        assert_eq!(result.line_points, POINTS_PER_LINE[1] * 2);
+        // Spawns on row 1 and falls to the bottom row
+        assert_eq!(result.drop_points, 18);
+        assert_eq!(engine.board(), &empty_board());
+        assert_eq!(engine.current_piece(), O);
+    }
+
+    #[test]
+    fn placement_lands_on_the_stack() {
+        let mut board = empty_board();
+        board[BOARD_HEIGHT - 1][0] = Some(L);
+
+        let mut engine = engine_with(board, &[O, I, T, S, Z, J, L]);
+        let mut rng = SeededRng::new(1);
+
+        let result = engine.place(O, 0, 0, DropKind::Soft, 1, &mut rng).unwrap();
+
+        assert_eq!(result.lines_cleared, 0);
+        assert_eq!(result.drop_points, 17);
+
+        for (row, col) in [(17, 0), (17, 1), (18, 0), (18, 1)] {
+            assert_eq!(engine.board()[row][col], Some(O));
+        }
+        assert_eq!(engine.board()[BOARD_HEIGHT - 2][2], None);
+    }
+
+    #[test]
+    fn hard_drop_scores_twice_the_distance() {
+        let mut engine = engine_with(empty_board(), &[O, I, T, S, Z, J, L]);
+        let mut rng = SeededRng::new(1);
+
+        let result = engine.place(O, 0, 0, DropKind::Hard, 1, &mut rng).unwrap();
+
+        // Spawns on rows 0 and 1 and falls to the bottom two rows
+        assert_eq!(result.drop_points, 18 * 2);
+    }
+
+    #[test]
+    fn rotation_kicks_off_a_blocked_cell() {
+        let mut board = empty_board();
+        // Where a vertical I would turn at spawn
+        board[3][5] = Some(Z);
+
+        let engine = engine_with(board, &[I, O, T, S, Z, J, L]);
+        let landing = engine.landing(I, 1, 0).unwrap();
+
+        assert_eq!(landing.distance, 16);
+        assert_eq!(landing.cells, vec![(16, 0), (17, 0), (18, 0), (19, 0)]);
+    }
+
+    #[test]
+    fn blocked_path_is_refused() {
+        let mut board = empty_board();
+        // A wall between the spawn and the left edge, column 0 itself is free
+        board[0][1] = Some(Z);
+        board[1][1] = Some(Z);
+
+        let engine = engine_with(board, &[O, I, T, S, Z, J, L]);
+
+        assert!(engine.landing(O, 0, 0).is_err());
+        assert!(engine.landing(O, 0, 8).is_ok());
+    }
+
+    #[test]
+    fn out_of_bounds_placement_is_refused() {
+        let engine = engine_with(empty_board(), &[I, O, T, S, Z, J, L]);
+
+        assert!(engine.landing(I, 0, 7).is_err());
+        assert!(engine.landing(I, 1, 9).is_ok());
+        assert!(engine.landing(I, 4, 0).is_err());
+    }
+}
@@ -1,28 +1,30 @@
 use anyhow::{Result, anyhow};
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use db::models::Direction;
 
-use crate::ws::models::Two048Data;
+use crate::ws::models::{Two048Data, Two048Move, Two048Tile};
 use crate::ws::validator::consts::{
-    ALLOWED_FUTURE_MS, GRID_SIZE, TILE_SCORE_MAP, VALID_NEW_VALUE, VALID_TILES,
+    ALLOWED_FUTURE_MS, GRID_SIZE, INITIAL_TILES, NEW_TILE_VALUE, RARE_NEW_TILE_VALUE,
+    RARE_TILE_ODDS, TILE_SCORE_MAP, VALID_TILES,
 };
+use crate::ws::validator::rng::SeededRng;
 
-pub fn two048_move_valid(data: &Two048Data, last_move: &Option<Two048Data>) -> Result<()> {
+pub fn two048_start_valid(timestamp: &DateTime<Utc>) -> Result<()> {
     let now = Utc::now();
 
-    if data.board == data.prev_board {
+    if timestamp.timestamp_millis() > now.timestamp_millis() + ALLOWED_FUTURE_MS {
         return Err(anyhow!(
-            "Board didn't change — this should not have been sent."
+            "Start timestamp {} is too far in the future (now: {})",
+            timestamp,
+            now
         ));
     }
 
-    if data.board.len() != GRID_SIZE
-        || data.board.iter().any(|row| row.len() != GRID_SIZE)
-        || data.prev_board.len() != GRID_SIZE
-        || data.prev_board.iter().any(|row| row.len() != GRID_SIZE)
-    {
-        return Err(anyhow!("Board dimensions are incorrect."));
-    }
+    Ok(())
+}
+
+pub fn two048_move_valid(data: &Two048Move, last_move: &Two048Data) -> Result<()> {
+    let now = Utc::now();
 
     if data.timestamp < data.prev_timestamp {
         return Err(anyhow!(
@@ -40,148 +42,83 @@ pub fn two048_move_valid(data: &Two048Data, last_move: &Option<Two048Data>) -> R
         ));
     }
 
-    let simulated_move = move_board(data.prev_board.clone(), data.direction);
-
-    if simulated_move == data.prev_board {
-        return Err(anyhow!("Move caused no change in board state."));
-    }
-
-    let new_tiles = find_all_new_tiles(&simulated_move, &data.board);
-    if new_tiles.len() != 1 {
-        return Err(anyhow!("Expected 1 new tile, found {}", new_tiles.len()));
-    }
-
-    let (new_r, new_c, new_val) = new_tiles[0];
-    if !VALID_NEW_VALUE.contains(&new_val) {
-        return Err(anyhow!("New tile value {} is not valid.", new_val));
-    }
-
-    let mut corrected_frontend_board = data.board.clone();
-    corrected_frontend_board[new_r][new_c] = 0;
-
-    if simulated_move != corrected_frontend_board {
+    if data.prev_timestamp != last_move.timestamp {
         return Err(anyhow!(
-            "Simulated move does not match frontend board after removing new tile."
+            "Provided prev_timestamp {} does not match server's {}",
+            data.prev_timestamp,
+            last_move.timestamp
         ));
     }
 
-    let Some(max_idx) = VALID_TILES
-        .iter()
-        .position(|&t| t == find_max_tile(&data.board))
-    else {
-        return Err(anyhow!("Current max tile is invalid."));
-    };
+    if last_move.board.len() != GRID_SIZE
+        || last_move.board.iter().any(|row| row.len() != GRID_SIZE)
+    {
+        return Err(anyhow!("Board dimensions are incorrect."));
+    }
 
-    let Some(prev_idx) = VALID_TILES
-        .iter()
-        .position(|&t| t == find_max_tile(&data.prev_board))
-    else {
-        return Err(anyhow!("Previous max tile is invalid."));
-    };
+    let simulated_move = move_board(last_move.board.clone(), data.direction);
 
-    if max_idx < prev_idx {
-        return Err(anyhow!(
-            "Max tile decreased: prev index {} -> current index {}",
-            prev_idx,
-            max_idx
-        ));
+    if simulated_move == last_move.board {
+        return Err(anyhow!("Move caused no change in board state."));
     }
 
-    if max_idx > prev_idx + 1 {
-        return Err(anyhow!(
-            "Max tile jumped more than one step: {} -> {}",
-            prev_idx,
-            max_idx
-        ));
-    }
+    Ok(())
+}
 
-    let current_expected_score = expected_score(VALID_TILES[max_idx]);
-    let prev_expected_score = expected_score(VALID_TILES[prev_idx]);
-
-    if max_idx == prev_idx + 1 {
-        let expected_diff = TILE_SCORE_MAP
-            .get(&VALID_TILES[max_idx])
-            .copied()
-            .unwrap_or(0);
-
-        if current_expected_score != prev_expected_score + expected_diff {
-            return Err(anyhow!(
-                "Expected score to increase by {}. Prev: {}, Got: {}, Actual: {}",
-                expected_diff,
-                prev_expected_score,
-                current_expected_score,
-                data.points
-            ));
-        }
-    } else if current_expected_score != prev_expected_score {
-        return Err(anyhow!(
-            "Score changed unexpectedly. Prev: {}, Got: {}",
-            prev_expected_score,
-            current_expected_score
-        ));
+/// Applies an already validated move on top of the last known state and spawns the next tile
+/// using the session RNG. The server is the only side that decides where tiles appear.
+pub fn two048_next_state(
+    data: &Two048Move,
+    last_move: &Two048Data,
+    rng: &mut SeededRng,
+) -> Two048Data {
+    let mut board = move_board(last_move.board.clone(), data.direction);
+    let new_tile = spawn_tile(&mut board, rng);
+    let highest_number = find_max_tile(&board);
+
+    Two048Data {
+        timestamp: data.timestamp,
+        prev_timestamp: data.prev_timestamp,
+        board,
+        prev_board: last_move.board.clone(),
+        direction: data.direction,
+        points: expected_score(highest_number),
+        prev_points: last_move.points,
+        highest_number,
+        prev_highest_number: last_move.highest_number,
+        new_tile,
     }
+}
 
-    if let Some(last) = last_move {
-        if data.prev_board != last.board {
-            return Err(anyhow!(
-                "Provided prev_board does not match server's last board."
-            ));
-        }
-        if data.prev_points != last.points {
-            return Err(anyhow!(
-                "Provided prev_points ({}) does not match server's ({})",
-                data.prev_points,
-                last.points
-            ));
-        }
-        if data.prev_highest_number != last.highest_number {
-            return Err(anyhow!(
-                "Provided prev_highest_number ({}) does not match server's ({}).",
-                data.prev_highest_number,
-                last.highest_number
-            ));
-        }
-    } else {
-        if data.prev_points != 0 {
-            return Err(anyhow!(
-                "2048 init state invalid. Points should start at 0, got current: {}, prev: {}",
-                data.points,
-                data.prev_points
-            ));
-        }
-
-        let initial_tile_values: Vec<i32> = data
-            .prev_board
-            .iter()
-            .flatten()
-            .filter(|&&v| v != 0)
-            .copied()
-            .collect();
-
-        if initial_tile_values.len() != 2 {
-            return Err(anyhow!(
-                "2048 init state invalid. Should have 2 tiles, found {}",
-                initial_tile_values.len()
-            ));
-        }
+/// The state sent before any move has been made
+pub fn two048_initial_state(timestamp: DateTime<Utc>, rng: &mut SeededRng) -> Two048Data {
+    let board = initial_board(rng);
+    let highest_number = find_max_tile(&board);
+
+    Two048Data {
+        timestamp,
+        prev_timestamp: timestamp,
+        board: board.clone(),
+        prev_board: board,
+        direction: Direction::Up,
+        highest_number,
+        prev_highest_number: highest_number,
+        points: 0,
+        prev_points: 0,
+        new_tile: None,
+    }
+}
 
-        if !initial_tile_values
-            .iter()
-            .all(|&v| VALID_NEW_VALUE.contains(&v))
-        {
-            return Err(anyhow!(
-                "2048 init state invalid. Initial tiles have invalid values."
-            ));
-        }
+/// Creates the starting board of a session. Always consumes the first `INITIAL_TILES` spawns of
+/// the RNG so a seed fully describes the game.
+pub fn initial_board(rng: &mut SeededRng) -> Vec<Vec<i32>> {
+    let mut board = vec![vec![0; GRID_SIZE]; GRID_SIZE];
 
-        if data.prev_highest_number != 0 {
-            return Err(anyhow!(
-                "2048 init state invalid. Highest number should be 0 at start."
-            ));
-        }
+    for _ in 0..INITIAL_TILES {
+        spawn_tile(&mut board, rng);
     }
 
-    Ok(())
+    board
 }
 
 fn move_board(mut board: Vec<Vec<i32>>, direction: Direction) -> Vec<Vec<i32>> {
@@ -241,22 +178,33 @@ fn process_row(row: &[i32]) -> Vec<i32> {
     new_row
 }
 
-fn find_all_new_tiles(
-    moved_board: &[Vec<i32>],
-    frontend_board: &[Vec<i32>],
-) -> Vec<(usize, usize, i32)> {
-    let mut diffs = vec![];
-    for r in 0..GRID_SIZE {
-        for c in 0..GRID_SIZE {
-            if moved_board[r][c] == 0 && frontend_board[r][c] != 0 {
-                diffs.push((r, c, frontend_board[r][c]));
+fn spawn_tile(board: &mut [Vec<i32>], rng: &mut SeededRng) -> Option<Two048Tile> {
+    let mut empty_cells = vec![];
+    for (r, row) in board.iter().enumerate() {
+        for (c, &val) in row.iter().enumerate() {
+            if val == 0 {
+                empty_cells.push((r, c));
             }
         }
     }
-    diffs
+
+    if empty_cells.is_empty() {
+        return None;
+    }
+
+    let (row, col) = empty_cells[rng.below(empty_cells.len())];
+    let value = if rng.below(RARE_TILE_ODDS) == 0 {
+        RARE_NEW_TILE_VALUE
+    } else {
+        NEW_TILE_VALUE
+    };
+
+    board[row][col] = value;
+
+    Some(Two048Tile { row, col, value })
 }
 
-fn find_max_tile(board: &[Vec<i32>]) -> i32 {
+pub fn find_max_tile(board: &[Vec<i32>]) -> i32 {
     let mut max_tile = 0;
 
     for row in board {
@@ -270,7 +218,7 @@ fn find_max_tile(board: &[Vec<i32>]) -> i32 {
     max_tile
 }
 
-fn expected_score(max_tile: i32) -> i32 {
+pub fn expected_score(max_tile: i32) -> i32 {
     let mut score = 0;
 
     for tile in VALID_TILES {
@@ -286,3 +234,81 @@ fn expected_score(max_tile: i32) -> i32 {
 
     score
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seed_pins_initial_board() {
+        let mut rng = SeededRng::new(42);
+
+        assert_eq!(
+            initial_board(&mut rng),
+            vec![
+                vec![0, 0, 0, 2],
+                vec![0, 2, 0, 0],
+                vec![0, 0, 0, 0],
+                vec![0, 0, 0, 0],
+            ]
+        );
+    }
+
+    #[test]
+    fn next_state_spawns_from_the_session_rng() {
+        let now = Utc::now();
+        let mut rng = SeededRng::new(7);
+        let start = two048_initial_state(now, &mut rng);
+
+        assert_eq!(start.board[1], vec![0, 0, 2, 2]);
+
+        let data = Two048Move {
+            timestamp: now,
+            prev_timestamp: now,
+            direction: Direction::Left,
+        };
+        let next = two048_next_state(&data, &start, &mut rng);
+
+        assert_eq!(
+            next.board,
+            vec![
+                vec![0, 0, 0, 0],
+                vec![4, 2, 0, 0],
+                vec![0, 0, 0, 0],
+                vec![0, 0, 0, 0],
+            ]
+        );
+
+        let tile = next.new_tile.unwrap();
+        assert_eq!((tile.row, tile.col, tile.value), (1, 1, NEW_TILE_VALUE));
+        assert_eq!(next.highest_number, 4);
+        assert_eq!(next.prev_board, start.board);
+    }
+
+    #[test]
+    fn full_board_spawns_nothing() {
+        let mut board = vec![
+            vec![2, 4, 2, 4],
+            vec![4, 2, 4, 2],
+            vec![2, 4, 2, 4],
+            vec![4, 2, 4, 2],
+        ];
+        let mut rng = SeededRng::new(1);
+
+        assert!(spawn_tile(&mut board, &mut rng).is_none());
+    }
+
+    #[test]
+    fn rows_merge_once_per_move() {
+        assert_eq!(process_row(&[2, 2, 4, 4]), vec![4, 8, 0, 0]);
+        assert_eq!(process_row(&[2, 2, 2, 0]), vec![4, 2, 0, 0]);
+        assert_eq!(process_row(&[0, 4, 0, 4]), vec![8, 0, 0, 0]);
+    }
+
+    #[test]
+    fn score_counts_every_tile_up_to_the_highest() {
+        assert_eq!(expected_score(4), 0);
+        assert_eq!(expected_score(8), 40);
+        assert_eq!(expected_score(32), 40 + 80 + 150);
+    }
+}
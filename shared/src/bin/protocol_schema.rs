@@ -0,0 +1,18 @@
+//! Prints the JSON Schema of the WebSocket protocol.
+//!
+//! Usage: `cargo run -p shared --features schema --bin protocol_schema > protocol.json`.
+//! TypeScript definitions can be generated from the output with `json-schema-to-typescript`.
+
+use schemars::schema_for;
+use serde_json::json;
+use shared::protocol::{PROTOCOL_VERSION, RequestEnvelope, WsResponse};
+
+fn main() {
+    let schema = json!({
+        "protocol_version": PROTOCOL_VERSION,
+        "request": schema_for!(RequestEnvelope),
+        "response": schema_for!(WsResponse),
+    });
+
+    println!("{}", serde_json::to_string_pretty(&schema).unwrap());
+}
@@ -1,4 +1,5 @@
 pub mod models;
+pub mod protocol;
 
 #[must_use]
 pub fn extract_reddit_id(url: &str) -> Option<&str> {
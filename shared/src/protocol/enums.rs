@@ -0,0 +1,198 @@
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "diesel",
+    derive(diesel_derive_enum::DbEnum),
+    db_enum(existing_type_path = "crate::protocol::sql_types::GameType")
+)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum GameType {
+    Snake,
+    Tetris,
+    Flappy,
+    Two048,
+}
+
+impl GameType {
+    pub const ALL: [Self; 4] = [Self::Snake, Self::Tetris, Self::Flappy, Self::Two048];
+
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GameType::Tetris => "tetris",
+            GameType::Snake => "snake",
+            GameType::Two048 => "two048",
+            GameType::Flappy => "flappy",
+        }
+    }
+}
+
+impl FromStr for GameType {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "tetris" => Ok(GameType::Tetris),
+            "snake" => Ok(GameType::Snake),
+            "2048" | "two048" => Ok(GameType::Two048),
+            "flappy" => Ok(GameType::Flappy),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "diesel",
+    derive(diesel_derive_enum::DbEnum),
+    db_enum(existing_type_path = "crate::protocol::sql_types::LeaderboardWindow")
+)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum LeaderboardWindow {
+    Daily,
+    Weekly,
+    Season,
+}
+
+impl LeaderboardWindow {
+    pub const ALL: [Self; 3] = [Self::Daily, Self::Weekly, Self::Season];
+
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LeaderboardWindow::Daily => "daily",
+            LeaderboardWindow::Weekly => "weekly",
+            LeaderboardWindow::Season => "season",
+        }
+    }
+}
+
+/// Moderation state of a user
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "diesel",
+    derive(diesel_derive_enum::DbEnum),
+    db_enum(existing_type_path = "crate::protocol::sql_types::UserStatus")
+)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum UserStatus {
+    #[default]
+    Active,
+    Banned,
+    /// Can keep playing but never shows up to others or earns anyone a bonus
+    ShadowBanned,
+}
+
+/// Why the points of a user changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "diesel",
+    derive(diesel_derive_enum::DbEnum),
+    db_enum(existing_type_path = "crate::protocol::sql_types::PointSource")
+)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum PointSource {
+    /// Points held when the ledger was introduced
+    Opening,
+    GameSession,
+    Task,
+    Referral,
+    Admin,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq, Hash)]
+#[cfg_attr(
+    feature = "diesel",
+    derive(diesel_derive_enum::DbEnum),
+    db_enum(existing_type_path = "crate::protocol::sql_types::TaskType")
+)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum TaskType {
+    JoinDiscord,
+    FollowTwitter,
+    JoinTelegram,
+    CreateTweet,
+    CheckTelegramPost,
+    CheckDiscordPost,
+    RetweetPost,
+    LikeTweet,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq, Hash)]
+#[cfg_attr(
+    feature = "diesel",
+    derive(diesel_derive_enum::DbEnum),
+    db_enum(existing_type_path = "crate::protocol::sql_types::Platform")
+)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum Platform {
+    Discord,
+    Twitter,
+    Telegram,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "diesel",
+    derive(diesel_derive_enum::DbEnum),
+    db_enum(existing_type_path = "crate::protocol::sql_types::TetrisPiece")
+)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum TetrisPiece {
+    I,
+    O,
+    T,
+    S,
+    Z,
+    J,
+    L,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "diesel",
+    derive(diesel_derive_enum::DbEnum),
+    db_enum(existing_type_path = "crate::protocol::sql_types::DropKind")
+)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum DropKind {
+    Soft,
+    Hard,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "diesel",
+    derive(diesel_derive_enum::DbEnum),
+    db_enum(existing_type_path = "crate::protocol::sql_types::Direction")
+)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum Chain {
+    Solana,
+    Evm,
+}
+
+/// Wire format of a connection, picked with the `encoding` query parameter of `/ws`.
+///
+/// JSON goes over text frames. MessagePack goes over binary frames with structs encoded as
+/// maps, so both carry the same field names.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum Encoding {
+    #[default]
+    #[serde(rename = "json")]
+    Json,
+    #[serde(rename = "msgpack")]
+    MessagePack,
+}
@@ -0,0 +1,131 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::{Direction, DropKind, GameType, TetrisPiece};
+
+/// Row 0 is the top of the board. Filled cells keep the piece that locked there.
+pub type TetrisBoard = Vec<Vec<Option<TetrisPiece>>>;
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TetrisData {
+    pub timestamp: DateTime<Utc>,
+    pub prev_timestamp: DateTime<Utc>,
+    pub points: i32,
+    pub prev_points: i32,
+    pub lines: i32,
+    pub prev_lines: i32,
+    pub level: i32,
+    pub prev_level: i32,
+    pub board: TetrisBoard,
+    pub current_piece: TetrisPiece,
+    pub next_pieces: Vec<TetrisPiece>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TetrisPlacement {
+    pub timestamp: DateTime<Utc>,
+    pub prev_timestamp: DateTime<Utc>,
+    pub piece: TetrisPiece,
+    pub rotation: i32,
+    pub column: i32,
+    /// Rows the piece falls score one point each on a soft drop and two on a hard drop
+    pub drop_kind: DropKind,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SnakeData {
+    pub timestamp: DateTime<Utc>,
+    pub prev_timestamp: DateTime<Utc>,
+    pub points: i32,
+    pub prev_points: i32,
+    pub length: i32,
+    pub prev_length: i32,
+    pub level: i32,
+    pub prev_level: i32,
+}
+
+impl SnakeData {
+    #[must_use]
+    pub fn new() -> Self {
+        let now = Utc::now();
+        Self {
+            timestamp: now,
+            prev_timestamp: now,
+            points: 0,
+            prev_points: 0,
+            length: 1,
+            prev_length: 1,
+            level: 1,
+            prev_level: 1,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Two048Data {
+    pub timestamp: DateTime<Utc>,
+    pub prev_timestamp: DateTime<Utc>,
+    pub board: Vec<Vec<i32>>,
+    pub prev_board: Vec<Vec<i32>>,
+    pub direction: Direction,
+    pub points: i32,
+    pub prev_points: i32,
+    pub highest_number: i32,
+    pub prev_highest_number: i32,
+    pub new_tile: Option<Two048Tile>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Two048Tile {
+    pub row: usize,
+    pub col: usize,
+    pub value: i32,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Two048Move {
+    pub timestamp: DateTime<Utc>,
+    pub prev_timestamp: DateTime<Utc>,
+    pub direction: Direction,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct FlappyData {
+    pub timestamp: DateTime<Utc>,
+    pub prev_timestamp: DateTime<Utc>,
+    pub points: i32,
+    pub prev_points: i32,
+    pub pipes: i32,
+    pub prev_pipes: i32,
+}
+
+impl FlappyData {
+    #[must_use]
+    pub fn new() -> Self {
+        let now = Utc::now();
+        Self {
+            timestamp: now,
+            prev_timestamp: now,
+            points: 0,
+            prev_points: 0,
+            pipes: 0,
+            prev_pipes: 0,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct PartialGameSession {
+    pub game_type: GameType,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub final_score: i32,
+}
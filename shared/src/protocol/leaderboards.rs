@@ -0,0 +1,44 @@
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::UserProfile;
+
+/// A leaderboard row. `score` is the total points on the global leaderboard and the best
+/// session score on a game leaderboard.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct LeaderboardEntry {
+    #[serde(flatten)]
+    pub user: UserProfile,
+    pub score: i32,
+}
+
+impl LeaderboardEntry {
+    #[must_use]
+    pub fn new(score: i32, user: UserProfile) -> Self {
+        Self { user, score }
+    }
+}
+
+/// An entry that was added or moved, with its 1 based rank after the change
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct RankedLeaderboardEntry {
+    pub rank: i64,
+    #[serde(flatten)]
+    pub entry: LeaderboardEntry,
+}
+
+/// Position after the last entry of a leaderboard page
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct LeaderboardCursor {
+    pub points: i32,
+    pub user_id: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct LeaderboardPageQuery {
+    pub cursor: Option<LeaderboardCursor>,
+    pub limit: Option<i64>,
+}
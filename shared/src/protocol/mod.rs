@@ -0,0 +1,25 @@
+//! Messages exchanged over the game WebSocket. The server, the frontend and any Rust client
+//! build against these types.
+
+mod enums;
+mod games;
+mod leaderboards;
+mod replay;
+mod request;
+mod response;
+#[cfg(feature = "diesel")]
+pub mod sql_types;
+mod tasks;
+mod users;
+
+pub use enums::*;
+pub use games::*;
+pub use leaderboards::*;
+pub use replay::*;
+pub use request::*;
+pub use response::*;
+pub use tasks::*;
+pub use users::*;
+
+/// Bumped on every breaking change to the messages. Sent to clients in `ConnectionStarted`.
+pub const PROTOCOL_VERSION: u32 = 4;
@@ -0,0 +1,66 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::{Direction, DropKind, GameType, TetrisPiece};
+
+/// A finished game session with its events in play order.
+///
+/// Each frame is serialized as a JSON array and starts with the milliseconds elapsed since
+/// `start_time`, which keeps long sessions small on the wire.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Replay {
+    pub session_id: String,
+    pub user_id: String,
+    pub game_type: GameType,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub final_score: i32,
+    pub seed: Option<i64>,
+    pub frames: ReplayFrames,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(tag = "type", content = "data")]
+pub enum ReplayFrames {
+    Tetris(Vec<TetrisFrame>),
+    Snake(Vec<SnakeFrame>),
+    Two048(Vec<Two048Frame>),
+    Flappy(Vec<FlappyFrame>),
+}
+
+/// `[offset_ms, piece, rotation, column, drop_kind, points, lines]`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TetrisFrame(
+    pub i64,
+    pub Option<TetrisPiece>,
+    pub Option<i32>,
+    pub Option<i32>,
+    pub Option<DropKind>,
+    pub i32,
+    pub i32,
+);
+
+/// `[offset_ms, points, length]`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SnakeFrame(pub i64, pub i32, pub i32);
+
+/// `[offset_ms, direction, spawn_row, spawn_col, spawn_value, points]`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Two048Frame(
+    pub i64,
+    pub Direction,
+    pub Option<i32>,
+    pub Option<i32>,
+    pub Option<i32>,
+    pub i32,
+);
+
+/// `[offset_ms, points, pipes]`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct FlappyFrame(pub i64, pub i32, pub i32);
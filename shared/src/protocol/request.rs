@@ -0,0 +1,108 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::error::Error;
+
+use crate::protocol::{
+    AuthPayload, BindWallet, ChallengeRequest, FlappyData, GameType, LeaderboardPageQuery,
+    LeaderboardWindow, SnakeData, TaskCheck, TelegramUser, TetrisPlacement, Two048Move,
+};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(tag = "type")]
+pub enum Request {
+    SignInChallenge {
+        data: ChallengeRequest,
+    },
+    Auth {
+        data: AuthPayload,
+    },
+    InitialPoints,
+    Me,
+    MeWithRankSocials,
+    GetActivity,
+    TetrisStart {
+        data: DateTime<Utc>,
+    },
+    Tetris {
+        data: TetrisPlacement,
+    },
+    TetrisEnd,
+    Snake {
+        data: SnakeData,
+    },
+    SnakeEnd,
+    Two048Start {
+        data: DateTime<Utc>,
+    },
+    Two048 {
+        data: Two048Move,
+    },
+    Two048End,
+    Flappy {
+        data: FlappyData,
+    },
+    FlappyEnd,
+    LeaderboardIn {
+        game: Option<GameType>,
+    },
+    LeaderboardOut {
+        game: Option<GameType>,
+    },
+    LeaderboardPage {
+        data: LeaderboardPageQuery,
+    },
+    LeaderboardAround {
+        count: Option<i64>,
+    },
+    WindowLeaderboard {
+        window: LeaderboardWindow,
+    },
+    UsernameUpdate {
+        data: String,
+    },
+    SocialLinks,
+    Telegram {
+        data: TelegramUser,
+    },
+    Tasks,
+    CheckTask {
+        data: TaskCheck,
+    },
+    CheckReferral {
+        data: String,
+    },
+    BindWallet {
+        data: BindWallet,
+    },
+    GetReplay {
+        session_id: String,
+    },
+    ResumeSession {
+        game: GameType,
+    },
+    /// Revokes every token of the user and closes all of their connections
+    LogoutEverywhere,
+}
+
+/// A request as sent by the client.
+///
+/// `request_id` is picked by the client and echoed back on every response to the request.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct RequestEnvelope {
+    #[serde(flatten)]
+    pub request: Request,
+    pub request_id: Option<String>,
+}
+
+impl RequestEnvelope {
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        serde_json::from_str(json)
+    }
+
+    #[cfg(feature = "msgpack")]
+    pub fn from_msgpack(bytes: &[u8]) -> Result<Self, rmp_serde::decode::Error> {
+        rmp_serde::from_slice(bytes)
+    }
+}
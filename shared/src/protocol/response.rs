@@ -0,0 +1,396 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::protocol::{
+    FlappyData, GameType, LeaderboardCursor, LeaderboardEntry, LeaderboardWindow, PROTOCOL_VERSION,
+    PartialGameSession, RankedLeaderboardEntry, Replay, SignInChallenge, SnakeData, SocialLinks,
+    TetrisData, TokenPair, Two048Data, UserProfile, UserTask, UserWithRankSocials,
+};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WsResponse {
+    pub status: Status,
+    pub response: Option<Response>,
+    pub error: Option<ErrorResponse>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum Status {
+    Success,
+    Error,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(tag = "type")]
+pub enum Response {
+    SignInChallenge {
+        data: SignInChallenge,
+    },
+    ConnectionStarted {
+        data: Option<TokenPair>,
+        protocol_version: u32,
+        resumable: Vec<GameType>,
+    },
+    UpdatedPoints {
+        points: i32,
+    },
+    Me {
+        data: UserProfile,
+    },
+    MeWithRankSocials {
+        data: UserWithRankSocials,
+    },
+    GameSessions {
+        data: Vec<PartialGameSession>,
+    },
+    /// Full snapshot of a live leaderboard. `version` is the base for later deltas.
+    Leaderboard {
+        game: Option<GameType>,
+        version: u64,
+        data: Vec<LeaderboardEntry>,
+    },
+    /// Changes to a subscribed leaderboard since `version - 1`.
+    ///
+    /// Clients drop the `removed` user ids, replace or insert the `changed` entries and sort by
+    /// rank. Deltas at or below the snapshot version are stale. A gap in versions means the
+    /// client missed one and should send `LeaderboardIn` again for a fresh snapshot.
+    LeaderboardDelta {
+        game: Option<GameType>,
+        version: u64,
+        changed: Vec<RankedLeaderboardEntry>,
+        removed: Vec<String>,
+    },
+    LeaderboardPage {
+        start_rank: i64,
+        next_cursor: Option<LeaderboardCursor>,
+        data: Vec<LeaderboardEntry>,
+    },
+    LeaderboardAround {
+        rank: i64,
+        start_rank: i64,
+        data: Vec<LeaderboardEntry>,
+    },
+    WindowLeaderboard {
+        window: LeaderboardWindow,
+        period: Option<String>,
+        ends_at: Option<DateTime<Utc>>,
+        data: Vec<LeaderboardEntry>,
+    },
+    NewTetris {
+        data: TetrisData,
+    },
+    NewSnake {
+        data: SnakeData,
+    },
+    NewTwo048 {
+        data: Two048Data,
+    },
+    NewFlappy {
+        data: FlappyData,
+    },
+    SocialLinks {
+        data: SocialLinks,
+    },
+    Tasks {
+        data: Vec<UserTask>,
+    },
+    TaskCompleted {
+        data: String,
+    },
+    Replay {
+        data: Replay,
+    },
+    /// The server is going down. Running games have been saved and the socket closes next.
+    ServerRestarting,
+    /// Every token of the user was revoked. Its sockets close next.
+    LoggedOut,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(tag = "type")]
+pub enum ErrorResponse {
+    InvalidSign,
+    InvalidJWT,
+    InternalError,
+    NotLoggedIn,
+    TelegramError {
+        data: String,
+    },
+    TaskNotCompleted {
+        data: String,
+    },
+    BadReferralCode,
+    BindFailed {
+        data: String,
+    },
+    ReplayNotFound {
+        data: String,
+    },
+    SessionNotResumable {
+        data: GameType,
+    },
+    /// The client speaks another protocol version. `data` is the server version.
+    UnsupportedProtocol {
+        data: u32,
+    },
+    /// The message could not be parsed. `data` is the parser error.
+    InvalidRequest {
+        data: String,
+    },
+    /// A move or input was rejected. `data` is the reason.
+    ValidationFailed {
+        data: String,
+    },
+    /// Too many requests. `data` is the number of seconds to wait before retrying.
+    RateLimited {
+        data: u64,
+    },
+    /// `data` names what the request referred to but does not exist.
+    NotFound {
+        data: String,
+    },
+    /// The account was banned and can no longer log in.
+    Banned,
+}
+
+impl fmt::Display for ErrorResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+impl std::error::Error for ErrorResponse {}
+
+impl WsResponse {
+    pub fn success(response: Response) -> Self {
+        Self {
+            status: Status::Success,
+            response: Some(response),
+            error: None,
+            request_id: None,
+        }
+    }
+
+    pub fn error(error: ErrorResponse) -> Self {
+        Self {
+            status: Status::Error,
+            response: None,
+            error: Some(error),
+            request_id: None,
+        }
+    }
+
+    #[must_use]
+    pub fn with_request_id(mut self, request_id: Option<String>) -> Self {
+        self.request_id = request_id;
+        self
+    }
+
+    pub fn sign_in_challenge(data: SignInChallenge) -> Self {
+        Self::success(Response::SignInChallenge { data })
+    }
+
+    pub fn connection_started(data: Option<TokenPair>, resumable: Vec<GameType>) -> Self {
+        Self::success(Response::ConnectionStarted {
+            data,
+            protocol_version: PROTOCOL_VERSION,
+            resumable,
+        })
+    }
+
+    pub fn updated_points(points: i32) -> Self {
+        Self::success(Response::UpdatedPoints { points })
+    }
+
+    pub fn me(data: UserProfile) -> Self {
+        Self::success(Response::Me { data })
+    }
+
+    pub fn me_with_rank_socials(data: UserWithRankSocials) -> Self {
+        Self::success(Response::MeWithRankSocials { data })
+    }
+
+    pub fn leaderboard(game: Option<GameType>, version: u64, data: Vec<LeaderboardEntry>) -> Self {
+        Self::success(Response::Leaderboard {
+            game,
+            version,
+            data,
+        })
+    }
+
+    pub fn leaderboard_delta(
+        game: Option<GameType>,
+        version: u64,
+        changed: Vec<RankedLeaderboardEntry>,
+        removed: Vec<String>,
+    ) -> Self {
+        Self::success(Response::LeaderboardDelta {
+            game,
+            version,
+            changed,
+            removed,
+        })
+    }
+
+    pub fn leaderboard_page(
+        start_rank: i64,
+        next_cursor: Option<LeaderboardCursor>,
+        data: Vec<LeaderboardEntry>,
+    ) -> Self {
+        Self::success(Response::LeaderboardPage {
+            start_rank,
+            next_cursor,
+            data,
+        })
+    }
+
+    pub fn leaderboard_around(rank: i64, start_rank: i64, data: Vec<LeaderboardEntry>) -> Self {
+        Self::success(Response::LeaderboardAround {
+            rank,
+            start_rank,
+            data,
+        })
+    }
+
+    pub fn window_leaderboard(
+        window: LeaderboardWindow,
+        period: Option<String>,
+        ends_at: Option<DateTime<Utc>>,
+        data: Vec<LeaderboardEntry>,
+    ) -> Self {
+        Self::success(Response::WindowLeaderboard {
+            window,
+            period,
+            ends_at,
+            data,
+        })
+    }
+
+    pub fn game_sessions(data: Vec<PartialGameSession>) -> Self {
+        Self::success(Response::GameSessions { data })
+    }
+
+    pub fn new_tetris(data: TetrisData) -> Self {
+        Self::success(Response::NewTetris { data })
+    }
+
+    pub fn new_flappy(data: FlappyData) -> Self {
+        Self::success(Response::NewFlappy { data })
+    }
+
+    pub fn new_snake(data: SnakeData) -> Self {
+        Self::success(Response::NewSnake { data })
+    }
+
+    pub fn new_two048(data: Two048Data) -> Self {
+        Self::success(Response::NewTwo048 { data })
+    }
+
+    pub fn social_links(data: SocialLinks) -> Self {
+        Self::success(Response::SocialLinks { data })
+    }
+
+    pub fn tasks(data: Vec<UserTask>) -> Self {
+        Self::success(Response::Tasks { data })
+    }
+
+    pub fn task_completed(data: String) -> Self {
+        Self::success(Response::TaskCompleted { data })
+    }
+
+    pub fn replay(data: Replay) -> Self {
+        Self::success(Response::Replay { data })
+    }
+
+    pub fn server_restarting() -> Self {
+        Self::success(Response::ServerRestarting)
+    }
+
+    pub fn logged_out() -> Self {
+        Self::success(Response::LoggedOut)
+    }
+
+    pub fn invalid_sign() -> Self {
+        Self::error(ErrorResponse::InvalidSign)
+    }
+
+    pub fn internal_error() -> Self {
+        Self::error(ErrorResponse::InternalError)
+    }
+
+    pub fn not_logged_in() -> Self {
+        Self::error(ErrorResponse::NotLoggedIn)
+    }
+
+    pub fn invalid_jwt() -> Self {
+        Self::error(ErrorResponse::InvalidJWT)
+    }
+
+    pub fn banned() -> Self {
+        Self::error(ErrorResponse::Banned)
+    }
+
+    pub fn telegram_error(data: String) -> Self {
+        Self::error(ErrorResponse::TelegramError { data })
+    }
+
+    pub fn task_not_completed(data: String) -> Self {
+        Self::error(ErrorResponse::TaskNotCompleted { data })
+    }
+
+    pub fn bad_referral_code() -> Self {
+        Self::error(ErrorResponse::BadReferralCode)
+    }
+
+    pub fn bind_failed(data: String) -> Self {
+        Self::error(ErrorResponse::BindFailed { data })
+    }
+
+    pub fn replay_not_found(data: String) -> Self {
+        Self::error(ErrorResponse::ReplayNotFound { data })
+    }
+
+    pub fn session_not_resumable(data: GameType) -> Self {
+        Self::error(ErrorResponse::SessionNotResumable { data })
+    }
+
+    pub fn unsupported_protocol() -> Self {
+        Self::error(ErrorResponse::UnsupportedProtocol {
+            data: PROTOCOL_VERSION,
+        })
+    }
+
+    pub fn invalid_request(data: String) -> Self {
+        Self::error(ErrorResponse::InvalidRequest { data })
+    }
+
+    pub fn validation_failed(data: String) -> Self {
+        Self::error(ErrorResponse::ValidationFailed { data })
+    }
+
+    pub fn rate_limited(data: u64) -> Self {
+        Self::error(ErrorResponse::RateLimited { data })
+    }
+
+    pub fn not_found(data: String) -> Self {
+        Self::error(ErrorResponse::NotFound { data })
+    }
+
+    #[must_use]
+    pub fn json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[must_use]
+    pub fn msgpack(&self) -> Vec<u8> {
+        rmp_serde::to_vec_named(self).unwrap()
+    }
+}
@@ -0,0 +1,38 @@
+//! Postgres types of the protocol enums. `db/src/schema.rs` refers to these instead of
+//! generating its own.
+
+#[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+#[diesel(postgres_type(name = "direction"))]
+pub struct Direction;
+
+#[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+#[diesel(postgres_type(name = "drop_kind"))]
+pub struct DropKind;
+
+#[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+#[diesel(postgres_type(name = "game_type"))]
+pub struct GameType;
+
+#[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+#[diesel(postgres_type(name = "leaderboard_window"))]
+pub struct LeaderboardWindow;
+
+#[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+#[diesel(postgres_type(name = "platform"))]
+pub struct Platform;
+
+#[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+#[diesel(postgres_type(name = "point_source"))]
+pub struct PointSource;
+
+#[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+#[diesel(postgres_type(name = "task_type"))]
+pub struct TaskType;
+
+#[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+#[diesel(postgres_type(name = "tetris_piece"))]
+pub struct TetrisPiece;
+
+#[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+#[diesel(postgres_type(name = "user_status"))]
+pub struct UserStatus;
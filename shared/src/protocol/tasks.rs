@@ -0,0 +1,34 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::{Platform, TaskType};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct UserTask {
+    #[serde(flatten)]
+    pub task: MiniTask,
+    pub completed: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct MiniTask {
+    pub id: String,
+    pub task_type: TaskType,
+    pub created_at: DateTime<Utc>,
+    pub ends_at: Option<DateTime<Utc>>,
+    pub title: String,
+    pub description: String,
+    pub redirect_url: Option<String>,
+    pub platform: Option<Platform>,
+    pub reward_point: i32,
+    pub proof_required: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TaskCheck {
+    pub task_id: String,
+    pub proof: Option<String>,
+}
@@ -0,0 +1,205 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::protocol::Chain;
+
+/// A user as sent to clients
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct UserProfile {
+    pub joined_at: DateTime<Utc>,
+    pub user_id: String,
+    pub username: Option<String>,
+    pub sol_wallet: Option<String>,
+    pub evm_wallet: Option<String>,
+    pub photo_url: String,
+    pub photo_id: Option<String>,
+    pub points: i32,
+    pub referral_code: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct UserWithRank {
+    #[serde(flatten)]
+    pub user: UserProfile,
+    pub rank: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct UserWithRankSocials {
+    #[serde(flatten)]
+    pub user: UserWithRank,
+    pub twitter: Option<String>,
+    pub discord: Option<String>,
+    pub telegram: Option<String>,
+}
+
+impl UserWithRankSocials {
+    #[must_use]
+    pub fn new(
+        user: UserWithRank,
+        twitter: Option<String>,
+        discord: Option<String>,
+        telegram: Option<String>,
+    ) -> Self {
+        Self {
+            user,
+            twitter,
+            discord,
+            telegram,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SocialLinks {
+    pub discord: Option<String>,
+    pub telegram: Option<String>,
+    pub twitter: Option<String>,
+}
+
+impl SocialLinks {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            discord: None,
+            telegram: None,
+            twitter: None,
+        }
+    }
+}
+
+/// A fresh wallet signature, an access token or a refresh token from an earlier connection.
+///
+/// `protocol_version` is the version the client was built against. Connections from a client
+/// on another version are refused. Clients that predate versioning leave it out.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(untagged)]
+pub enum AuthPayload {
+    /// `signature` signs the message of the challenge `nonce` was issued with
+    Signed {
+        public_key: String,
+        signature: String,
+        chain: Chain,
+        nonce: String,
+        protocol_version: Option<u32>,
+    },
+    Token {
+        token: String,
+        protocol_version: Option<u32>,
+    },
+    /// Used once the access token expired. The refresh token is consumed and a new pair is sent
+    /// back in `ConnectionStarted`.
+    Refresh {
+        refresh_token: String,
+        protocol_version: Option<u32>,
+    },
+}
+
+impl AuthPayload {
+    #[must_use]
+    pub fn protocol_version(&self) -> Option<u32> {
+        match self {
+            AuthPayload::Signed {
+                protocol_version, ..
+            }
+            | AuthPayload::Token {
+                protocol_version, ..
+            }
+            | AuthPayload::Refresh {
+                protocol_version, ..
+            } => *protocol_version,
+        }
+    }
+}
+
+/// Tokens issued on login. The access token is short lived, the refresh token can be used once
+/// to get a new pair. `expires_in` is the lifetime of the access token in seconds.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TokenPair {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub expires_in: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct BindWallet {
+    pub chain: Chain,
+    pub address: String,
+    pub signature: String,
+    pub nonce: String,
+}
+
+/// Asks for a sign in message for `address`. `chain_id` is the EVM chain the wallet is on and
+/// defaults to mainnet.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ChallengeRequest {
+    pub chain: Chain,
+    pub address: String,
+    pub chain_id: Option<u64>,
+}
+
+/// A message to sign, following EIP-4361 for EVM wallets and Sign In With Solana for Solana
+/// wallets. It can be used once, before `expires_at`, by sending `nonce` with the signature.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SignInChallenge {
+    pub nonce: String,
+    pub message: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TelegramUser {
+    pub id: i64,
+    pub first_name: String,
+    pub last_name: Option<String>,
+    pub username: Option<String>,
+    pub photo_url: Option<String>,
+    pub auth_date: i64,
+    pub hash: String,
+}
+
+impl TelegramUser {
+    #[must_use]
+    pub fn key_value_map(&self) -> BTreeMap<String, String> {
+        let mut map = BTreeMap::new();
+        map.insert("id".to_string(), self.id.to_string());
+        map.insert("first_name".to_string(), self.first_name.clone());
+
+        if let Some(last_name) = &self.last_name {
+            map.insert("last_name".to_string(), last_name.to_string());
+        }
+
+        if let Some(username) = &self.username {
+            map.insert("username".to_string(), username.to_string());
+        }
+
+        if let Some(photo_url) = &self.photo_url {
+            map.insert("photo_url".to_string(), photo_url.to_string());
+        }
+
+        map.insert("auth_date".to_string(), self.auth_date.to_string());
+        map
+    }
+
+    #[must_use]
+    pub fn name_to_use(&self) -> String {
+        if let Some(username) = &self.username {
+            username.to_string()
+        } else {
+            format!("{} {}", self.first_name, self.last_name.as_ref().unwrap())
+                .trim()
+                .to_string()
+        }
+    }
+}